@@ -0,0 +1,47 @@
+//! Times every phase of running the scripts in `benches/scripts`. The binary
+//! reports its own phase times with the hidden `--bench-internal` flag, so
+//! starting the process is not part of what is measured
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+const SCRIPTS: [&str; 3] = ["large_regex", "deep_expressions", "many_tests"];
+const PHASES: [&str; 4] = ["lex", "parse", "type_check", "interpret"];
+
+fn phase_time(script: &str, phase: &str) -> Duration {
+    let output = Command::new(env!("CARGO_BIN_EXE_test-script"))
+        .arg(format!("benches/scripts/{script}.tesc"))
+        .args(["--quiet", "--disable-warnings", "--bench-internal"])
+        .stdout(Stdio::null())
+        .output()
+        .expect("failed to run test-script");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix("bench-internal: "))
+        .find_map(|line| match line.split_once(' ') {
+            Some((name, nanoseconds)) if name == phase => nanoseconds.parse().ok(),
+            _ => None,
+        })
+        .map(Duration::from_nanos)
+        .unwrap_or_else(|| panic!("{script} did not report the {phase} phase:\n{stderr}"))
+}
+
+fn passes(c: &mut Criterion) {
+    for script in SCRIPTS {
+        let mut group = c.benchmark_group(script);
+        group.sample_size(10);
+        for phase in PHASES {
+            group.bench_function(phase, |b| {
+                b.iter_custom(|iterations| {
+                    (0..iterations).map(|_| phase_time(script, phase)).sum()
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, passes);
+criterion_main!(benches);
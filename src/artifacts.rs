@@ -0,0 +1,120 @@
+use crate::process::Process;
+
+use std::path::{Path, PathBuf};
+
+/// The options that are left out of the command line to run a failed test
+/// again, since they are given with new values or would record a manifest or
+/// history of only that test
+const RERUN_OPTIONS: [&str; 5] = [
+    "--seed",
+    "--filter",
+    "--manifest",
+    "--verify-manifest",
+    "--history",
+];
+
+/// Writes what is needed to debug a failed test after the run, in a directory
+/// of its own in `--artifacts`: the transcript of what was sent and read, what
+/// the program wrote to stderr, its environment, the seed of the random
+/// strings and the command lines to run the program and the test again
+#[derive(Debug)]
+pub struct Artifacts {
+    directory: PathBuf,
+    seed: u64,
+}
+
+impl Artifacts {
+    pub fn new(directory: PathBuf, seed: u64) -> Self {
+        Self { directory, seed }
+    }
+
+    /// The directory of a test, in the directories of its suites
+    fn test_directory(&self, test: &str) -> PathBuf {
+        let mut directory = self.directory.clone();
+        for part in test.split("::") {
+            directory.push(
+                part.chars()
+                    .map(|c| match c.is_alphanumeric() || c == '_' || c == '-' {
+                        true => c,
+                        false => '_',
+                    })
+                    .collect::<String>(),
+            );
+        }
+        directory
+    }
+
+    /// Writes the artifacts of a failed test and returns their directory.
+    /// Only call this after the program has exited
+    pub fn write(&self, test: &str, process: &mut Process) -> std::io::Result<PathBuf> {
+        let directory = self.test_directory(test);
+        self.remove(test);
+        std::fs::create_dir_all(&directory)?;
+
+        let mut transcript = process.transcript().join("\n");
+        transcript.push('\n');
+        write(&directory, "transcript.txt", &transcript)?;
+        let stderr = process.stderr().map(|(stderr, _)| stderr);
+        write(&directory, "stderr.txt", &stderr.unwrap_or_default())?;
+        let environment = process
+            .environment()
+            .iter()
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect::<String>();
+        write(&directory, "environment.txt", &environment)?;
+        write(&directory, "seed.txt", &format!("{}\n", self.seed))?;
+        write(
+            &directory,
+            "command.txt",
+            &format!(
+                "Command: {}\nRerun: {}\n",
+                process.command(),
+                self.rerun(test)
+            ),
+        )?;
+        Ok(directory)
+    }
+
+    /// Removes the artifacts a test left in an earlier run, so they are not
+    /// mistaken for those of a test that passed now
+    pub fn remove(&self, test: &str) {
+        let _ = std::fs::remove_dir_all(self.test_directory(test));
+    }
+
+    /// The command line of this run with the seed of this run, that only runs
+    /// the test
+    fn rerun(&self, test: &str) -> String {
+        let mut args = Vec::new();
+        let mut skip = false;
+        for arg in std::env::args() {
+            if std::mem::take(&mut skip) {
+                continue;
+            }
+            let option = arg.split('=').next().unwrap_or_default();
+            match RERUN_OPTIONS.contains(&option) {
+                true => skip = !arg.contains('='),
+                false => args.push(quote(&arg)),
+            }
+        }
+        args.push(format!("--seed {}", self.seed));
+        args.push(format!("--filter {}", quote(test)));
+        args.join(" ")
+    }
+}
+
+fn write(directory: &Path, file: &str, contents: &str) -> std::io::Result<()> {
+    std::fs::write(directory.join(file), contents)
+}
+
+/// Quotes an argument for a shell if it contains anything but letters,
+/// digits and a few characters that are safe
+fn quote(arg: &str) -> String {
+    match !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_./=:,@+".contains(c))
+    {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', "'\\''")),
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::Path;
+
+/// Reads the test cases in a CSV or JSON file, every case a list of strings.
+/// The first line of a CSV file names the columns and is not a case. A JSON
+/// file holds an array of arrays, whose numbers and bools are kept as they
+/// are written
+pub fn load(path: &Path) -> Result<Vec<Vec<String>>, String> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => load_csv(path),
+        Some("json") => load_json(path),
+        _ => Err(String::from("expected a `.csv` or `.json` file")),
+    }
+}
+
+fn load_csv(path: &Path) -> Result<Vec<Vec<String>>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| e.to_string())?;
+    reader
+        .records()
+        .map(|record| {
+            record
+                .map(|record| record.iter().map(String::from).collect())
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+}
+
+fn load_json(path: &Path) -> Result<Vec<Vec<String>>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let value = serde_json::from_str::<serde_json::Value>(&contents).map_err(|e| e.to_string())?;
+    let cases = value
+        .as_array()
+        .ok_or_else(|| String::from("expected an array of cases"))?;
+    cases
+        .iter()
+        .enumerate()
+        .map(|(index, case)| {
+            let values = case
+                .as_array()
+                .ok_or_else(|| format!("case {} is not an array", index + 1))?;
+            values
+                .iter()
+                .map(|value| match value {
+                    serde_json::Value::String(value) => Ok(value.clone()),
+                    serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                        Ok(value.to_string())
+                    }
+                    _ => Err(format!(
+                        "case {} contains `{}`, expected strings, numbers and bools",
+                        index + 1,
+                        value
+                    )),
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,78 @@
+//! Checks a script without running it, for a playground or an editor that
+//! shows the diagnostics of a script as it is written. Nothing here spawns a
+//! process, so it is available in builds without the `native` feature.
+
+use crate::cli::Args;
+use crate::diagnostic::{self, Diagnostics};
+use crate::{lexer, parser, type_checker};
+
+use clap::Parser;
+use std::path::PathBuf;
+
+pub use crate::diagnostic::Severity;
+
+/// Where in the script a diagnostic points. Lines and columns start at 1,
+/// offsets are in bytes from the start of the script
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub offset: usize,
+    pub end_offset: usize,
+}
+
+/// An error, warning or note reported while checking a script
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The code explained by `test-script --explain`, or the name of the
+    /// warning that `allow` takes. `None` for diagnostics without either
+    pub code: Option<String>,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    /// The diagnostic as `test-script` prints it, without colors
+    pub rendered: String,
+}
+
+/// Lexes, parses and type checks a script, and returns what was reported
+/// sorted by where it points. Scripts can be checked on several threads at
+/// once, since the diagnostics of each check go to their own `Diagnostics`
+pub fn check_source(source: &str) -> Vec<Diagnostic> {
+    let args = Args::parse_from(["test-script"]);
+
+    Diagnostics::new(Box::new(std::io::sink())).scope(|| {
+        Diagnostics::collect();
+        let tokens = lexer::Lexer::new(source, PathBuf::from("<source>")).tokenize();
+        let program = parser::Parser::new(tokens, args.clone()).parse();
+        match &program {
+            Ok(program) | Err(program) => {
+                let _ = type_checker::TypeChecker::new(program, args).check();
+            }
+        }
+        Diagnostics::take()
+    })
+}
+
+impl From<&diagnostic::Diagnostic<'_>> for Diagnostic {
+    fn from(diagnostic: &diagnostic::Diagnostic) -> Self {
+        Self {
+            code: match diagnostic.code {
+                "" => None,
+                code => Some(code.to_string()),
+            },
+            severity: diagnostic.severity,
+            message: diagnostic.message.clone(),
+            span: diagnostic.token.map(|token| Span {
+                line: token.row,
+                column: token.column,
+                end_line: token.end_row,
+                end_column: token.end_column,
+                offset: token.offset,
+                end_offset: token.end_offset,
+            }),
+            rendered: strip_ansi_escapes::strip_str(&diagnostic.rendered),
+        }
+    }
+}
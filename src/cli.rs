@@ -1,42 +1,451 @@
-use crate::error::LexerError;
+use crate::config::{Config, SuiteConfig};
+use crate::diagnostic::{self, Annotate, ColorChoice, MessageFormat};
+use crate::doc;
+use crate::error::{ConfigError, LexerError, WARNING_NAMES};
 use crate::exitcode::ExitCode;
+use crate::explain;
+use crate::grammar;
+use crate::history;
+use crate::init;
+use crate::manifest::Manifest;
+use crate::plugin;
 use crate::test;
 
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use colored::Colorize;
 use std::path::PathBuf;
+use std::time::Duration;
+
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
 #[derive(Parser, Debug, Clone)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[clap(index = 1)]
-    pub file: PathBuf,
+    pub file: Option<PathBuf>,
+
+    #[clap(short = 'e', long, value_name = "SCRIPT", conflicts_with = "file")]
+    pub eval: Option<String>,
 
-    #[clap(short = 'W', long)]
+    #[clap(long, value_name = "CODE")]
+    pub explain: Option<String>,
+
+    #[clap(short = 'W', long, global = true)]
     pub disable_warnings: bool,
 
-    #[clap(short = 'S', long)]
+    #[clap(short = 'S', long, global = true)]
     pub disable_style_warnings: bool,
 
-    #[clap(short = 'M', long)]
+    #[clap(short = 'M', long, global = true)]
     pub disable_magic_warnings: bool,
 
-    #[clap(short, long, default_value = "3")]
+    /// Report a warning as an error, can be given more than once
+    #[clap(long, value_name = "WARNING", value_parser = parse_warning, global = true)]
+    pub deny: Vec<String>,
+
+    /// A command that waits for input before it prints anything, can be
+    /// given more than once
+    #[clap(long, value_name = "COMMAND", global = true)]
+    pub waits_for_input: Vec<String>,
+
+    /// Only run the tests whose full name, with the names of their suites,
+    /// matches PATTERN, where `*` matches anything. Can be given more than once
+    #[clap(long, value_name = "PATTERN", global = true)]
+    pub filter: Vec<String>,
+
+    #[clap(short, long, default_value = "3", global = true)]
     pub max_size: u32,
 
-    #[clap(short, long)]
-    pub debug: bool,
+    #[clap(long, default_value = "1000000", global = true)]
+    pub max_combinations: usize,
+
+    #[clap(long, global = true)]
+    pub pty: bool,
+
+    /// Run every program in a temporary directory, on Linux without network
+    #[clap(long, global = true)]
+    pub sandbox: bool,
+
+    #[clap(long, value_name = "COMMAND", global = true)]
+    pub wrapper: Vec<String>,
+
+    #[clap(long, value_name = "SECONDS", default_value = "2", value_parser = parse_timeout, global = true)]
+    pub timeout: Duration,
+
+    #[clap(long, global = true)]
+    pub keep_line_endings: bool,
+
+    #[clap(long, global = true)]
+    pub keep_trailing_whitespace: bool,
+
+    #[clap(long, global = true)]
+    pub collapse_whitespace: bool,
+
+    #[clap(long, global = true)]
+    pub strip_ansi: bool,
+
+    /// Write the output read by `output_file` to its file instead of
+    /// comparing them
+    #[clap(long, global = true)]
+    pub update_expected: bool,
+
+    #[clap(long, value_name = "EPSILON", value_parser = parse_tolerance, global = true)]
+    pub float_epsilon: Option<f64>,
+
+    #[clap(long, value_name = "TOLERANCE", value_parser = parse_tolerance, global = true)]
+    pub number_tolerance: Option<f64>,
+
+    #[clap(long, global = true)]
+    pub profile_interpreter: bool,
+
+    /// Write which statements and branches of the script ran to FILE, as
+    /// HTML if it ends in `.html` and as lcov otherwise
+    #[clap(long, value_name = "FILE", global = true)]
+    pub script_coverage: Option<PathBuf>,
+
+    /// Write the transcript, stderr, environment, seed and command of every
+    /// failed test to a directory named after the test in DIR
+    #[clap(
+        long,
+        value_name = "DIR",
+        default_value = "target/test_script",
+        global = true
+    )]
+    pub artifacts: PathBuf,
+
+    /// Do not write the artifacts of failed tests
+    #[clap(long, global = true)]
+    pub no_artifacts: bool,
+
+    /// The seed of the random strings created from regexes, random by
+    /// default. Every test starts from the seed
+    #[clap(long, value_name = "SEED", global = true)]
+    pub seed: Option<u64>,
+
+    /// Write the version, seed and settings of the run, the SHA-256 of the
+    /// script and of the files it runs, and the result of every test to FILE
+    #[clap(long, value_name = "FILE", global = true)]
+    pub manifest: Option<PathBuf>,
+
+    /// Run the script of the manifest at FILE again with its seed and filter,
+    /// and fail if anything in the manifest is different in this run
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["seed", "filter"],
+        global = true
+    )]
+    pub verify_manifest: Option<PathBuf>,
+
+    /// Record the outcome and duration of every test in the SQLite database
+    /// FILE, to compare runs with `test-script history`
+    #[clap(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        default_missing_value = history::DEFAULT_FILE,
+        global = true
+    )]
+    pub history: Option<PathBuf>,
+
+    /// The manifest read from `--verify-manifest`
+    #[clap(skip)]
+    pub expected_manifest: Option<Manifest>,
+
+    /// The config file that was found, for the manifest
+    #[clap(skip)]
+    pub config_file: Option<PathBuf>,
+
+    #[clap(skip)]
+    pub plugins: Vec<PathBuf>,
+
+    /// The output comparison options of the suites in the config file
+    #[clap(skip)]
+    pub suites: Vec<(String, SuiteConfig)>,
+
+    /// Print how long each phase took, for the benchmarks in `benches`
+    #[clap(long, hide = true, global = true)]
+    pub bench_internal: bool,
+
+    #[clap(
+        short,
+        long,
+        action = clap::ArgAction::Count,
+        conflicts_with = "quiet",
+        global = true
+    )]
+    pub verbose: u8,
+
+    #[clap(short, long, global = true)]
+    pub quiet: bool,
+
+    #[clap(long, value_enum, default_value = "human", global = true)]
+    pub message_format: MessageFormat,
+
+    #[clap(long, value_enum, default_value = "auto", global = true)]
+    pub color: ColorChoice,
+
+    /// Also print every diagnostic in the format of a CI system, which links
+    /// it to its line of the script
+    #[clap(long, value_enum, conflicts_with = "message_format", global = true)]
+    pub annotate: Option<Annotate>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run a script, use `-` as the file to read the script from stdin
+    Run {
+        #[clap(index = 1)]
+        file: Option<PathBuf>,
+
+        #[clap(short = 'e', long, value_name = "SCRIPT", conflicts_with = "file")]
+        eval: Option<String>,
+    },
+    /// Print a Markdown overview of the tests and functions in a script
+    Doc {
+        #[clap(index = 1)]
+        file: PathBuf,
+    },
+    /// Print a TextMate grammar for editors to highlight scripts with
+    Grammar,
+    /// Print the tests that newly fail, newly pass or slowed down in the
+    /// latest run recorded with `--history`, compared with the run before it
+    History {
+        #[clap(index = 1, default_value = history::DEFAULT_FILE)]
+        file: PathBuf,
+    },
+    /// Create a `tests` directory, a config file and an example test
+    Init {
+        #[clap(index = 1, default_value = ".")]
+        directory: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    VeryVerbose,
+}
+
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let seconds = value.parse::<f64>().map_err(|e| e.to_string())?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+fn parse_tolerance(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>().map_err(|e| e.to_string())? {
+        tolerance if tolerance >= 0.0 => Ok(tolerance),
+        _ => Err("must be a number that is not negative".to_string()),
+    }
+}
+
+fn parse_warning(value: &str) -> Result<String, String> {
+    match WARNING_NAMES.contains(&value) {
+        true => Ok(value.to_string()),
+        false => Err(format!(
+            "unknown warning, expected one of {}",
+            WARNING_NAMES.join(", ")
+        )),
+    }
+}
+
+impl Args {
+    fn apply_config(&mut self, config: Config, matches: &clap::ArgMatches) {
+        if matches.value_source("max_size") == Some(ValueSource::DefaultValue) {
+            if let Some(max_size) = config.max_size {
+                self.max_size = max_size;
+            }
+        }
+        if matches.value_source("max_combinations") == Some(ValueSource::DefaultValue) {
+            if let Some(max_combinations) = config.max_combinations {
+                self.max_combinations = max_combinations;
+            }
+        }
+        self.disable_warnings |= config.disable_warnings;
+        self.disable_style_warnings |= config.disable_style_warnings;
+        self.disable_magic_warnings |= config.disable_magic_warnings;
+        self.deny.extend(config.deny);
+        self.waits_for_input.extend(config.waits_for_input);
+        self.keep_line_endings |= config.keep_line_endings;
+        self.keep_trailing_whitespace |= config.keep_trailing_whitespace;
+        self.collapse_whitespace |= config.collapse_whitespace;
+        self.strip_ansi |= config.strip_ansi;
+        self.sandbox |= config.sandbox;
+        self.float_epsilon = self.float_epsilon.or(config.float_epsilon);
+        self.number_tolerance = self.number_tolerance.or(config.number_tolerance);
+        self.plugins = config.plugins;
+        // An option given on the command line is used for every suite too
+        let given = |id| matches.value_source(id) == Some(ValueSource::CommandLine);
+        self.suites = config.suites;
+        for (_, suite) in &mut self.suites {
+            if given("keep_line_endings") {
+                suite.keep_line_endings = None;
+            }
+            if given("keep_trailing_whitespace") {
+                suite.keep_trailing_whitespace = None;
+            }
+            if given("collapse_whitespace") {
+                suite.collapse_whitespace = None;
+            }
+            if given("strip_ansi") {
+                suite.strip_ansi = None;
+            }
+            if given("number_tolerance") {
+                suite.number_tolerance = None;
+            }
+        }
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        match (self.quiet, self.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::VeryVerbose,
+        }
+    }
 }
 
 pub fn run() {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    diagnostic::set_color(args.color);
+    diagnostic::set_message_format(args.message_format);
+    if let Some(annotate) = args.annotate {
+        diagnostic::set_annotate(annotate);
+    }
+
+    if let Some(code) = &args.explain {
+        match explain::explain(code) {
+            Some(explanation) => println!("{explanation}"),
+            None => {
+                eprintln!(
+                    "{}No explanation found for `{}`\n",
+                    "error: ".bright_red(),
+                    code
+                );
+                std::process::exit(ExitCode::UnknownErrorCode as i32);
+            }
+        }
+        return;
+    }
+
+    let mut run: fn(Args) = test::run;
+    match args.command.take() {
+        Some(Command::Run { file, eval }) => {
+            args.file = file;
+            args.eval = eval;
+        }
+        Some(Command::Doc { file }) => {
+            args.file = Some(file);
+            run = doc::run;
+        }
+        Some(Command::Grammar) => {
+            print!("{}", grammar::textmate());
+            return;
+        }
+        Some(Command::History { file }) => {
+            if let Err(e) = history::run(&file) {
+                eprintln!(
+                    "{}Failed to read the history `{}`: {}\n",
+                    "error: ".bright_red(),
+                    file.display(),
+                    e
+                );
+                std::process::exit(ExitCode::HistoryFailed as i32);
+            }
+            return;
+        }
+        Some(Command::Init { directory }) => {
+            if let Err(e) = init::run(&directory) {
+                eprintln!(
+                    "{}Failed to initialize `{}`: {}\n",
+                    "error: ".bright_red(),
+                    directory.display(),
+                    e
+                );
+                std::process::exit(ExitCode::InitFailed as i32);
+            }
+            return;
+        }
+        None => (),
+    }
+
+    let current_directory = std::env::current_dir().unwrap_or_default();
+    if let Some(path) = Config::find(&current_directory) {
+        match Config::load(&path) {
+            Ok(config) => {
+                for plugin in &config.plugins {
+                    if let Err(e) = plugin::load(plugin) {
+                        ConfigError::Plugin(plugin.clone(), e).print();
+                        std::process::exit(ExitCode::PluginFailed as i32);
+                    }
+                }
+                args.config_file = Some(path);
+                args.apply_config(config, &matches)
+            }
+            Err(e) => {
+                e.print();
+                std::process::exit(ExitCode::InvalidConfig as i32);
+            }
+        }
+    }
+    diagnostic::set_denied_warnings(args.deny.clone());
+
+    if let Some(path) = &args.verify_manifest {
+        let manifest = Manifest::load(path).unwrap_or_else(|e| {
+            eprintln!(
+                "{}Failed to read the manifest `{}`: {}\n",
+                "error: ".bright_red(),
+                path.display(),
+                e
+            );
+            std::process::exit(ExitCode::ManifestFailed as i32);
+        });
+        args.seed = Some(manifest.seed());
+        args.filter = manifest.filter();
+        if args.file.is_none() && args.eval.is_none() {
+            args.file = Some(PathBuf::from(manifest.script()));
+        }
+        args.expected_manifest = Some(manifest);
+    }
 
-    if args.file.extension().expect("File extension must be tesc") != "tesc" {
-        LexerError::FileExtensionNotTesc(&args.file).print();
+    let file = match (&args.file, &args.eval) {
+        (_, Some(_)) => return run_with_stack(run, args),
+        (Some(file), None) if file.as_os_str() == "-" => return run_with_stack(run, args),
+        (Some(file), None) => file,
+        (None, None) => Args::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "a script file or `--eval <SCRIPT>` is required",
+            )
+            .exit(),
+    };
+    if file.extension().expect("File extension must be tesc") != "tesc" {
+        LexerError::FileExtensionNotTesc(file).print();
         std::process::exit(ExitCode::FileExtentionNotTesc as i32);
-    } else if !args.file.exists() {
-        LexerError::FileNotFound(&args.file).print();
+    } else if !file.exists() {
+        LexerError::FileNotFound(file).print();
         std::process::exit(ExitCode::SourceFileNotFound as i32);
     }
 
-    test::run(args);
+    run_with_stack(run, args);
+}
+
+/// Runs the script on a thread with a stack large enough for
+/// `MAX_RECURSION_DEPTH` nested function calls
+fn run_with_stack(run: fn(Args), args: Args) {
+    let thread = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(args))
+        .expect("Failed to start the interpreter thread");
+    if let Err(panic) = thread.join() {
+        std::panic::resume_unwind(panic);
+    }
 }
@@ -0,0 +1,209 @@
+use crate::error::{ConfigError, WARNING_NAMES};
+
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = "test_script.toml";
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub max_size: Option<u32>,
+    pub max_combinations: Option<usize>,
+    pub disable_warnings: bool,
+    pub disable_style_warnings: bool,
+    pub disable_magic_warnings: bool,
+    pub deny: Vec<String>,
+    pub waits_for_input: Vec<String>,
+    pub keep_line_endings: bool,
+    pub keep_trailing_whitespace: bool,
+    pub collapse_whitespace: bool,
+    pub strip_ansi: bool,
+    pub sandbox: bool,
+    /// Shared libraries with builtins, relative to the directory of the file
+    pub plugins: Vec<PathBuf>,
+    pub float_epsilon: Option<f64>,
+    pub number_tolerance: Option<f64>,
+    /// The `[suite."name"]` tables, by the name of their suite
+    pub suites: Vec<(String, SuiteConfig)>,
+}
+
+/// How the output of the tests in a suite, and in the suites in it, is
+/// compared. A key that is not set keeps the value of the whole file.
+#[derive(Debug, Clone, Default)]
+pub struct SuiteConfig {
+    pub keep_line_endings: Option<bool>,
+    pub keep_trailing_whitespace: Option<bool>,
+    pub collapse_whitespace: Option<bool>,
+    pub strip_ansi: Option<bool>,
+    pub number_tolerance: Option<f64>,
+}
+
+impl Config {
+    pub fn find(directory: &Path) -> Option<PathBuf> {
+        directory
+            .ancestors()
+            .map(|directory| directory.join(CONFIG_FILE))
+            .find(|path| path.is_file())
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        let table = contents
+            .parse::<toml::Table>()
+            .map_err(|e| ConfigError::Syntax(path.to_path_buf(), e.message().to_string()))?;
+
+        let mut config = Config::default();
+        for (key, value) in table.iter() {
+            match key.as_str() {
+                "max_size" => {
+                    config.max_size = Some(
+                        value
+                            .as_integer()
+                            .and_then(|value| u32::try_from(value).ok())
+                            .ok_or_else(|| Self::invalid_value(path, key, "a positive integer"))?,
+                    )
+                }
+                "max_combinations" => {
+                    config.max_combinations = Some(
+                        value
+                            .as_integer()
+                            .and_then(|value| usize::try_from(value).ok())
+                            .ok_or_else(|| Self::invalid_value(path, key, "a positive integer"))?,
+                    )
+                }
+                "disable_warnings" => {
+                    config.disable_warnings = Self::bool(path, key, value)?;
+                }
+                "disable_style_warnings" => {
+                    config.disable_style_warnings = Self::bool(path, key, value)?;
+                }
+                "disable_magic_warnings" => {
+                    config.disable_magic_warnings = Self::bool(path, key, value)?;
+                }
+                "deny" => {
+                    config.deny = Self::warnings(path, key, value)?;
+                }
+                "waits_for_input" => {
+                    config.waits_for_input = value
+                        .as_array()
+                        .and_then(|commands| {
+                            commands
+                                .iter()
+                                .map(|command| command.as_str().map(str::to_string))
+                                .collect::<Option<Vec<String>>>()
+                        })
+                        .ok_or_else(|| Self::invalid_value(path, key, "an array of strings"))?;
+                }
+                "keep_line_endings" => {
+                    config.keep_line_endings = Self::bool(path, key, value)?;
+                }
+                "keep_trailing_whitespace" => {
+                    config.keep_trailing_whitespace = Self::bool(path, key, value)?;
+                }
+                "collapse_whitespace" => {
+                    config.collapse_whitespace = Self::bool(path, key, value)?;
+                }
+                "strip_ansi" => {
+                    config.strip_ansi = Self::bool(path, key, value)?;
+                }
+                "sandbox" => {
+                    config.sandbox = Self::bool(path, key, value)?;
+                }
+                "plugins" => {
+                    let directory = path.parent().unwrap_or(Path::new(""));
+                    config.plugins = value
+                        .as_array()
+                        .and_then(|plugins| {
+                            plugins
+                                .iter()
+                                .map(|plugin| plugin.as_str().map(|plugin| directory.join(plugin)))
+                                .collect::<Option<Vec<PathBuf>>>()
+                        })
+                        .ok_or_else(|| Self::invalid_value(path, key, "an array of paths"))?;
+                }
+                "float_epsilon" => {
+                    config.float_epsilon = Some(Self::tolerance(path, key, value)?);
+                }
+                "number_tolerance" => {
+                    config.number_tolerance = Some(Self::tolerance(path, key, value)?);
+                }
+                "suite" => {
+                    let suites = value
+                        .as_table()
+                        .ok_or_else(|| Self::invalid_value(path, key, "a table of suites"))?;
+                    for (name, value) in suites {
+                        config
+                            .suites
+                            .push((name.clone(), Self::suite(path, name, value)?));
+                    }
+                }
+                _ => return Err(ConfigError::UnknownKey(path.to_path_buf(), key.clone())),
+            }
+        }
+        Ok(config)
+    }
+
+    fn suite(path: &Path, name: &str, value: &toml::Value) -> Result<SuiteConfig, ConfigError> {
+        let table = value.as_table().ok_or_else(|| {
+            Self::invalid_value(path, &format!("suite.{name}"), "a table of options")
+        })?;
+        let mut suite = SuiteConfig::default();
+        for (option, value) in table {
+            let key = format!("suite.{name}.{option}");
+            match option.as_str() {
+                "keep_line_endings" => {
+                    suite.keep_line_endings = Some(Self::bool(path, &key, value)?);
+                }
+                "keep_trailing_whitespace" => {
+                    suite.keep_trailing_whitespace = Some(Self::bool(path, &key, value)?);
+                }
+                "collapse_whitespace" => {
+                    suite.collapse_whitespace = Some(Self::bool(path, &key, value)?);
+                }
+                "strip_ansi" => {
+                    suite.strip_ansi = Some(Self::bool(path, &key, value)?);
+                }
+                "number_tolerance" => {
+                    suite.number_tolerance = Some(Self::tolerance(path, &key, value)?);
+                }
+                _ => return Err(ConfigError::UnknownKey(path.to_path_buf(), key)),
+            }
+        }
+        Ok(suite)
+    }
+
+    fn bool(path: &Path, key: &str, value: &toml::Value) -> Result<bool, ConfigError> {
+        value
+            .as_bool()
+            .ok_or_else(|| Self::invalid_value(path, key, "a bool"))
+    }
+
+    fn warnings(path: &Path, key: &str, value: &toml::Value) -> Result<Vec<String>, ConfigError> {
+        value
+            .as_array()
+            .and_then(|names| {
+                names
+                    .iter()
+                    .map(|name| name.as_str().filter(|name| WARNING_NAMES.contains(name)))
+                    .map(|name| name.map(str::to_string))
+                    .collect::<Option<Vec<String>>>()
+            })
+            .ok_or_else(|| Self::invalid_value(path, key, "an array of warning names"))
+    }
+
+    fn tolerance(path: &Path, key: &str, value: &toml::Value) -> Result<f64, ConfigError> {
+        value
+            .as_float()
+            .or_else(|| value.as_integer().map(|value| value as f64))
+            .filter(|tolerance| *tolerance >= 0.0)
+            .ok_or_else(|| Self::invalid_value(path, key, "a number that is not negative"))
+    }
+
+    fn invalid_value(path: &Path, key: &str, expected: &str) -> ConfigError {
+        ConfigError::InvalidValue {
+            path: path.to_path_buf(),
+            key: key.to_string(),
+            expected: expected.to_string(),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use crate::diagnostic::outputln;
+use crate::instruction::{Instruction, InstructionType};
+
+use indexmap::IndexMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::rc::Rc;
+
+/// What ran in one test, or outside of every test for `None`
+#[derive(Debug, Default)]
+struct Counts {
+    /// How many times each statement ran, by the offset of its first token
+    statements: HashMap<usize, u64>,
+    /// How many times each `if` ran its body and its `else`, by its offset
+    branches: HashMap<usize, [u64; 2]>,
+}
+
+/// Which statements and branches of the script ran, collected with
+/// `--script-coverage`. A statement is an instruction in a block or a global
+/// constant, and every `if` has two branches, also when it has no `else`
+#[derive(Debug)]
+pub struct Coverage {
+    file: Rc<str>,
+    source: Rc<str>,
+    /// The row of every statement, by its offset
+    statements: BTreeMap<usize, usize>,
+    /// The row of every `if` and whether it has an `else`, by its offset
+    branches: BTreeMap<usize, (usize, bool)>,
+    runs: IndexMap<Option<String>, Counts>,
+}
+
+impl Coverage {
+    pub fn new(program: &[Instruction]) -> Self {
+        let mut coverage = Self {
+            file: Rc::from(""),
+            source: Rc::from(""),
+            statements: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            runs: IndexMap::from([(None, Counts::default())]),
+        };
+        if let Some(instruction) = program.first() {
+            coverage.file = Rc::clone(&instruction.token.file);
+            coverage.source = Rc::clone(&instruction.token.source);
+        }
+        for instruction in program {
+            if let InstructionType::Assignment { .. } = instruction.r#type {
+                coverage.add_statement(instruction);
+            }
+        }
+        let mut pending = program.iter().collect::<Vec<&Instruction>>();
+        while let Some(instruction) = pending.pop() {
+            match &instruction.r#type {
+                InstructionType::Block { instructions, .. } => {
+                    for statement in instructions {
+                        coverage.add_statement(statement);
+                    }
+                }
+                InstructionType::Conditional { r#else, .. } => {
+                    let has_else = r#else.r#type != InstructionType::None;
+                    coverage
+                        .branches
+                        .insert(instruction.span.offset, (instruction.span.row, has_else));
+                }
+                _ => (),
+            }
+            pending.extend(instruction.children());
+        }
+        coverage
+    }
+
+    fn add_statement(&mut self, statement: &Instruction) {
+        if statement.r#type != InstructionType::None {
+            self.statements
+                .insert(statement.span.offset, statement.span.row);
+        }
+    }
+
+    pub fn statement(&mut self, statement: &Instruction, test: Option<&str>) {
+        let counts = self.runs.entry(test.map(String::from)).or_default();
+        *counts.statements.entry(statement.span.offset).or_default() += 1;
+    }
+
+    pub fn branch(&mut self, conditional: &Instruction, taken: bool, test: Option<&str>) {
+        let counts = self.runs.entry(test.map(String::from)).or_default();
+        let branches = counts.branches.entry(conditional.span.offset).or_default();
+        branches[usize::from(!taken)] += 1;
+    }
+
+    /// Writes the report, as HTML if the path ends in `.html` and as lcov
+    /// otherwise, and prints a summary of it
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let report = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("html") => self.html(),
+            _ => self.lcov(),
+        };
+        if let Some(directory) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(directory)?;
+        }
+        std::fs::write(path, report)?;
+
+        let total = self.total();
+        outputln!(
+            "Script coverage: {} of {} statements and {} of {} branches ran, written to {}",
+            total
+                .statements
+                .values()
+                .filter(|count| **count > 0)
+                .count(),
+            self.statements.len(),
+            self.branches_taken(&total),
+            self.branches.len() * 2,
+            path.display()
+        );
+        let tests = self.missed_else();
+        if !tests.is_empty() {
+            outputln!(
+                "Tests that never ran an else branch: {}",
+                tests
+                    .iter()
+                    .map(|(test, _)| test.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Everything that ran, in every test and outside of them
+    fn total(&self) -> Counts {
+        let mut total = Counts::default();
+        for counts in self.runs.values() {
+            for (offset, count) in &counts.statements {
+                *total.statements.entry(*offset).or_default() += count;
+            }
+            for (offset, [body, r#else]) in &counts.branches {
+                let branches = total.branches.entry(*offset).or_default();
+                branches[0] += body;
+                branches[1] += r#else;
+            }
+        }
+        total
+    }
+
+    fn branches_taken(&self, counts: &Counts) -> usize {
+        counts
+            .branches
+            .values()
+            .flatten()
+            .filter(|count| **count > 0)
+            .count()
+    }
+
+    /// How many times a statement on each row ran, the most of any statement
+    /// on the row
+    fn rows(&self, counts: &Counts) -> BTreeMap<usize, u64> {
+        let mut rows = BTreeMap::new();
+        for (offset, row) in &self.statements {
+            let count = counts.statements.get(offset).copied().unwrap_or_default();
+            let entry = rows.entry(*row).or_default();
+            *entry = count.max(*entry);
+        }
+        rows
+    }
+
+    /// Every test that ran an `if` with an `else` without running the
+    /// `else`, with the rows of those `if`s
+    fn missed_else(&self) -> Vec<(&String, Vec<usize>)> {
+        self.runs
+            .iter()
+            .filter_map(|(test, counts)| Some((test.as_ref()?, counts)))
+            .filter_map(|(test, counts)| {
+                let rows = self
+                    .branches
+                    .iter()
+                    .filter(|(offset, (_, has_else))| {
+                        *has_else
+                            && counts
+                                .branches
+                                .get(offset)
+                                .is_some_and(|[_, r#else]| *r#else == 0)
+                    })
+                    .map(|(_, (row, _))| *row)
+                    .collect::<Vec<usize>>();
+                (!rows.is_empty()).then_some((test, rows))
+            })
+            .collect()
+    }
+
+    /// One record for every test, and one named `TN:` for the global
+    /// constants and the statements that ran outside of every test
+    fn lcov(&self) -> String {
+        let mut report = String::new();
+        for (test, counts) in &self.runs {
+            report.push_str(&format!("TN:{}\n", test.as_deref().unwrap_or_default()));
+            report.push_str(&format!("SF:{}\n", self.file));
+            for (block, (offset, (row, _))) in self.branches.iter().enumerate() {
+                for (branch, taken) in counts
+                    .branches
+                    .get(offset)
+                    .map(|taken| taken.map(|count| count.to_string()))
+                    .unwrap_or_else(|| ["-".to_string(), "-".to_string()])
+                    .iter()
+                    .enumerate()
+                {
+                    report.push_str(&format!("BRDA:{},{},{},{}\n", row, block, branch, taken));
+                }
+            }
+            report.push_str(&format!("BRF:{}\n", self.branches.len() * 2));
+            report.push_str(&format!("BRH:{}\n", self.branches_taken(counts)));
+            let rows = self.rows(counts);
+            for (row, count) in &rows {
+                report.push_str(&format!("DA:{},{}\n", row, count));
+            }
+            report.push_str(&format!("LF:{}\n", rows.len()));
+            report.push_str(&format!(
+                "LH:{}\n",
+                rows.values().filter(|count| **count > 0).count()
+            ));
+            report.push_str("end_of_record\n");
+        }
+        report
+    }
+
+    /// The script with how many times each row ran, followed by the tests
+    /// that never ran an `else`
+    fn html(&self) -> String {
+        let total = self.total();
+        let rows = self.rows(&total);
+        let mut branches = HashMap::<usize, Vec<[u64; 2]>>::new();
+        for (offset, (row, _)) in &self.branches {
+            let taken = total.branches.get(offset).copied().unwrap_or_default();
+            branches.entry(*row).or_default().push(taken);
+        }
+
+        let mut report = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Coverage of {0}</title>\n<style>\n\
+             body {{ font-family: sans-serif; }}\n\
+             table {{ border-collapse: collapse; font-family: monospace; }}\n\
+             td {{ padding: 0 0.5em; white-space: pre; }}\n\
+             .count, .row {{ text-align: right; color: #666; }}\n\
+             .ran {{ background: #dfd; }}\n\
+             .missed {{ background: #fdd; }}\n\
+             .partial {{ background: #ffd; }}\n\
+             </style>\n</head>\n<body>\n<h1>Coverage of {0}</h1>\n\
+             <p>{1} of {2} statements and {3} of {4} branches ran.</p>\n",
+            escape(&self.file),
+            total
+                .statements
+                .values()
+                .filter(|count| **count > 0)
+                .count(),
+            self.statements.len(),
+            self.branches_taken(&total),
+            self.branches.len() * 2,
+        );
+
+        report.push_str("<table>\n");
+        for (index, line) in self.source.lines().enumerate() {
+            let row = index + 1;
+            let (class, count) = match rows.get(&row) {
+                Some(0) => ("missed", "0".to_string()),
+                Some(count) => ("ran", count.to_string()),
+                None => ("", String::new()),
+            };
+            let (class, title) = match branches.get(&row) {
+                Some(taken) => {
+                    let title = taken
+                        .iter()
+                        .map(|[body, r#else]| format!("body ran {}, else ran {}", body, r#else))
+                        .collect::<Vec<String>>()
+                        .join("; ");
+                    match taken.iter().flatten().all(|count| *count > 0) {
+                        true => (class, title),
+                        false => ("partial", title),
+                    }
+                }
+                None => (class, String::new()),
+            };
+            report.push_str(&format!(
+                "<tr class=\"{}\" title=\"{}\"><td class=\"row\">{}</td>\
+                 <td class=\"count\">{}</td><td>{}</td></tr>\n",
+                class,
+                title,
+                row,
+                count,
+                escape(line)
+            ));
+        }
+        report.push_str("</table>\n");
+
+        let tests = self.missed_else();
+        if !tests.is_empty() {
+            report.push_str("<h2>Tests that never ran an else branch</h2>\n<ul>\n");
+            for (test, rows) in tests {
+                let lines = match rows.len() {
+                    1 => "line",
+                    _ => "lines",
+                };
+                let rows = rows
+                    .iter()
+                    .map(|row| row.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                report.push_str(&format!(
+                    "<li><code>{}</code>: the <code>if</code> on {} {}</li>\n",
+                    escape(test),
+                    lines,
+                    rows
+                ));
+            }
+            report.push_str("</ul>\n");
+        }
+        report.push_str("</body>\n</html>\n");
+        report
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
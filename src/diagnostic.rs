@@ -0,0 +1,373 @@
+use crate::check;
+use crate::token::Token;
+
+use serde_json::json;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{IsTerminal, Write};
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+pub fn set_color(choice: ColorChoice) {
+    let colorize = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && std::io::stderr().is_terminal()
+        }
+    };
+    colored::control::set_override(colorize);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+static MESSAGE_FORMAT: OnceLock<MessageFormat> = OnceLock::new();
+
+pub fn set_message_format(format: MessageFormat) {
+    if format == MessageFormat::Json {
+        colored::control::set_override(false);
+    }
+    let _ = MESSAGE_FORMAT.set(format);
+}
+
+pub fn message_format() -> MessageFormat {
+    *MESSAGE_FORMAT.get().unwrap_or(&MessageFormat::Human)
+}
+
+/// Like `println!`, but writes to stderr with `--message-format json`, so
+/// stdout only has the diagnostics
+macro_rules! outputln {
+    ($($arg:tt)*) => {
+        match $crate::diagnostic::message_format() {
+            $crate::diagnostic::MessageFormat::Human => println!($($arg)*),
+            $crate::diagnostic::MessageFormat::Json => eprintln!($($arg)*),
+        }
+    };
+}
+
+/// Like `print!`, but writes to stderr with `--message-format json`
+macro_rules! output {
+    ($($arg:tt)*) => {
+        match $crate::diagnostic::message_format() {
+            $crate::diagnostic::MessageFormat::Human => print!($($arg)*),
+            $crate::diagnostic::MessageFormat::Json => eprint!($($arg)*),
+        }
+    };
+}
+
+pub(crate) use {output, outputln};
+
+/// A CI system that links the diagnostics it finds in the log of a job to
+/// the lines of the script
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Annotate {
+    /// Workflow commands of GitHub Actions
+    Github,
+}
+
+static ANNOTATE: OnceLock<Annotate> = OnceLock::new();
+
+pub fn set_annotate(annotate: Annotate) {
+    let _ = ANNOTATE.set(annotate);
+}
+
+static DENIED_WARNINGS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Reports the named warnings as errors, which stop the script from running
+pub fn set_denied_warnings(names: Vec<String>) {
+    let _ = DENIED_WARNINGS.set(names);
+}
+
+pub fn is_denied(warning: &str) -> bool {
+    DENIED_WARNINGS
+        .get()
+        .is_some_and(|names| names.iter().any(|name| name == warning))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+pub struct Diagnostic<'a> {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// Where the diagnostic points, `None` for runtime errors without a location
+    pub token: Option<&'a Token>,
+    pub rendered: String,
+}
+
+impl Diagnostic<'_> {
+    pub fn emit(&self) {
+        Diagnostics::with(|diagnostics| diagnostics.emit(self));
+    }
+
+    /// Writes the diagnostic to `sink` in the selected message format
+    pub fn write(&self, sink: &mut dyn Write) -> std::io::Result<()> {
+        match message_format() {
+            MessageFormat::Human => {
+                writeln!(sink, "{}", self.rendered)?;
+                match ANNOTATE.get() {
+                    Some(Annotate::Github) => writeln!(sink, "{}", self.to_github()),
+                    None => Ok(()),
+                }
+            }
+            MessageFormat::Json => writeln!(sink, "{}", self.to_json()),
+        }
+    }
+
+    /// A workflow command that makes GitHub Actions annotate the line of the
+    /// diagnostic with its message
+    pub fn to_github(&self) -> String {
+        let command = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "notice",
+        };
+        let mut properties = Vec::new();
+        if let Some(token) = self.token {
+            properties.push(format!("file={}", github_property(&token.file)));
+            properties.push(format!("line={}", token.row));
+            properties.push(format!("col={}", token.column));
+            properties.push(format!("endLine={}", token.end_row));
+            if token.end_row == token.row {
+                properties.push(format!("endColumn={}", token.end_column));
+            }
+        }
+        if !self.code.is_empty() {
+            properties.push(format!("title={}", github_property(self.code)));
+        }
+        format!(
+            "::{} {}::{}",
+            command,
+            properties.join(","),
+            github_data(&self.message)
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        json!({
+            "code": (!self.code.is_empty()).then_some(self.code),
+            "severity": self.severity.to_string(),
+            "message": self.message,
+            "file": self.token.map(|token| &*token.file),
+            "span": self.token.map(|token| json!({
+                "line": token.row,
+                "column": token.column,
+                "end_line": token.end_row,
+                "end_column": token.end_column,
+                "offset": token.offset,
+                "end_offset": token.end_offset,
+            })),
+            "rendered": self.rendered,
+        })
+        .to_string()
+    }
+}
+
+/// Collects every diagnostic and writes it to a single sink, one whole
+/// diagnostic at a time so that concurrent tests never interleave them.
+/// Human readable diagnostics are written to stderr and JSON diagnostics to
+/// stdout
+pub struct Diagnostics {
+    state: Mutex<State>,
+}
+
+struct State {
+    sink: Option<Box<dyn Write + Send>>,
+    /// Diagnostics held back until `finish`, `None` when they are written
+    /// as soon as they are emitted
+    collected: Option<Vec<Collected>>,
+}
+
+struct Collected {
+    location: Option<(String, usize, usize)>,
+    severity: Severity,
+    output: Vec<u8>,
+    diagnostic: check::Diagnostic,
+}
+
+static DIAGNOSTICS: Diagnostics = Diagnostics {
+    state: Mutex::new(State {
+        sink: None,
+        collected: None,
+    }),
+};
+
+thread_local! {
+    /// The diagnostics this thread reports to instead of `DIAGNOSTICS`, see
+    /// `Diagnostics::scope`
+    static SCOPED: RefCell<Option<Diagnostics>> = const { RefCell::new(None) };
+}
+
+/// Puts back the diagnostics a thread reported to before `scope`, also when
+/// the scope panics
+struct Restore(Option<Diagnostics>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        SCOPED.set(self.0.take());
+    }
+}
+
+impl Diagnostics {
+    /// Diagnostics that are written to `sink` instead of stderr or stdout
+    pub fn new(sink: Box<dyn Write + Send>) -> Diagnostics {
+        Diagnostics {
+            state: Mutex::new(State {
+                sink: Some(sink),
+                collected: None,
+            }),
+        }
+    }
+
+    /// Runs `f` with every diagnostic of this thread going to `self`, so
+    /// scripts can be checked on several threads at once. `collect`,
+    /// `finish` and `take` in `f` also use `self`
+    pub fn scope<T>(self, f: impl FnOnce() -> T) -> T {
+        let _restore = Restore(SCOPED.replace(Some(self)));
+        f()
+    }
+
+    /// Calls `f` with the diagnostics of this thread
+    fn with<T>(f: impl FnOnce(&Diagnostics) -> T) -> T {
+        SCOPED.with_borrow(|scoped| f(scoped.as_ref().unwrap_or(&DIAGNOSTICS)))
+    }
+
+    /// Holds back every diagnostic emitted from now on until `finish`
+    pub fn collect() {
+        Self::with(|diagnostics| {
+            diagnostics.lock().collected.get_or_insert_with(Vec::new);
+        });
+    }
+
+    /// Writes the collected diagnostics sorted by their position, without
+    /// the duplicates that error recovery may report, followed by the number
+    /// of errors and warnings. Returns the number of errors
+    pub fn finish() -> usize {
+        Self::with(Self::finish_collected)
+    }
+
+    fn finish_collected(&self) -> usize {
+        let mut state = self.lock();
+        let Some(collected) = state.collected.take() else {
+            return 0;
+        };
+        let collected = Self::sorted(collected);
+
+        let count = |severity| {
+            collected
+                .iter()
+                .filter(|diagnostic| diagnostic.severity == severity)
+                .count()
+        };
+        let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+
+        let sink = state.sink();
+        // There is nowhere left to report diagnostics that could not be written
+        for diagnostic in &collected {
+            let _ = sink.write_all(&diagnostic.output);
+        }
+        if message_format() == MessageFormat::Human && errors + warnings > 0 {
+            let _ = writeln!(
+                sink,
+                "{} {}, {} {}\n",
+                errors,
+                if errors == 1 { "error" } else { "errors" },
+                warnings,
+                if warnings == 1 { "warning" } else { "warnings" },
+            );
+        }
+        let _ = sink.flush();
+        errors
+    }
+
+    /// Returns the collected diagnostics sorted like `finish` writes them,
+    /// instead of writing them
+    pub fn take() -> Vec<check::Diagnostic> {
+        let collected = Self::with(|diagnostics| diagnostics.lock().collected.take());
+        let collected = collected.unwrap_or_default();
+        Self::sorted(collected)
+            .into_iter()
+            .map(|collected| collected.diagnostic)
+            .collect()
+    }
+
+    fn sorted(mut collected: Vec<Collected>) -> Vec<Collected> {
+        collected.sort_by(|a, b| a.location.cmp(&b.location));
+        let mut seen = HashSet::new();
+        collected.retain(|diagnostic| seen.insert(diagnostic.output.clone()));
+        collected
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn emit(&self, diagnostic: &Diagnostic) {
+        let mut state = self.lock();
+        if let Some(collected) = &mut state.collected {
+            let mut output = Vec::new();
+            let _ = diagnostic.write(&mut output);
+            collected.push(Collected {
+                location: diagnostic
+                    .token
+                    .map(|token| (token.file.to_string(), token.row, token.column)),
+                severity: diagnostic.severity,
+                output,
+                diagnostic: check::Diagnostic::from(diagnostic),
+            });
+            return;
+        }
+        let sink = state.sink();
+        // There is nowhere left to report a diagnostic that could not be written
+        let _ = diagnostic.write(sink.as_mut()).and_then(|_| sink.flush());
+    }
+}
+
+impl State {
+    fn sink(&mut self) -> &mut Box<dyn Write + Send> {
+        self.sink.get_or_insert_with(|| match message_format() {
+            MessageFormat::Human => Box::new(std::io::stderr()),
+            MessageFormat::Json => Box::new(std::io::stdout()),
+        })
+    }
+}
+
+/// Escapes the message of a GitHub workflow command
+fn github_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a property of a GitHub workflow command, which also ends at `:`
+/// and `,`
+fn github_property(value: &str) -> String {
+    github_data(value).replace(':', "%3A").replace(',', "%2C")
+}
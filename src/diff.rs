@@ -0,0 +1,128 @@
+use crate::render;
+
+use colored::Colorize;
+
+enum Line<'a> {
+    Same(&'a str),
+    Expected(&'a str),
+    Output(&'a str),
+    /// The number of expected and printed lines that were not aligned
+    Skipped(usize, usize),
+}
+
+/// The most pairs of changed lines that are aligned, since aligning takes
+/// time and memory for every pair. Beyond it only the first changed lines
+/// are shown
+const MAX_PAIRS: usize = 1_000_000;
+
+/// The lines of both sides in order, where the longest run of lines that
+/// are `equal` on both sides is kept and the rest are removed or added
+fn lines<'a>(
+    expected: &[&'a str],
+    output: &[&'a str],
+    equal: impl Fn(&str, &str) -> bool,
+) -> Vec<Line<'a>> {
+    // The lines that are equal at the start and at the end are not aligned,
+    // so a few changed lines in a long file are cheap
+    let prefix = expected
+        .iter()
+        .zip(output)
+        .take_while(|(expected, output)| equal(expected, output))
+        .count();
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(output[prefix..].iter().rev())
+        .take_while(|(expected, output)| equal(expected, output))
+        .count();
+    let changed_expected = &expected[prefix..expected.len() - suffix];
+    let changed_output = &output[prefix..output.len() - suffix];
+
+    let mut lines = output[..prefix]
+        .iter()
+        .map(|line| Line::Same(line))
+        .collect::<Vec<Line>>();
+    match changed_expected.len().saturating_mul(changed_output.len()) <= MAX_PAIRS {
+        true => lines.extend(align(changed_expected, changed_output, equal)),
+        // Both sides have changed lines, or there would be no pairs
+        false => lines.extend([
+            Line::Expected(changed_expected[0]),
+            Line::Output(changed_output[0]),
+            Line::Skipped(changed_expected.len() - 1, changed_output.len() - 1),
+        ]),
+    }
+    lines.extend(
+        output[output.len() - suffix..]
+            .iter()
+            .map(|line| Line::Same(line)),
+    );
+    lines
+}
+
+/// Aligns the lines with the longest common subsequence of both sides
+fn align<'a>(
+    expected: &[&'a str],
+    output: &[&'a str],
+    equal: impl Fn(&str, &str) -> bool,
+) -> Vec<Line<'a>> {
+    // common[i][j] is the length of the longest common subsequence of
+    // expected[i..] and output[j..]
+    let mut common = vec![vec![0usize; output.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..output.len()).rev() {
+            common[i][j] = match equal(expected[i], output[j]) {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < output.len() {
+        if i < expected.len() && j < output.len() && equal(expected[i], output[j]) {
+            lines.push(Line::Same(output[j]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == output.len() || common[i + 1][j] >= common[i][j + 1])
+        {
+            lines.push(Line::Expected(expected[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Output(output[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// A colored unified diff from the expected lines to the lines the program
+/// printed, with whitespace made visible
+pub fn unified(expected: &[&str], output: &[&str], equal: impl Fn(&str, &str) -> bool) -> String {
+    let mut diff = format!(
+        "{}\n{}\n{}\n",
+        "--- expected".bright_red(),
+        "+++ output".bright_green(),
+        format!("@@ -1,{} +1,{} @@", expected.len(), output.len()).bright_blue()
+    );
+    for line in lines(expected, output, equal) {
+        let line = match line {
+            Line::Same(line) => format!(" {}", render::visible(line)),
+            Line::Expected(line) => format!("-{}", render::visible(line))
+                .bright_red()
+                .to_string(),
+            Line::Output(line) => format!("+{}", render::visible(line))
+                .bright_green()
+                .to_string(),
+            Line::Skipped(expected, output) => format!(
+                "@@ {} more expected and {} more printed lines differ, too many to compare @@",
+                expected, output
+            )
+            .bright_blue()
+            .to_string(),
+        };
+        diff.push_str(&line);
+        diff.push('\n');
+    }
+    diff
+}
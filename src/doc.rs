@@ -0,0 +1,78 @@
+use crate::cli::Args;
+use crate::diagnostic::Diagnostics;
+use crate::instruction::{Instruction, InstructionType};
+use crate::{lexer, parser, test};
+
+pub fn run(args: Args) {
+    let (file, contents) = test::read_source(&args);
+    Diagnostics::collect();
+    let tokens = lexer::Lexer::new(&contents, file.clone()).tokenize();
+    let program = parser::Parser::new(tokens, args).parse();
+    Diagnostics::finish();
+
+    if let Ok(program) = program {
+        print!("{}", markdown(&file.to_string_lossy(), &program));
+    }
+}
+
+fn markdown(title: &str, program: &[Instruction]) -> String {
+    let mut tests = String::new();
+    let mut functions = String::new();
+
+    for instruction in program {
+        match &instruction.r#type {
+            InstructionType::Test { .. } | InstructionType::Suite { .. } => {
+                push_test(&mut tests, instruction)
+            }
+            InstructionType::Function {
+                name,
+                parameters,
+                return_type,
+                ..
+            } => {
+                let parameters = parameters
+                    .iter()
+                    .map(|parameter| parameter.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                functions.push_str(&format!("### `fn {name}({parameters}): {return_type}`\n\n"));
+                push_documentation(&mut functions, instruction);
+            }
+            _ => (),
+        }
+    }
+
+    let mut result = format!("# {title}\n\n");
+    if !tests.is_empty() {
+        result.push_str(&format!("## Tests\n\n{tests}"));
+    }
+    if !functions.is_empty() {
+        result.push_str(&format!("## Functions\n\n{functions}"));
+    }
+    result
+}
+
+/// Pushes a test, or a suite followed by its tests
+fn push_test(result: &mut String, instruction: &Instruction) {
+    match &instruction.r#type {
+        InstructionType::Test { name, command, .. } => {
+            result.push_str(&format!("### `{name}`\n\nCommand: `{command}`\n\n"));
+            push_documentation(result, instruction);
+        }
+        InstructionType::Suite { name, instructions } => {
+            result.push_str(&format!("### Suite `{name}`\n\n"));
+            push_documentation(result, instruction);
+            for instruction in instructions {
+                push_test(result, instruction);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn push_documentation(result: &mut String, instruction: &Instruction) {
+    let documentation = instruction.documentation();
+    if !documentation.is_empty() {
+        result.push_str(&format!("{documentation}\n\n"));
+    }
+}
@@ -1,14 +1,33 @@
-use crate::cli::Args;
-use crate::error::{ParseWarning, ParseWarningType};
-use crate::instruction::{Instruction, InstructionResult, InstructionType};
+use crate::cli::{Args, Verbosity};
+use crate::coverage::Coverage;
+use crate::diagnostic::outputln;
+use crate::error::{InterpreterError, InterpreterErrorType, ParseWarning, ParseWarningType};
+use crate::instruction::{self, Attribute, Instruction, InstructionResult, InstructionType};
+use crate::plugin;
+use crate::profile::Profile;
+use crate::suggestion;
+use crate::symbol::Symbol;
+use crate::token::{Token, TokenType};
 use crate::variable::Variable;
 
 use indexmap::IndexMap;
+use std::rc::Rc;
+
+/// The number of nested function calls a test may make before it fails,
+/// the interpreter runs on a stack large enough for this many calls
+pub const MAX_RECURSION_DEPTH: usize = 1000;
 
 #[derive(Debug)]
 pub struct ParseEnvironment {
     pub variables: Vec<IndexMap<String, Variable>>,
-    pub functions: IndexMap<String, Box<Instruction>>,
+    pub functions: IndexMap<String, Rc<Instruction>>,
+    /// The functions declared in each scope, a function declared inside a
+    /// test or function is removed with the scope it is declared in
+    function_scopes: Vec<Vec<String>>,
+    /// Where every function declared inside a test or function is first
+    /// declared, a global function may not be declared with the same name
+    pub nested_functions: IndexMap<String, Token>,
+    pub allowed_warnings: Vec<String>,
     pub args: Args,
 }
 
@@ -17,21 +36,59 @@ impl ParseEnvironment {
         ParseEnvironment {
             variables: vec![IndexMap::new()],
             functions: IndexMap::new(),
+            function_scopes: vec![Vec::new()],
+            nested_functions: IndexMap::new(),
+            allowed_warnings: Vec::new(),
             args,
         }
     }
 
     pub fn add_scope(&mut self) {
         self.variables.push(IndexMap::new());
+        self.function_scopes.push(Vec::new());
     }
 
     pub fn remove_scope(&mut self) {
         self.check_unused();
         self.check_assigned();
         self.variables.pop();
+        for name in self.function_scopes.pop().unwrap_or_default() {
+            self.functions.shift_remove(&name);
+        }
+    }
+
+    /// Removes every scope but the global one from `variables`. A function
+    /// is called with a new frame, so its body can not see the variables of
+    /// the test or function it is declared in
+    pub fn hide_locals(&mut self) -> Vec<IndexMap<String, Variable>> {
+        self.variables.split_off(1)
+    }
+
+    pub fn restore_locals(&mut self, locals: Vec<IndexMap<String, Variable>>) {
+        self.variables.extend(locals);
+    }
+
+    /// Whether a function declared now is inside a test or function
+    pub fn is_nested(&self) -> bool {
+        self.function_scopes.len() > 1
     }
 
-    pub fn insert(&mut self, variable: Variable) {
+    pub fn allow_warnings(&mut self, attributes: &[Attribute]) {
+        for attribute in attributes.iter().filter(|a| a.name == "allow") {
+            for argument in &attribute.arguments {
+                if let TokenType::Identifier { value } = &argument.r#type {
+                    self.allowed_warnings.push(value.clone());
+                }
+            }
+        }
+    }
+
+    pub fn insert(&mut self, mut variable: Variable) {
+        for name in &self.allowed_warnings {
+            if !variable.allowed_warnings.contains(name) {
+                variable.allowed_warnings.push(name.clone());
+            }
+        }
         self.variables
             .last_mut()
             .unwrap()
@@ -48,6 +105,15 @@ impl ParseEnvironment {
         None
     }
 
+    /// Whether `name` refers to a global constant, and not to a variable
+    /// that shadows it
+    pub fn is_global(&self, name: &str) -> bool {
+        self.variables
+            .iter()
+            .rposition(|scope| scope.contains_key(name))
+            == Some(0)
+    }
+
     fn check_unused(&self) {
         for variable in &self.variables[self.variables.len() - 1] {
             if !variable.1.read && variable.1.name.chars().nth(0).unwrap() != '_' {
@@ -56,13 +122,13 @@ impl ParseEnvironment {
                         ParseWarningType::VariableNotRead,
                         variable.1.last_assignment_token.clone(),
                     )
-                    .print(self.args.disable_warnings);
+                    .print(self.args.disable_warnings, &variable.1.allowed_warnings);
                 } else {
                     ParseWarning::new(
                         ParseWarningType::UnusedVariable,
                         variable.1.identifier_token.clone(),
                     )
-                    .print(self.args.disable_warnings);
+                    .print(self.args.disable_warnings, &variable.1.allowed_warnings);
                 }
             }
         }
@@ -78,44 +144,98 @@ impl ParseEnvironment {
                     ParseWarningType::VariableNeverReAssigned,
                     variable.1.declaration_token.clone(),
                 )
-                .print(self.args.disable_warnings);
+                .print(self.args.disable_warnings, &variable.1.allowed_warnings);
             }
         }
     }
 
-    pub fn add_function(&mut self, function: Box<Instruction>) {
+    pub fn add_function(&mut self, function: Rc<Instruction>) {
         match &function.r#type {
             InstructionType::Function { name, .. } => {
+                if self.is_nested() {
+                    let scope = self.function_scopes.last_mut().unwrap();
+                    if !scope.contains(name) {
+                        scope.push(name.clone());
+                    }
+                    self.nested_functions
+                        .entry(name.clone())
+                        .or_insert_with(|| function.token.clone());
+                }
                 self.functions.insert(name.to_string(), function);
             }
             _ => unreachable!(),
         }
     }
 
-    pub fn get_function(&self, name: &str) -> Option<&Box<Instruction>> {
+    pub fn get_function(&self, name: &str) -> Option<&Rc<Instruction>> {
         self.functions.get(name)
     }
+
+    pub fn similar_variable(&self, name: &str) -> Option<String> {
+        suggestion::similar(
+            name,
+            self.variables
+                .iter()
+                .flat_map(|scope| scope.keys())
+                .map(String::as_str),
+        )
+    }
+
+    pub fn similar_function(&self, name: &str) -> Option<String> {
+        suggestion::similar(
+            name,
+            self.functions
+                .keys()
+                .map(String::as_str)
+                .chain(instruction::builtin_names())
+                .chain(plugin::names()),
+        )
+    }
 }
 
 pub struct Environment {
     pub frames: Vec<Frame>,
-    pub global_constants: IndexMap<String, InstructionResult>,
-    pub functions: IndexMap<String, Instruction>,
+    pub global_constants: IndexMap<Symbol, InstructionResult>,
+    /// Shared with every call, so that calling a function does not copy its body
+    pub functions: IndexMap<String, Rc<Instruction>>,
+    pub calls: Vec<Call>,
+    pub test: Option<String>,
+    /// Set with `--profile-interpreter`
+    pub profile: Option<Profile>,
+    /// Set with `--script-coverage`
+    pub coverage: Option<Coverage>,
+    /// How much two floats may differ and still be equal
+    pub float_epsilon: f64,
+    /// Set with `--update-expected`, `output_file` writes the output to its
+    /// file instead of comparing them
+    pub update_expected: bool,
+    verbosity: Verbosity,
 }
 
 impl Environment {
-    pub fn new() -> Environment {
+    pub fn new(verbosity: Verbosity) -> Environment {
         Self {
             frames: vec![],
             global_constants: IndexMap::new(),
             functions: IndexMap::new(),
+            calls: Vec::new(),
+            test: None,
+            profile: None,
+            coverage: None,
+            update_expected: false,
+            float_epsilon: 0.0,
+            verbosity,
         }
     }
 
-    pub fn add_frame(&mut self) {
+    pub fn add_frame(&mut self) -> Result<(), InterpreterError> {
+        if self.frames.len() > MAX_RECURSION_DEPTH {
+            return Err(InterpreterErrorType::RecursionLimit(MAX_RECURSION_DEPTH).into());
+        }
         self.frames.push(Frame {
             variables: vec![IndexMap::new()],
         });
+        Ok(())
     }
 
     pub fn remove_frame(&mut self) {
@@ -132,7 +252,10 @@ impl Environment {
         self.frames[len - 1].variables.pop();
     }
 
-    pub fn insert(&mut self, name: String, value: InstructionResult) {
+    pub fn insert(&mut self, name: Symbol, value: InstructionResult) {
+        if self.verbosity >= Verbosity::VeryVerbose {
+            outputln!("Environment: {name} = {value}");
+        }
         let len = self.frames.len();
         if len == 0 {
             self.global_constants.insert(name, value);
@@ -147,21 +270,21 @@ impl Environment {
             .insert(name, value);
     }
 
-    pub fn get(&self, name: &str) -> Option<&InstructionResult> {
+    pub fn get(&self, name: Symbol) -> Option<&InstructionResult> {
         let len = self.frames.len();
         if len == 0 {
-            return self.global_constants.get(name);
+            return self.global_constants.get(&name);
         }
         for scope in self.frames[len - 1].variables.iter().rev() {
-            if let Some(r#type) = scope.get(name) {
+            if let Some(r#type) = scope.get(&name) {
                 return Some(r#type);
             }
         }
 
-        self.global_constants.get(name)
+        self.global_constants.get(&name)
     }
 
-    pub fn add_function(&mut self, function: Instruction) {
+    pub fn add_function(&mut self, function: Rc<Instruction>) {
         match &function.r#type {
             InstructionType::Function { name, .. } => {
                 self.functions.insert(name.to_string(), function);
@@ -170,12 +293,18 @@ impl Environment {
         }
     }
 
-    pub fn get_function(&self, name: &str) -> Option<&Instruction> {
-        self.functions.get(name)
+    pub fn get_function(&self, name: &str) -> Option<Rc<Instruction>> {
+        self.functions.get(name).cloned()
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub name: String,
+    pub token: Token,
+}
+
 #[derive(Debug)]
 pub struct Frame {
-    pub variables: Vec<IndexMap<String, InstructionResult>>,
+    pub variables: Vec<IndexMap<Symbol, InstructionResult>>,
 }
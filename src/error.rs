@@ -1,4 +1,7 @@
-use crate::instruction::InstructionResult;
+use crate::diagnostic::{self, Diagnostic, Severity};
+use crate::environment::{Call, Environment};
+use crate::exitcode::ExitCode;
+use crate::instruction::{AttributeTarget, InstructionResult};
 use crate::r#type::Type;
 use crate::token::{PrintStyle, Token, TokenType};
 use crate::variable::{SnakeCase, Variable};
@@ -51,8 +54,57 @@ impl<'a> LexerError<'a> {
     }
 }
 
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Syntax(PathBuf, String),
+    UnknownKey(PathBuf, String),
+    InvalidValue {
+        path: PathBuf,
+        key: String,
+        expected: String,
+    },
+    Plugin(PathBuf, String),
+}
+
+impl ConfigError {
+    pub fn print(&self) {
+        let error_msg = match &self {
+            ConfigError::Io(path, e) => {
+                format!("Could not read config file `{}`: {}", path.display(), e)
+            }
+            ConfigError::Syntax(path, message) => {
+                format!("Invalid config file `{}`: {}", path.display(), message)
+            }
+            ConfigError::UnknownKey(path, key) => {
+                format!("Unknown key `{}` in config file `{}`", key, path.display())
+            }
+            ConfigError::InvalidValue {
+                path,
+                key,
+                expected,
+            } => format!(
+                "Expected `{}` to be {} in config file `{}`",
+                key,
+                expected,
+                path.display()
+            ),
+            ConfigError::Plugin(path, message) => {
+                format!(
+                    "Failed to load the plugin `{}`: {}",
+                    path.display(),
+                    message
+                )
+            }
+        };
+        eprintln!("{}{}\n", "error: ".bright_red(), error_msg);
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseErrorType {
+    UnexpectedCharacter(char),
+    IntegerTooLarge(String),
     UnexpectedToken(TokenType),
 
     UnexpectedEndOfFile,
@@ -72,26 +124,111 @@ pub enum ParseErrorType {
     },
 
     GlobalScope(TokenType),
+    SuiteScope(TokenType),
 
     TypeCast {
         from: Type,
         to: Type,
     },
 
-    RegexError,
+    RegexError(Option<String>),
+    RegexTooLarge {
+        pattern: String,
+        count: usize,
+        budget: usize,
+    },
 
-    IdentifierNotDefined(String),
+    IdentifierNotDefined {
+        name: String,
+        suggestion: Option<String>,
+    },
+    FunctionNotDefined {
+        name: String,
+        suggestion: Option<String>,
+    },
+    NotCallable(Variable),
+    FunctionRedefinition(String),
 
-    ConstantReassignment(Variable),
+    ConstantReassignment {
+        variable: Variable,
+        global: bool,
+    },
+    ConstantCycle(Vec<String>),
 
     VaribleTypeAnnotation,
 
+    AssignmentInCondition,
+
+    /// A builtin that uses the program of a test, or a function that calls
+    /// one, used where no test is running
+    ProgramOutsideTest {
+        name: String,
+        function: bool,
+    },
+
+    MissingReturn {
+        name: String,
+        expected: Type,
+    },
+
+    UnknownAttribute(String),
+    UnknownWarning(String),
+    InvalidAttributeArgument {
+        attribute: String,
+        expected: String,
+    },
+    MisplacedAttribute {
+        attribute: String,
+        target: AttributeTarget,
+    },
+
     None,
 }
 
+impl ParseErrorType {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseErrorType::UnexpectedToken(_) => "E0001",
+            ParseErrorType::UnexpectedEndOfFile => "E0002",
+            ParseErrorType::UnclosedDelimiter(_) => "E0003",
+            ParseErrorType::MismatchedType { .. } => "E0004",
+            ParseErrorType::MismatchedArguments { .. } => "E0005",
+            ParseErrorType::MismatchedTokenType { .. } => "E0006",
+            ParseErrorType::GlobalScope(_) => "E0007",
+            ParseErrorType::SuiteScope(_) => "E0024",
+            ParseErrorType::TypeCast { .. } => "E0008",
+            ParseErrorType::RegexError(_) => "E0009",
+            ParseErrorType::RegexTooLarge { .. } => "E0016",
+            ParseErrorType::IdentifierNotDefined { .. } => "E0010",
+            ParseErrorType::ConstantReassignment { .. } => "E0011",
+            ParseErrorType::VaribleTypeAnnotation => "E0012",
+            ParseErrorType::UnknownAttribute(_) => "E0013",
+            ParseErrorType::UnknownWarning(_) => "E0014",
+            ParseErrorType::InvalidAttributeArgument { .. } => "E0015",
+            ParseErrorType::MissingReturn { .. } => "E0017",
+            ParseErrorType::FunctionNotDefined { .. } => "E0018",
+            ParseErrorType::NotCallable(_) => "E0019",
+            ParseErrorType::AssignmentInCondition => "E0020",
+            ParseErrorType::FunctionRedefinition(_) => "E0021",
+            ParseErrorType::MisplacedAttribute { .. } => "E0022",
+            ParseErrorType::ConstantCycle(_) => "E0023",
+            ParseErrorType::ProgramOutsideTest { .. } => "E0025",
+            ParseErrorType::UnexpectedCharacter(_) => "E0026",
+            ParseErrorType::IntegerTooLarge(_) => "E0027",
+            ParseErrorType::None => "",
+        }
+    }
+}
+
 impl std::fmt::Display for ParseErrorType {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
+            ParseErrorType::UnexpectedCharacter(c) => {
+                write!(f, "Unexpected character: {:?}", c)
+            }
+            ParseErrorType::IntegerTooLarge(value) => {
+                write!(f, "Integer literal `{}` is larger than {}", value, i64::MAX)
+            }
             ParseErrorType::UnexpectedToken(token) => {
                 let token = match token {
                     TokenType::Semicolon
@@ -100,6 +237,9 @@ impl std::fmt::Display for ParseErrorType {
                     | TokenType::OpenBlock
                     | TokenType::CloseBlock
                     | TokenType::Colon
+                    | TokenType::Attribute
+                    | TokenType::OpenBracket
+                    | TokenType::CloseBracket
                     | TokenType::Type { .. } => format!("`{token}`"),
                     _ => format!("{token}"),
                 };
@@ -144,6 +284,9 @@ impl std::fmt::Display for ParseErrorType {
                     | TokenType::OpenBlock
                     | TokenType::CloseBlock
                     | TokenType::Colon
+                    | TokenType::Attribute
+                    | TokenType::OpenBracket
+                    | TokenType::CloseBracket
                     | TokenType::Type { .. } => format!("`{expected}`"),
                     _ => format!("{expected}"),
                 };
@@ -157,22 +300,98 @@ impl std::fmt::Display for ParseErrorType {
             ParseErrorType::GlobalScope(token) => {
                 write!(f, "Unexpected token in global scope: {token}")
             }
+            ParseErrorType::SuiteScope(token) => {
+                write!(f, "Unexpected token in suite: {token}")
+            }
 
             ParseErrorType::TypeCast { from, to } => {
                 write!(f, "Cannot cast `{from}` to `{to}`")
             }
 
-            ParseErrorType::RegexError => write!(f, "Regex syntax not supported"),
+            ParseErrorType::RegexError(None) => write!(f, "Regex syntax not supported"),
+            ParseErrorType::RegexError(Some(message)) => write!(f, "Invalid regex: {message}"),
+            ParseErrorType::RegexTooLarge {
+                pattern,
+                count,
+                budget,
+            } => {
+                let count = match *count {
+                    usize::MAX => String::from("too many"),
+                    count => format!("at least {count}"),
+                };
+                write!(
+                    f,
+                    "Regex expands to too many strings: `{pattern}` creates {count}, \
+                     but at most {budget} are allowed (lower `--max-size` or raise \
+                     `--max-combinations`)"
+                )
+            }
 
-            ParseErrorType::IdentifierNotDefined(identifier) => {
-                write!(f, "Identifier `{identifier}` not defined")
+            ParseErrorType::IdentifierNotDefined { name, .. } => {
+                write!(f, "Identifier `{name}` not defined")
+            }
+            ParseErrorType::FunctionNotDefined { name, .. } => {
+                write!(f, "Function `{name}` not defined")
+            }
+            ParseErrorType::NotCallable(variable) => {
+                write!(f, "`{}` is a variable, not a function", variable.name)
             }
-            ParseErrorType::ConstantReassignment(constant) => {
-                write!(f, "Cannot reassign constant `{}`", constant.name)
+            ParseErrorType::FunctionRedefinition(name) => {
+                write!(f, "Function `{name}` is already defined")
             }
+            ParseErrorType::ConstantReassignment { variable, .. } => {
+                write!(f, "Cannot reassign constant `{}`", variable.name)
+            }
+            ParseErrorType::ConstantCycle(names) => match names.as_slice() {
+                [name] => write!(f, "Constant `{name}` depends on itself"),
+                [names @ .., last] => write!(
+                    f,
+                    "Constants {} and `{last}` depend on each other",
+                    names
+                        .iter()
+                        .map(|name| format!("`{name}`"))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+                [] => unreachable!(),
+            },
             ParseErrorType::VaribleTypeAnnotation => {
                 write!(f, "Type annotations are required")
             }
+            ParseErrorType::AssignmentInCondition => {
+                write!(f, "Assignment used as a condition")
+            }
+            ParseErrorType::ProgramOutsideTest { name, function } => match function {
+                true => write!(
+                    f,
+                    "Function `{name}` uses the program of a test, but is called outside of a test"
+                ),
+                false => write!(
+                    f,
+                    "`{name}` uses the program of a test, but is used outside of a test"
+                ),
+            },
+            ParseErrorType::MissingReturn { name, expected } => {
+                write!(
+                    f,
+                    "Function `{name}` does not end with a value of type `{expected}` on every path"
+                )
+            }
+            ParseErrorType::UnknownAttribute(name) => {
+                write!(f, "Unknown attribute `{name}`")
+            }
+            ParseErrorType::UnknownWarning(name) => {
+                write!(f, "Unknown warning `{name}`")
+            }
+            ParseErrorType::InvalidAttributeArgument {
+                attribute,
+                expected,
+            } => {
+                write!(f, "Invalid argument to `{attribute}`, expected {expected}")
+            }
+            ParseErrorType::MisplacedAttribute { attribute, target } => {
+                write!(f, "Attribute `{attribute}` can not be used on {target}")
+            }
 
             ParseErrorType::None => write!(f, ""),
         }
@@ -183,17 +402,41 @@ impl std::fmt::Display for ParseErrorType {
 pub struct ParseError {
     pub r#type: ParseErrorType,
     token: Token,
+    /// Other code that explains the error, with a message for each
+    labels: Vec<(Token, String)>,
 }
 
 impl ParseError {
     pub fn new(r#type: ParseErrorType, token: Token) -> ParseError {
-        ParseError { r#type, token }
+        ParseError {
+            r#type,
+            token,
+            labels: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, token: Token, message: impl Into<String>) -> ParseError {
+        self.labels.push((token, message.into()));
+        self
+    }
+
+    /// Whether the error is only caused by a value of unknown type, which
+    /// already has been reported where its type was lost
+    pub fn is_caused_by_unknown_type(&self) -> bool {
+        match &self.r#type {
+            ParseErrorType::MismatchedType { expected, actual } => {
+                *actual == Type::Any || expected.contains(&Type::Any)
+            }
+            ParseErrorType::TypeCast { from, .. } => *from == Type::Any,
+            _ => false,
+        }
     }
 
     pub fn none() -> ParseError {
         ParseError {
             r#type: ParseErrorType::None,
             token: Token::none(),
+            labels: Vec::new(),
         }
     }
 
@@ -202,18 +445,33 @@ impl ParseError {
             return;
         }
 
+        Diagnostic {
+            code: self.r#type.code(),
+            severity: Severity::Error,
+            message: self.r#type.to_string(),
+            token: Some(&self.token),
+            rendered: self.render(),
+        }
+        .emit();
+    }
+
+    fn header(&self) -> colored::ColoredString {
+        format!("error[{}]: ", self.r#type.code()).bright_red()
+    }
+
+    pub fn render(&self) -> String {
         match &self.r#type {
             ParseErrorType::MismatchedTokenType {
                 expected: TokenType::Semicolon,
                 actual: _actual,
             } => match &self.token.last_token {
                 Some(last_token) => {
-                    eprintln!(
+                    format!(
                         "{}{}              \n\
                          In: {}:{}:{}      \n\
                          {}                \n\
                          {}                \n",
-                        "error: ".bright_red(),
+                        self.header(),
                         self.r#type,
                         last_token.file,
                         last_token.row,
@@ -224,11 +482,11 @@ impl ParseError {
                     )
                 }
                 None => {
-                    eprintln!(
+                    format!(
                         "{}{}              \n\
                          In: {}:{}:{}      \n\
                          {}                \n",
-                        "error: ".bright_red(),
+                        self.header(),
                         self.r#type,
                         self.token.file,
                         self.token.row,
@@ -237,29 +495,103 @@ impl ParseError {
                     )
                 }
             },
-            ParseErrorType::ConstantReassignment(var) => {
-                eprintln!(
+            ParseErrorType::ConstantReassignment {
+                variable: var,
+                global,
+            } => {
+                // Parameters are declared by their name, other constants by `const`
+                let help = match (global, &var.declaration_token.r#type) {
+                    (true, _) => "declared as a global constant here",
+                    (false, TokenType::Identifier { .. }) => "declared as a `const` parameter here",
+                    (false, _) => "consider changing to `let`",
+                };
+                format!(
                     "{}{}              \n\
                      In: {}:{}:{}      \n\
                      {}                \n\
                      {}                \n",
-                    "error: ".bright_red(),
+                    self.header(),
                     self.r#type,
                     self.token.file,
                     var.declaration_token.row,
                     var.declaration_token.column,
-                    var.declaration_token
-                        .as_string(PrintStyle::Help("consider changing to `let`")),
+                    var.declaration_token.as_string(PrintStyle::Help(help)),
                     self.token.as_string(PrintStyle::Error),
                 )
             }
 
-            ParseErrorType::VaribleTypeAnnotation => eprintln!(
+            ParseErrorType::IdentifierNotDefined {
+                suggestion: Some(suggestion),
+                ..
+            } => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {}                \n\
+                 {}a variable with a similar name exists: `{}`\n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Error),
+                "help: ".bright_blue(),
+                suggestion,
+            ),
+            ParseErrorType::FunctionNotDefined {
+                suggestion: Some(suggestion),
+                ..
+            } => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {}                \n\
+                 {}a function with a similar name exists: `{}`\n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Error),
+                "help: ".bright_blue(),
+                suggestion,
+            ),
+            ParseErrorType::NotCallable(variable) => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {}                \n\
+                 {}                \n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                variable
+                    .identifier_token
+                    .as_string(PrintStyle::Help(&format!(
+                        "`{}` is declared here with type `{}`",
+                        variable.name, variable.r#type
+                    ))),
+                self.token.as_string(PrintStyle::Error),
+            ),
+
+            ParseErrorType::AssignmentInCondition => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {}                \n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token
+                    .as_string(PrintStyle::Help("use `==` to compare the values")),
+            ),
+
+            ParseErrorType::VaribleTypeAnnotation => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
                  {}                \n\
                  {}                \n",
-                "error: ".bright_red(),
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
@@ -270,19 +602,41 @@ impl ParseError {
                 ),
                 self.token.as_string(PrintStyle::Error),
             ),
-            _ => eprintln!(
+            _ => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
-                 {}                \n",
-                "error: ".bright_red(),
+                 {}",
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
                 self.token.column,
-                self.token.as_string(PrintStyle::Error),
+                self.render_spans(),
             ),
         }
     }
+
+    /// The erroneous code and the labels in the order they appear in the file
+    fn render_spans(&self) -> String {
+        let mut spans = vec![(&self.token, None)];
+        spans.extend(
+            self.labels
+                .iter()
+                .filter(|(token, _)| token.file == self.token.file)
+                .map(|(token, message)| (token, Some(message))),
+        );
+        spans.sort_by_key(|(token, _)| token.offset);
+        spans
+            .into_iter()
+            .map(|(token, message)| {
+                let style = match message {
+                    Some(message) => PrintStyle::Help(message),
+                    None => PrintStyle::Error,
+                };
+                format!("{}                \n", token.as_string(style))
+            })
+            .collect()
+    }
 }
 
 pub enum ParseWarningType<'a> {
@@ -290,6 +644,7 @@ pub enum ParseWarningType<'a> {
     EmptyBlock,
 
     UnusedValue,
+    UnusedComparison,
     UnusedVariable,
     VariableNotRead,
     VariableNeverReAssigned,
@@ -302,6 +657,17 @@ pub enum ParseWarningType<'a> {
     NoBlock(&'a Token),
 
     MagicLiteral(Type),
+
+    ConstantCondition(bool),
+    DivisionByZero,
+
+    LossyCast { from: Type, to: Type },
+    LossyClass,
+
+    UnconditionalRecursion(String),
+    AssignmentAsValue,
+
+    OutputBeforeInput(String),
 }
 
 pub struct ParseWarning<'a> {
@@ -309,12 +675,61 @@ pub struct ParseWarning<'a> {
     pub token: Token,
 }
 
+pub const WARNING_NAMES: [&str; 19] = [
+    "trailing_semicolon",
+    "empty_block",
+    "unused_value",
+    "unused_comparison",
+    "unused_variable",
+    "variable_not_read",
+    "variable_never_reassigned",
+    "constant_not_upper_case",
+    "variable_not_snake_case",
+    "self_assignment",
+    "no_block",
+    "magic_literal",
+    "constant_condition",
+    "division_by_zero",
+    "lossy_cast",
+    "lossy_class",
+    "unconditional_recursion",
+    "assignment_as_value",
+    "output_before_input",
+];
+
+impl<'a> ParseWarningType<'a> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParseWarningType::TrailingSemicolon => "trailing_semicolon",
+            ParseWarningType::EmptyBlock => "empty_block",
+            ParseWarningType::UnusedValue => "unused_value",
+            ParseWarningType::UnusedComparison => "unused_comparison",
+            ParseWarningType::UnusedVariable => "unused_variable",
+            ParseWarningType::VariableNotRead => "variable_not_read",
+            ParseWarningType::VariableNeverReAssigned => "variable_never_reassigned",
+            ParseWarningType::ConstantNotUpperCase(_) => "constant_not_upper_case",
+            ParseWarningType::VariableNotSnakeCase(_) => "variable_not_snake_case",
+            ParseWarningType::SelfAssignment => "self_assignment",
+            ParseWarningType::NoBlock(_) => "no_block",
+            ParseWarningType::MagicLiteral(_) => "magic_literal",
+            ParseWarningType::ConstantCondition(_) => "constant_condition",
+            ParseWarningType::DivisionByZero => "division_by_zero",
+            ParseWarningType::LossyCast { .. } => "lossy_cast",
+            ParseWarningType::LossyClass => "lossy_class",
+            ParseWarningType::UnconditionalRecursion(_) => "unconditional_recursion",
+            ParseWarningType::AssignmentAsValue => "assignment_as_value",
+            ParseWarningType::OutputBeforeInput(_) => "output_before_input",
+        }
+    }
+}
+
 impl<'a> std::fmt::Display for ParseWarningType<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ParseWarningType::TrailingSemicolon => write!(f, "Trailing semicolon"),
             ParseWarningType::EmptyBlock => write!(f, "Empty block"),
             ParseWarningType::UnusedValue => write!(f, "Unused value"),
+            ParseWarningType::UnusedComparison => write!(f, "Comparison used as a statement"),
             ParseWarningType::UnusedVariable => write!(f, "Unused variable"),
             ParseWarningType::VariableNotRead => {
                 write!(f, "Variable is not read after assignment")
@@ -331,6 +746,23 @@ impl<'a> std::fmt::Display for ParseWarningType<'a> {
             ParseWarningType::SelfAssignment => write!(f, "Assignment without effect"),
             ParseWarningType::NoBlock(_) => write!(f, "A block should be used here"),
             ParseWarningType::MagicLiteral(r#type) => write!(f, "Magic {type} detected"),
+            ParseWarningType::ConstantCondition(value) => {
+                write!(f, "Condition is always {value}")
+            }
+            ParseWarningType::DivisionByZero => write!(f, "Division by zero"),
+            ParseWarningType::LossyCast { from, to } => {
+                write!(f, "Lossy cast from `{from}` to `{to}`")
+            }
+            ParseWarningType::LossyClass => {
+                write!(f, "Regex only creates the ASCII characters of a class")
+            }
+            ParseWarningType::UnconditionalRecursion(name) => {
+                write!(f, "Function `{name}` calls itself on every path")
+            }
+            ParseWarningType::AssignmentAsValue => write!(f, "Assignment used as a value"),
+            ParseWarningType::OutputBeforeInput(command) => {
+                write!(f, "Output is read before `{command}` is sent any input")
+            }
         }
     }
 }
@@ -340,16 +772,53 @@ impl<'a> ParseWarning<'a> {
         ParseWarning { r#type, token }
     }
 
-    pub fn print(&self, disable_warnings: bool) {
-        if disable_warnings {
+    fn ignored_by_comment(&self) -> bool {
+        match self.token.line().rfind("//") {
+            Some(index) => self.token.line()[index + 2..].trim() == "tesc:ignore",
+            None => false,
+        }
+    }
+
+    /// A warning denied with `--deny` is reported as an error, even when
+    /// warnings are disabled, unless it is allowed where it occurs
+    pub fn print(&self, disable_warnings: bool, allowed_warnings: &[String]) {
+        let denied = diagnostic::is_denied(self.r#type.name());
+        if (disable_warnings && !denied)
+            || allowed_warnings
+                .iter()
+                .any(|name| name == self.r#type.name())
+            || self.ignored_by_comment()
+        {
             return;
         }
+
+        Diagnostic {
+            code: self.r#type.name(),
+            severity: match denied {
+                true => Severity::Error,
+                false => Severity::Warning,
+            },
+            message: self.r#type.to_string(),
+            token: Some(&self.token),
+            rendered: self.render(),
+        }
+        .emit();
+    }
+
+    fn header(&self) -> colored::ColoredString {
+        match diagnostic::is_denied(self.r#type.name()) {
+            true => format!("error[{}]: ", self.r#type.name()).bright_red(),
+            false => "warning: ".bright_yellow(),
+        }
+    }
+
+    pub fn render(&self) -> String {
         match &self.r#type {
-            ParseWarningType::TrailingSemicolon => eprintln!(
+            ParseWarningType::TrailingSemicolon => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
                  {} {}             \n",
-                "warning: ".bright_yellow(),
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
@@ -357,11 +826,11 @@ impl<'a> ParseWarning<'a> {
                 self.token.as_string(PrintStyle::Warning),
                 "remove this semicolon".bright_yellow(),
             ),
-            ParseWarningType::EmptyBlock => eprintln!(
+            ParseWarningType::EmptyBlock => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
                  {} {}             \n",
-                "warning: ".bright_yellow(),
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
@@ -369,22 +838,34 @@ impl<'a> ParseWarning<'a> {
                 self.token.as_string(PrintStyle::Warning),
                 "remove this block".bright_yellow(),
             ),
-            ParseWarningType::UnusedValue => eprintln!(
+            ParseWarningType::UnusedValue => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
                  {}                \n",
-                "warning: ".bright_yellow(),
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
                 self.token.column,
                 self.token.as_string(PrintStyle::Warning),
             ),
-            ParseWarningType::UnusedVariable => eprintln!(
+            ParseWarningType::UnusedComparison => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
                  {} {}             \n",
-                "warning: ".bright_yellow(),
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Warning),
+                "use `=` to assign a value".bright_yellow(),
+            ),
+            ParseWarningType::UnusedVariable => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {} {}             \n",
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
@@ -392,22 +873,22 @@ impl<'a> ParseWarning<'a> {
                 self.token.as_string(PrintStyle::Warning),
                 "prefix with `_` to suppress this warning".bright_yellow(),
             ),
-            ParseWarningType::VariableNotRead => eprintln!(
+            ParseWarningType::VariableNotRead => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
                  {}                \n",
-                "warning: ".bright_yellow(),
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
                 self.token.column,
                 self.token.as_string(PrintStyle::Warning),
             ),
-            ParseWarningType::VariableNeverReAssigned => eprintln!(
+            ParseWarningType::VariableNeverReAssigned => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
                  {} {}             \n",
-                "warning: ".bright_yellow(),
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
@@ -417,11 +898,11 @@ impl<'a> ParseWarning<'a> {
             ),
             ParseWarningType::ConstantNotUpperCase(identifier) => {
                 let new_name = identifier.to_upper_snake_case();
-                eprintln!(
+                format!(
                     "{}{}              \n\
                      In: {}:{}:{}      \n\
                      {} {}             \n",
-                    "warning: ".bright_yellow(),
+                    self.header(),
                     self.r#type,
                     self.token.file,
                     self.token.row,
@@ -432,11 +913,11 @@ impl<'a> ParseWarning<'a> {
             }
             ParseWarningType::VariableNotSnakeCase(identifier) => {
                 let new_name = identifier.to_snake_case();
-                eprintln!(
+                format!(
                     "{}{}              \n\
                      In: {}:{}:{}      \n\
                      {} {}             \n",
-                    "warning: ".bright_yellow(),
+                    self.header(),
                     self.r#type,
                     self.token.file,
                     self.token.row,
@@ -445,11 +926,11 @@ impl<'a> ParseWarning<'a> {
                     format!("consider changing the name to {new_name}").bright_yellow(),
                 )
             }
-            ParseWarningType::SelfAssignment => eprintln!(
+            ParseWarningType::SelfAssignment => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
                  {}                \n",
-                "warning: ".bright_yellow(),
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
@@ -458,11 +939,11 @@ impl<'a> ParseWarning<'a> {
             ),
             ParseWarningType::NoBlock(token) => match &self.token.last_token {
                 Some(last_token) => {
-                    eprintln!(
+                    format!(
                         "{}{}              \n\
                              In: {}:{}:{}      \n\
                              {}                \n",
-                        "warning: ".bright_yellow(),
+                        self.header(),
                         self.r#type,
                         last_token.file,
                         last_token.row,
@@ -472,11 +953,11 @@ impl<'a> ParseWarning<'a> {
                 }
                 _ => unreachable!(),
             },
-            ParseWarningType::MagicLiteral(_type) => eprintln!(
+            ParseWarningType::MagicLiteral(_type) => format!(
                 "{}{}              \n\
                  In: {}:{}:{}      \n\
                  {} {}             \n",
-                "warning: ".bright_yellow(),
+                self.header(),
                 self.r#type,
                 self.token.file,
                 self.token.row,
@@ -484,28 +965,366 @@ impl<'a> ParseWarning<'a> {
                 self.token.as_string(PrintStyle::Warning),
                 "consider using a named constant".bright_yellow(),
             ),
+            ParseWarningType::ConstantCondition(value) => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {} {}             \n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Warning),
+                match value {
+                    true => "the `else` branch is never run",
+                    false => "this branch is never run",
+                }
+                .bright_yellow(),
+            ),
+            ParseWarningType::DivisionByZero => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {} {}             \n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Warning),
+                "this is always zero".bright_yellow(),
+            ),
+            ParseWarningType::LossyCast { to, .. } => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {} {}             \n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Warning),
+                format!("`try_cast(value, {to})` fails instead of losing information")
+                    .bright_yellow(),
+            ),
+            ParseWarningType::LossyClass => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {} {}             \n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Warning),
+                "other characters are only created if they are written out, like `[a-zé]`"
+                    .bright_yellow(),
+            ),
+            ParseWarningType::UnconditionalRecursion(_) => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {} {}             \n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Warning),
+                "it can never return, and every test calling it fails".bright_yellow(),
+            ),
+            ParseWarningType::AssignmentAsValue => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {} {}             \n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Warning),
+                "an assignment has no value, assign first and then use the variable"
+                    .bright_yellow(),
+            ),
+            ParseWarningType::OutputBeforeInput(_) => format!(
+                "{}{}              \n\
+                 In: {}:{}:{}      \n\
+                 {} {}             \n",
+                self.header(),
+                self.r#type,
+                self.token.file,
+                self.token.row,
+                self.token.column,
+                self.token.as_string(PrintStyle::Warning),
+                "the command waits for input first, so this waits until the timeout"
+                    .bright_yellow(),
+            ),
+        }
+    }
+}
+
+/// Notes that a variable has no type because of an earlier error, once
+/// instead of at every type error it would cause
+pub fn unknown_type_note(variable: &Variable, token: &Token) {
+    let message = format!(
+        "The type of `{}` is unknown due to a previous error",
+        variable.name
+    );
+    Diagnostic {
+        code: "",
+        severity: Severity::Note,
+        rendered: format!(
+            "{}{}              \n\
+             In: {}:{}:{}      \n\
+             {}                \n",
+            "note: ".bright_blue(),
+            message,
+            token.file,
+            token.row,
+            token.column,
+            token.as_string(PrintStyle::Help(
+                "type errors involving it are not reported"
+            )),
+        ),
+        message,
+        token: Some(token),
+    }
+    .emit();
+}
+
+pub enum SpawnError {
+    NotFound(String),
+    PermissionDenied(String),
+    Failed { command: String, message: String },
+}
+
+impl std::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SpawnError::NotFound(command) => write!(f, "Failed to find command `{command}`"),
+            SpawnError::PermissionDenied(command) => {
+                write!(f, "Permission denied to run command `{command}`")
+            }
+            SpawnError::Failed { command, message } => {
+                write!(f, "Failed to run command `{command}`: {message}")
+            }
         }
     }
 }
 
-pub enum InterpreterError {
+pub enum InterpreterErrorType {
     TypeCast {
         result: InstructionResult,
         from: Type,
         to: Type,
     },
     TestFailed(String),
+    /// Several expected lines that the output did not match
+    OutputMismatch {
+        message: String,
+        diff: String,
+    },
+    Spawn {
+        test: String,
+        error: SpawnError,
+    },
+    Arithmetic(String),
+    RecursionLimit(usize),
+}
+
+impl InterpreterErrorType {
+    pub fn code(&self) -> &'static str {
+        match &self {
+            InterpreterErrorType::TypeCast { .. } => "E0101",
+            InterpreterErrorType::TestFailed(_) | InterpreterErrorType::OutputMismatch { .. } => {
+                "E0102"
+            }
+            InterpreterErrorType::Spawn { error, .. } => match error {
+                SpawnError::NotFound(_) => "E0103",
+                SpawnError::PermissionDenied(_) => "E0104",
+                SpawnError::Failed { .. } => "E0105",
+            },
+            InterpreterErrorType::Arithmetic(_) => "E0106",
+            InterpreterErrorType::RecursionLimit(_) => "E0107",
+        }
+    }
+}
+
+/// The longest cycle of functions calling each other that is collapsed in
+/// a trace
+const MAX_TRACE_CYCLE: usize = 8;
+
+/// How many times the first `length` lines are repeated right after them
+fn repetitions(lines: &[String], length: usize) -> usize {
+    let mut repeated = 0;
+    while lines.len() >= length * (repeated + 2)
+        && lines[..length] == lines[length * (repeated + 1)..length * (repeated + 2)]
+    {
+        repeated += 1;
+    }
+    repeated
+}
+
+pub struct InterpreterError {
+    pub r#type: InterpreterErrorType,
+    token: Option<Token>,
+    calls: Vec<Call>,
+    test: Option<String>,
+}
+
+impl From<InterpreterErrorType> for InterpreterError {
+    fn from(r#type: InterpreterErrorType) -> InterpreterError {
+        InterpreterError {
+            r#type,
+            token: None,
+            calls: Vec::new(),
+            test: None,
+        }
+    }
 }
 
 impl InterpreterError {
+    pub fn new(r#type: InterpreterErrorType, token: Token) -> InterpreterError {
+        InterpreterError {
+            r#type,
+            token: Some(token),
+            calls: Vec::new(),
+            test: None,
+        }
+    }
+
+    /// Points the error at the instruction it was raised in, unless an inner
+    /// instruction already did, along with the function calls leading there.
+    pub fn locate(mut self, token: &Token, environment: &Environment) -> InterpreterError {
+        if self.token.is_none() {
+            self.token = Some(token.clone());
+            self.calls = environment.calls.clone();
+            self.test = environment.test.clone();
+        }
+        self
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.r#type.code()
+    }
+
+    pub fn exit_code(&self) -> Option<ExitCode> {
+        match &self.r#type {
+            InterpreterErrorType::Spawn { error, .. } => Some(match error {
+                SpawnError::NotFound(_) => ExitCode::ProcessNotFound,
+                SpawnError::PermissionDenied(_) => ExitCode::ProcessPermissionDenied,
+                SpawnError::Failed { .. } => ExitCode::Unknown,
+            }),
+            _ => None,
+        }
+    }
+
+    fn message(&self) -> String {
+        match &self.r#type {
+            InterpreterErrorType::TypeCast { result, from, to } => {
+                format!(
+                    "Type cast error: Failed to cast the `{from}` {} to `{to}`",
+                    result.render()
+                )
+            }
+            InterpreterErrorType::TestFailed(message)
+            | InterpreterErrorType::OutputMismatch { message, .. } => {
+                format!("Test failed: {message}")
+            }
+            InterpreterErrorType::Spawn { test, error } => format!("{error} for test `{test}`"),
+            InterpreterErrorType::Arithmetic(message) => match &self.test {
+                Some(test) => format!("{message} in test `{test}`"),
+                None => message.to_string(),
+            },
+            InterpreterErrorType::RecursionLimit(depth) => {
+                format!("Maximum recursion depth of {depth} nested calls exceeded")
+            }
+        }
+    }
+
+    fn trace(&self, token: &Token) -> String {
+        if self.calls.is_empty() {
+            return String::new();
+        }
+        let mut lines = Vec::new();
+        let mut location = token;
+        for call in self.calls.iter().rev() {
+            lines.push(format!(
+                "at {}:{}:{} in {}()",
+                location.file, location.row, location.column, call.name
+            ));
+            location = &call.token;
+        }
+        lines.push(match &self.test {
+            Some(test) => format!(
+                "at {}:{}:{} in test `{}`",
+                location.file, location.row, location.column, test
+            ),
+            None => format!("at {}:{}:{}", location.file, location.row, location.column),
+        });
+
+        // Recursive calls repeat the same lines, a single line for a function
+        // calling itself and a cycle of lines for functions calling each
+        // other, so runs of them are collapsed
+        let mut trace = String::new();
+        let mut index = 0;
+        while index < lines.len() {
+            let (length, repeated) = (1..=MAX_TRACE_CYCLE)
+                .map(|length| (length, repetitions(&lines[index..], length)))
+                .find(|(_, repeated)| *repeated > 0)
+                .unwrap_or((1, 0));
+            for line in &lines[index..index + length] {
+                trace.push_str(&format!("    {line}\n"));
+            }
+            match (length, repeated) {
+                (_, 0) => (),
+                (1, _) => trace.push_str(&format!("    ... repeated {repeated} more times\n")),
+                _ => trace.push_str(&format!(
+                    "    ... the {length} calls above repeated {repeated} more times\n"
+                )),
+            }
+            index += length * (repeated + 1);
+        }
+        trace
+    }
+
+    fn diff(&self) -> String {
+        match &self.r#type {
+            InterpreterErrorType::OutputMismatch { diff, .. } => format!("\n{diff}"),
+            _ => String::new(),
+        }
+    }
+
     pub fn print(&self) {
-        match &self {
-            InterpreterError::TypeCast { result, from, to } => {
-                eprintln!("Type cast error: Failed to cast `{from} {result}` to `{to}`\n");
+        let header = format!("error[{}]: ", self.code()).bright_red();
+        let message = self.message();
+        match &self.token {
+            Some(token) => Diagnostic {
+                code: self.code(),
+                severity: Severity::Error,
+                rendered: format!(
+                    "{}{}              \n\
+                     In: {}:{}:{}      \n\
+                     {}                \n\
+                     {}",
+                    header,
+                    message,
+                    token.file,
+                    token.row,
+                    token.column,
+                    token.as_string(PrintStyle::Error),
+                    self.trace(token),
+                ) + &self.diff(),
+                message,
+                token: Some(token),
             }
-            InterpreterError::TestFailed(message) => {
-                eprintln!("Test failed: {message}");
+            .emit(),
+            None => Diagnostic {
+                code: self.code(),
+                severity: Severity::Error,
+                rendered: format!("{header}{message}") + &self.diff(),
+                message,
+                token: None,
             }
+            .emit(),
         }
     }
 }
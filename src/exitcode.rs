@@ -1,9 +1,20 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExitCode {
     // Lexer
     SourceFileNotFound = 1,
     FileExtentionNotTesc = 2,
     SourcePermissionDenied = 3,
 
+    // Cli
+    UnknownErrorCode = 11,
+    InvalidConfig = 12,
+    InitFailed = 13,
+    CoverageFailed = 14,
+    PluginFailed = 15,
+    ManifestFailed = 16,
+    ManifestDiffers = 17,
+    HistoryFailed = 18,
+
     // Process
     ProcessNotFound = 21,
     ProcessPermissionDenied = 22,
@@ -0,0 +1,597 @@
+pub const EXPLANATIONS: [(&str, &str); 34] = [
+    (
+        "E0001",
+        "A token was found where it is not allowed.
+
+The parser expected the start of an expression or statement, but found a
+token that cannot appear there.
+
+Erroneous example:
+
+    test(\"./main\") {
+        input(\"a\") )
+    }
+
+Remove the token or replace it with one that is valid at this position.",
+    ),
+    (
+        "E0002",
+        "The file ended in the middle of a construct.
+
+This usually means that a block, parenthesis or statement was never
+finished.
+
+Erroneous example:
+
+    test(\"./main\") {
+        input(\"a\"
+
+Finish the construct, for example by adding the missing `)` and `}`.",
+    ),
+    (
+        "E0003",
+        "A delimiter was opened but never closed.
+
+Every `{` must have a matching `}` and every `(` a matching `)`.
+
+Erroneous example:
+
+    test(\"./main\") {
+        input(\"a\");
+
+Add the missing closing delimiter.",
+    ),
+    (
+        "E0004",
+        "An expression has a different type than the one that was expected.
+
+Every variable, parameter, operator and built-in expects values of specific
+types, and values are never converted implicitly.
+
+Erroneous example:
+
+    const N: int = \"10\";
+
+Change the value, or convert it explicitly with `as`:
+
+    const N: int = \"10\" as int;",
+    ),
+    (
+        "E0005",
+        "A function was called with the wrong number of arguments.
+
+Erroneous example:
+
+    fn add(a: int, b: int): int {
+        a + b
+    }
+
+    test(\"./main\") {
+        add(1);
+    }
+
+Pass exactly one argument for every parameter of the function.",
+    ),
+    (
+        "E0006",
+        "A specific token was expected, but another one was found.
+
+This is most commonly a missing semicolon at the end of a statement, or a
+missing `:` before a type annotation.
+
+Erroneous example:
+
+    test(\"./main\") {
+        input(\"a\")
+        output(\"a\");
+    }
+
+Insert the expected token.",
+    ),
+    (
+        "E0007",
+        "Something other than a test, suite, function or constant was found in
+the global scope.
+
+Only tests, suites, `fn` declarations and `const` declarations may appear at
+the top level of a file.
+
+Erroneous example:
+
+    let x: int = 1;
+
+Move the statement into a test or function, or declare it with `const`.",
+    ),
+    (
+        "E0008",
+        "A value cannot be cast to the requested type.
+
+Only some casts are supported, for example `string as int`,
+`int as string`, `string as bool`, `bool as string`, `string as regex`,
+`float as int` and `int as bool`.
+
+Erroneous example:
+
+    const B: bool = 1.5 as bool;
+
+Use a supported cast, or compare the value instead:
+
+    const B: bool = 1.5 != 0.0;",
+    ),
+    (
+        "E0009",
+        "The regex literal uses syntax that cannot be expanded.
+
+Regex literals are expanded into every string they match, so only a subset
+of the regex syntax is supported: literals, classes, repetitions,
+concatenations, alternations, groups and the anchors `^` and `$`.
+
+Erroneous example:
+
+    test(\"./main\") {
+        for s: string in `\\bword\\b` { }
+    }
+
+Rewrite the regex using the supported syntax, for example `word`.
+
+The same error is reported for a regex that is not valid at all, for example
+one with an unclosed group. The message then says what is wrong with it.",
+    ),
+    (
+        "E0010",
+        "An identifier was used that has not been declared.
+
+Variables must be declared with `let` or `const` before they are used. If a
+variable with a similar name is in scope, it is suggested.
+
+Erroneous example:
+
+    test(\"./main\") {
+        input(name);
+    }
+
+Declare the identifier before using it, or fix the spelling.",
+    ),
+    (
+        "E0011",
+        "A constant was assigned a new value.
+
+Values declared with `const`, and `const` function parameters, can never be
+reassigned.
+
+Erroneous example:
+
+    test(\"./main\") {
+        const X: int = 1;
+        X = 2;
+    }
+
+Declare the variable with `let` if it needs to change.",
+    ),
+    (
+        "E0012",
+        "A variable was declared without a type annotation.
+
+All variables and constants must be declared with an explicit type.
+
+Erroneous example:
+
+    test(\"./main\") {
+        let x = 1;
+    }
+
+Add the type after the name:
+
+    test(\"./main\") {
+        let x: int = 1;
+    }",
+    ),
+    (
+        "E0013",
+        "An unknown attribute was used.
+
+Erroneous example:
+
+    #[alow(magic_literal)]
+    test(\"./main\") { }
+
+Check the spelling of the attribute.",
+    ),
+    (
+        "E0014",
+        "An `allow` attribute names a warning that does not exist.
+
+Erroneous example:
+
+    #[allow(magic_number)]
+    test(\"./main\") { }
+
+Use one of the warning names listed in the documentation, for example
+`magic_literal`.",
+    ),
+    (
+        "E0015",
+        "An attribute was given an argument it does not accept.
+
+Erroneous example:
+
+    #[pty(0, 80)]
+    test(\"./main\") { }
+
+The size of a `pty` must be given as a number of rows and columns between 1
+and 65535:
+
+    #[pty(24, 80)]
+    test(\"./main\") { }",
+    ),
+    (
+        "E0016",
+        "A regex literal expands to more strings than allowed.
+
+Every regex literal is expanded into all of the strings it matches, and the
+number of strings grows quickly with every repetition and class. To keep the
+memory use in check, a regex may expand to at most `--max-combinations`
+strings, 1000000 by default. The message counts the strings up to the part
+of the regex that exceeds the limit, the whole regex creates even more.
+
+Erroneous example:
+
+    test(\"./main\") {
+        for s: string in `[a-z]{5}` { }
+    }
+
+Use a smaller regex, lower `--max-size` to repeat `*` and `+` fewer times, or
+raise `--max-combinations` if the strings are really needed. To test with
+some of the strings only, pick them at random with `sample`:
+
+    test(\"./main\") {
+        for s: string in sample(`[a-z]{5}`, 100) { }
+    }",
+    ),
+    (
+        "E0017",
+        "A function may end without a value of its return type.
+
+There is no `return` keyword, a function returns the value of the last
+statement in its body when that statement has no semicolon. Every path
+through the function must therefore end with a value: an `if` needs an
+`else`, and a `for` may run zero times.
+
+Erroneous example:
+
+    fn sign(n: int): int {
+        if n < 0 {
+            0 - 1
+        }
+    }
+
+Add the missing branch, or end the function with a value:
+
+    fn sign(n: int): int {
+        if n < 0 {
+            0 - 1
+        } else {
+            1
+        }
+    }
+
+A semicolon after the last value discards it, so removing the semicolon
+also fixes this error:
+
+    fn double(n: int): int {
+        n * 2;
+    }",
+    ),
+    (
+        "E0018",
+        "A function was called that has not been declared.
+
+Functions must be declared with `fn`. A global function can be called
+anywhere in the file, a function declared inside a test or function only
+after its declaration in the same block. If a function or built-in with a
+similar name exists, it is suggested.
+
+Erroneous example:
+
+    test(\"./main\") {
+        prnt(\"a\");
+    }
+
+Declare the function, or fix the spelling.",
+    ),
+    (
+        "E0019",
+        "A variable was called as if it was a function.
+
+Erroneous example:
+
+    test(\"./main\") {
+        const COUNT: int = 1;
+        COUNT(2);
+    }
+
+Only functions declared with `fn` and built-ins can be called. Rename the
+variable if it shadows the name of a function.",
+    ),
+    (
+        "E0020",
+        "An assignment was used as the condition of an `if`.
+
+An assignment has no value, so it can never be a condition. This is usually
+a comparison that is missing an `=`.
+
+Erroneous example:
+
+    test(\"./main\") {
+        let x: int = 1;
+        if x = 5 { }
+    }
+
+Use `==` to compare the values:
+
+    if x == 5 { }",
+    ),
+    (
+        "E0021",
+        "A function was declared with the name of a function that is already
+visible.
+
+A function declared inside a test or function is only visible in the block
+it is declared in, but it may not hide another function. A global function
+may not reuse the name of a function declared inside a test or function.
+
+Erroneous example:
+
+    fn double(n: int): int {
+        n * 2
+    }
+
+    test(\"./main\") {
+        fn double(n: int): int {
+            n + n
+        }
+    }
+
+Give the function a different name.",
+    ),
+    (
+        "E0022",
+        "An attribute was written in front of something it does not apply to.
+
+Attributes that change how the program of a test is started, like `pty`,
+`cwd` and `max_runtime`, can only be used in front of a test. `allow` can be
+used in front of a test, a function or a statement.
+
+Erroneous example:
+
+    test(\"./main\") {
+        #[pty]
+        input(\"a\");
+    }
+
+Move the attribute in front of the test:
+
+    #[pty]
+    test(\"./main\") {
+        input(\"a\");
+    }",
+    ),
+    (
+        "E0023",
+        "A global constant depends on its own value.
+
+A global constant can use the constants and functions declared anywhere in
+the file, and is evaluated before the tests run, after the constants it
+uses. A constant that uses itself, directly, through other constants or
+through the functions it calls, can never be evaluated.
+
+Erroneous example:
+
+    const WIDTH: int = HEIGHT * 2;
+    const HEIGHT: int = WIDTH / 2;
+
+Give one of the constants a value that does not depend on the others:
+
+    const WIDTH: int = HEIGHT * 2;
+    const HEIGHT: int = 10;",
+    ),
+    (
+        "E0024",
+        "Something other than a test or suite was found in a suite.
+
+A suite only groups tests under a name. Functions and constants are declared
+at the top level of the file, where every test can use them.
+
+Erroneous example:
+
+    suite \"parser\" {
+        const INPUT: string = \"1 + 2\";
+    }
+
+Move the declaration out of the suite:
+
+    const INPUT: string = \"1 + 2\";
+
+    suite \"parser\" {
+        addition(\"./main\") {
+            input(INPUT);
+        }
+    }",
+    ),
+    (
+        "E0025",
+        "The program of a test was used outside of a test.
+
+`input`, `output` and the other builtins that talk to a program can only be
+used in a test, where the command of the test is running. Global constants
+and the commands of tests are evaluated before any program runs, so they can
+not use these builtins, or call functions that do.
+
+Erroneous example:
+
+    const START: int = elapsed_ms();
+
+Use the builtin in the tests that need it:
+
+    startup(\"./main\") {
+        output(\"Ready\");
+        const START: int = elapsed_ms();
+    }",
+    ),
+    (
+        "E0026",
+        "A character was found that can not start a token.
+
+Outside of strings, regex literals and comments, a script only contains
+letters, digits, `_`, whitespace and the punctuation of the language. `&` and
+`|` are only valid doubled, as `&&` and `||`.
+
+Erroneous example:
+
+    test(\"./main\") {
+        if ready & done {
+            input(\"a\");
+        }
+    }
+
+Remove the character, or write the operator that was meant:
+
+    test(\"./main\") {
+        if ready && done {
+            input(\"a\");
+        }
+    }",
+    ),
+    (
+        "E0027",
+        "An integer literal does not fit in an `int`.
+
+An `int` is a signed 64 bit integer, so an integer literal can be at most
+9223372036854775807.
+
+Erroneous example:
+
+    const LARGE: int = 10000000000000000000;
+
+Use a `float` for numbers this large:
+
+    const LARGE: float = 10000000000000000000.0;",
+    ),
+    (
+        "E0101",
+        "A value could not be converted at runtime.
+
+Casting a string to a number or a bool succeeds only if the string contains
+a valid value of that type.
+
+Erroneous example:
+
+    test(\"./main\") {
+        const N: int = \"ten\" as int;
+    }
+
+Make sure the string holds a value of the target type before casting.
+
+`try_cast` fails the same way when a `float` has a fraction or does not fit
+in an `int`, or when an `int` other than 0 and 1 is cast to a `bool`.",
+    ),
+    (
+        "E0102",
+        "A test failed.
+
+The program under test did not behave as the script expected: it printed a
+different line than the one given to `output`, could not be written to or
+read from, or exited with a non-zero exit code.
+
+Compare the expected output in the message with what the program printed,
+and run with `-v` to see every line sent and received. When `output` is given
+several lines, the message ends with a diff where expected lines start with
+`-` and printed lines start with `+`.",
+    ),
+    (
+        "E0103",
+        "The command of a test could not be found.
+
+The first word of the command must be the path to an executable, or the name
+of an executable in one of the directories in `PATH`.
+
+Erroneous example:
+
+    test(\"./mian\") { }
+
+Check the spelling of the command, and that the program has been built.",
+    ),
+    (
+        "E0104",
+        "The command of a test is not executable.
+
+The file exists, but the current user is not allowed to execute it.
+
+Erroneous example:
+
+    test(\"./main.c\") { }
+
+Run the compiled program instead, or make the file executable with
+`chmod +x`.",
+    ),
+    (
+        "E0105",
+        "The command of a test could not be started.
+
+The program was found, but starting it failed, for example because no
+pseudo-terminal could be opened for a `#[pty]` test. The message contains the
+reason reported by the operating system.",
+    ),
+    (
+        "E0106",
+        "An arithmetic operation failed at runtime.
+
+Integers are 64 bits wide. Dividing an integer by zero, taking the remainder
+of a division by zero, or a result that does not fit in 64 bits stops the
+test instead of producing a wrong value.
+
+Erroneous example:
+
+    test(\"./main\") {
+        let zero: int = 0;
+        print((10 / zero) as string);
+    }
+
+Check the divisor before dividing, or use a `float` where the result may be
+large.",
+    ),
+    (
+        "E0107",
+        "Too many function calls were nested.
+
+A function may call itself, but at most 1000 calls may be active at the same
+time. Deeper recursion usually means that the recursion never stops.
+
+Erroneous example:
+
+    fn count(n: int): int {
+        count(n + 1)
+    }
+
+    test(\"./main\") {
+        print(count(0) as string);
+    }
+
+Make sure every recursive function has a case that does not call itself:
+
+    fn count(n: int): int {
+        if n >= 10 {
+            n
+        } else {
+            count(n + 1)
+        }
+    }",
+    ),
+];
+
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(error_code, _)| error_code.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
@@ -0,0 +1,60 @@
+use crate::instruction;
+use crate::lexer::{BOOLEANS, ITERABLE_ASSIGNMENT, KEYWORDS, TYPES, TYPE_CAST};
+
+use serde_json::{json, Value};
+
+const SCOPE_NAME: &str = "source.tesc";
+
+fn words(words: &[&str]) -> String {
+    format!("\\b({})\\b", words.join("|"))
+}
+
+fn pattern(name: &str, regex: &str) -> Value {
+    json!({ "name": name, "match": regex })
+}
+
+fn region(name: &str, begin: &str, end: &str, escape: Option<&str>) -> Value {
+    let mut region = json!({ "name": name, "begin": begin, "end": end });
+    if let Some(escape) = escape {
+        region["patterns"] = json!([pattern("constant.character.escape.tesc", escape)]);
+    }
+    region
+}
+
+pub fn textmate() -> String {
+    let patterns = [
+        pattern("comment.line.documentation.tesc", "///(?!/).*$"),
+        pattern("comment.line.double-slash.tesc", "//.*$"),
+        region("meta.attribute.tesc", "#\\[", "\\]", None),
+        region("string.quoted.double.tesc", "\"", "\"", Some("\\\\[ntr\"]")),
+        region("string.regexp.tesc", "`", "`", Some("\\\\[\\\\`]")),
+        pattern("keyword.control.tesc", &words(&KEYWORDS)),
+        pattern(
+            "keyword.operator.word.tesc",
+            &words(&[ITERABLE_ASSIGNMENT, TYPE_CAST]),
+        ),
+        pattern("storage.type.tesc", &words(&TYPES)),
+        pattern("constant.language.boolean.tesc", &words(&BOOLEANS)),
+        pattern(
+            "support.function.builtin.tesc",
+            &words(&instruction::builtin_names().collect::<Vec<&str>>()),
+        ),
+        pattern("constant.numeric.tesc", "\\b[0-9]+(\\.[0-9]+)?\\b"),
+        pattern(
+            "entity.name.function.tesc",
+            "\\b[A-Za-z_][A-Za-z0-9_]*(?=\\s*\\()",
+        ),
+        pattern(
+            "keyword.operator.tesc",
+            "==|!=|<=|>=|&&|\\|\\||[-+*/%<>=!~]",
+        ),
+    ];
+
+    let grammar = json!({
+        "name": "test-script",
+        "scopeName": SCOPE_NAME,
+        "fileTypes": ["tesc"],
+        "patterns": patterns,
+    });
+    format!("{:#}\n", grammar)
+}
@@ -0,0 +1,214 @@
+//! The results of earlier runs, recorded with `--history` in an SQLite
+//! database and compared by the `history` subcommand.
+
+// Without the `native` feature there is no SQLite to record or compare with
+#![cfg_attr(not(feature = "native"), allow(dead_code))]
+
+use std::path::Path;
+use std::time::Duration;
+
+pub const DEFAULT_FILE: &str = ".test_script_history.sqlite";
+
+/// A test is slower than in the previous run when it took this many times as
+/// long...
+const SLOWER_FACTOR: f64 = 1.2;
+/// ...and at least this much longer, so tests that take a few milliseconds
+/// are not reported for noise
+const SLOWER_MINIMUM: Duration = Duration::from_millis(50);
+
+/// The outcome and duration of a test in a run
+pub struct TestResult {
+    pub name: String,
+    pub passed: bool,
+    pub duration: Duration,
+}
+
+/// How the latest run of a script differs from the run before it
+pub struct Comparison {
+    pub script: String,
+    pub run: i64,
+    pub previous: i64,
+    pub newly_failing: Vec<String>,
+    pub newly_passing: Vec<String>,
+    /// The tests that slowed down, with their duration in the previous and
+    /// the latest run
+    pub slower: Vec<(String, Duration, Duration)>,
+}
+
+#[cfg(feature = "native")]
+fn open(path: &Path) -> rusqlite::Result<rusqlite::Connection> {
+    let connection = rusqlite::Connection::open(path)?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS runs (
+            id INTEGER PRIMARY KEY,
+            script TEXT NOT NULL,
+            seed INTEGER NOT NULL,
+            started INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS results (
+            run INTEGER NOT NULL REFERENCES runs (id),
+            test TEXT NOT NULL,
+            passed INTEGER NOT NULL,
+            duration REAL NOT NULL
+        );",
+    )?;
+    Ok(connection)
+}
+
+/// Adds a run of `script` to the history at `path`, creating it if needed
+#[cfg(feature = "native")]
+pub fn record(path: &Path, script: &str, seed: u64, results: &[TestResult]) -> Result<(), String> {
+    let record = || -> rusqlite::Result<()> {
+        let mut connection = open(path)?;
+        let transaction = connection.transaction()?;
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        transaction.execute(
+            "INSERT INTO runs (script, seed, started) VALUES (?1, ?2, ?3)",
+            // SQLite has no unsigned integers, the seed is stored with the
+            // same bits
+            (script, seed as i64, started as i64),
+        )?;
+        let run = transaction.last_insert_rowid();
+        for result in results {
+            transaction.execute(
+                "INSERT INTO results (run, test, passed, duration) VALUES (?1, ?2, ?3, ?4)",
+                (
+                    run,
+                    &result.name,
+                    result.passed,
+                    result.duration.as_secs_f64(),
+                ),
+            )?;
+        }
+        transaction.commit()
+    };
+    record().map_err(|e| e.to_string())
+}
+
+/// Compares the latest run in the history at `path` with the run of the same
+/// script before it. `None` when the script has not run twice
+#[cfg(feature = "native")]
+pub fn compare(path: &Path) -> Result<Option<Comparison>, String> {
+    use rusqlite::OptionalExtension;
+
+    if !path.is_file() {
+        return Err(String::from("No such file"));
+    }
+    let compare = || -> rusqlite::Result<Option<Comparison>> {
+        let connection = open(path)?;
+        let Some((run, script)) = connection
+            .query_row(
+                "SELECT id, script FROM runs ORDER BY id DESC LIMIT 1",
+                (),
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let Some(previous) = connection
+            .query_row(
+                "SELECT id FROM runs WHERE script = ?1 AND id < ?2 ORDER BY id DESC LIMIT 1",
+                (&script, run),
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        let mut comparison = Comparison {
+            script,
+            run,
+            previous,
+            newly_failing: Vec::new(),
+            newly_passing: Vec::new(),
+            slower: Vec::new(),
+        };
+        let mut statement = connection.prepare(
+            "SELECT latest.test, previous.passed, latest.passed, previous.duration, latest.duration
+             FROM results AS latest JOIN results AS previous ON latest.test = previous.test
+             WHERE latest.run = ?1 AND previous.run = ?2
+             ORDER BY latest.rowid",
+        )?;
+        let rows = statement.query_map((run, previous), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, bool>(1)?,
+                row.get::<_, bool>(2)?,
+                Duration::from_secs_f64(row.get(3)?),
+                Duration::from_secs_f64(row.get(4)?),
+            ))
+        })?;
+        for row in rows {
+            let (test, passed_before, passed, before, now) = row?;
+            match (passed_before, passed) {
+                (true, false) => comparison.newly_failing.push(test.clone()),
+                (false, true) => comparison.newly_passing.push(test.clone()),
+                _ => (),
+            }
+            if now.as_secs_f64() > before.as_secs_f64() * SLOWER_FACTOR
+                && now > before + SLOWER_MINIMUM
+            {
+                comparison.slower.push((test, before, now));
+            }
+        }
+        Ok(Some(comparison))
+    };
+    compare().map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "native"))]
+pub fn record(
+    _path: &Path,
+    _script: &str,
+    _seed: u64,
+    _results: &[TestResult],
+) -> Result<(), String> {
+    Err(String::from(
+        "the history is not supported in this build of test-script",
+    ))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn compare(_path: &Path) -> Result<Option<Comparison>, String> {
+    Err(String::from(
+        "the history is not supported in this build of test-script",
+    ))
+}
+
+/// Prints the comparison of the latest run in the history at `path` with the
+/// one before it, for the `history` subcommand
+pub fn run(path: &Path) -> Result<(), String> {
+    let Some(comparison) = compare(path)? else {
+        println!("There are not two runs of the same script to compare yet");
+        return Ok(());
+    };
+    println!(
+        "Run {} of `{}` compared with run {}",
+        comparison.run, comparison.script, comparison.previous
+    );
+    let list = |title: &str, tests: &[String]| {
+        if !tests.is_empty() {
+            println!("{}: {}", title, tests.join(", "));
+        }
+    };
+    list("Newly failing", &comparison.newly_failing);
+    list("Newly passing", &comparison.newly_passing);
+    if !comparison.slower.is_empty() {
+        println!("Slower:");
+        for (test, before, now) in &comparison.slower {
+            println!("    {}: {:.2?} -> {:.2?}", test, before, now);
+        }
+    }
+    if comparison.newly_failing.is_empty()
+        && comparison.newly_passing.is_empty()
+        && comparison.slower.is_empty()
+    {
+        println!("No test changed");
+    }
+    Ok(())
+}
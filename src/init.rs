@@ -0,0 +1,84 @@
+use crate::config::CONFIG_FILE;
+
+use colored::Colorize;
+use std::path::Path;
+
+const TESTS_DIRECTORY: &str = "tests";
+const EXAMPLE_FILE: &str = "example.tesc";
+
+const CONFIG_TEMPLATE: &str = "\
+# Configuration for test-script.
+# Options given on the command line take precedence over this file.
+
+# How many times `*` and `+` repeat when a regex literal is expanded
+max_size = 3
+
+# How many strings a regex literal may expand to
+max_combinations = 1000000
+
+# disable_warnings = false
+# disable_style_warnings = false
+# disable_magic_warnings = false
+
+# Warnings that are reported as errors and stop the tests from running
+# deny = [\"no_block\"]
+
+# Commands that read input before they print anything
+# waits_for_input = [\"./repl\"]
+
+# How lines printed by the program are compared with `output`
+# keep_line_endings = false
+# keep_trailing_whitespace = false
+# collapse_whitespace = false
+# strip_ansi = false
+# number_tolerance = 0.001
+
+# The same options for the tests of one suite only
+# [suite.\"parser::errors\"]
+# keep_trailing_whitespace = true
+
+# How much two floats may differ and still be equal with `==`
+# float_epsilon = 0.000001
+
+# Run every program in a temporary directory, on Linux without network
+# sandbox = false
+
+# Shared libraries with builtins of their own, relative to this file
+# plugins = [\"target/release/libchecks.so\"]
+";
+
+const EXAMPLE_TEMPLATE: &str = "\
+// `head -n 1` prints the first line it reads and exits.
+// Replace the command with the command that runs your program.
+// Run this file with `test-script tests/example.tesc`.
+
+const GREETING: string = \"Hello, World!\";
+
+echo_first_line(\"head -n 1\") {
+    input(GREETING);
+    output(GREETING);
+}
+";
+
+fn create_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    if path.exists() {
+        println!(
+            "{} {} (already exists)",
+            "Skipped".bright_yellow(),
+            path.display()
+        );
+        return Ok(());
+    }
+    std::fs::write(path, contents)?;
+    println!("{} {}", "Created".bright_green(), path.display());
+    Ok(())
+}
+
+pub fn run(directory: &Path) -> std::io::Result<()> {
+    let tests = directory.join(TESTS_DIRECTORY);
+    std::fs::create_dir_all(&tests)?;
+
+    create_file(&directory.join(CONFIG_FILE), CONFIG_TEMPLATE)?;
+    create_file(&tests.join(EXAMPLE_FILE), EXAMPLE_TEMPLATE)?;
+    Ok(())
+}
@@ -1,14 +1,32 @@
-use crate::environment::Environment;
-use crate::error::InterpreterError;
+use crate::diagnostic::{output, outputln};
+use crate::environment::{Call, Environment};
+use crate::error::{InterpreterError, InterpreterErrorType};
+use crate::plugin;
 use crate::process::Process;
 use crate::r#type::Type;
+use crate::render;
 use crate::token::{Token, TokenType};
 use crate::variable::Variable;
 
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::time::Instant;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum InstructionResult {
     String(String),
-    Regex(Vec<String>),
+    Regex {
+        pattern: String,
+        values: Rc<[String]>,
+    },
+    /// The strings of every regex in a product, combined while iterating
+    Product(Vec<Rc<[String]>>),
+    /// The cases read from a file with `load_cases`
+    Cases {
+        path: String,
+        cases: Rc<[Vec<String>]>,
+    },
     Int(i64),
     Float(f64),
     Bool(bool),
@@ -19,7 +37,17 @@ impl std::fmt::Display for InstructionResult {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             InstructionResult::String(s) => write!(f, "{}", s),
-            InstructionResult::Regex(s) => write!(f, "{:?}", s),
+            InstructionResult::Regex { values, .. } => write!(f, "{:?}", values),
+            InstructionResult::Product(factors) => write!(
+                f,
+                "{}",
+                factors
+                    .iter()
+                    .map(|values| format!("{:?}", values))
+                    .collect::<Vec<String>>()
+                    .join(" * ")
+            ),
+            InstructionResult::Cases { cases, .. } => write!(f, "{:?}", cases),
             InstructionResult::Int(i) => write!(f, "{}", i),
             InstructionResult::Float(i) => write!(f, "{}", i),
             InstructionResult::Bool(b) => write!(f, "{}", b),
@@ -28,7 +56,39 @@ impl std::fmt::Display for InstructionResult {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Eq)]
+/// Equal floats, or floats at most `epsilon` apart. Infinities are only
+/// equal to themselves and NaN is equal to nothing
+fn float_equal(left: f64, right: f64, epsilon: f64) -> bool {
+    left == right || (left - right).abs() <= epsilon
+}
+
+impl InstructionResult {
+    /// The values a `for` loop over the value assigns, for the values of the
+    /// types in `Type::iterable_inner_type`
+    pub fn elements(&self) -> Box<dyn Iterator<Item = InstructionResult> + '_> {
+        match self {
+            InstructionResult::Regex { values, .. } => {
+                Box::new(values.iter().cloned().map(InstructionResult::String))
+            }
+            InstructionResult::String(value) => Box::new(
+                value
+                    .chars()
+                    .map(|c| InstructionResult::String(c.to_string())),
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The value as it is shown in a failure message
+    pub fn render(&self) -> String {
+        match self {
+            InstructionResult::String(value) => render::string(value),
+            value => format!("`{}`", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BinaryOperator {
     And,
     Or,
@@ -45,58 +105,79 @@ pub enum BinaryOperator {
     Multiplication,
     Division,
     Modulo,
+
+    Matches,
 }
 
 impl std::fmt::Display for BinaryOperator {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                BinaryOperator::And => "&&",
-                BinaryOperator::Or => "||",
-
-                BinaryOperator::Equal => "==",
-                BinaryOperator::NotEqual => "!=",
-                BinaryOperator::GreaterThan => ">",
-                BinaryOperator::GreaterThanOrEqual => ">=",
-                BinaryOperator::LessThan => "<",
-                BinaryOperator::LessThanOrEqual => "<=",
-
-                BinaryOperator::Addition => "+",
-                BinaryOperator::Subtraction => "-",
-                BinaryOperator::Multiplication => "*",
-                BinaryOperator::Division => "/",
-                BinaryOperator::Modulo => "%",
-            }
-        )
+        write!(f, "{}", self.as_str())
     }
 }
 
 impl BinaryOperator {
-    pub fn value(&self) -> Self {
+    pub fn as_str(&self) -> &'static str {
         match self {
-            BinaryOperator::Addition => Self::Addition,
-            BinaryOperator::Subtraction => Self::Addition,
-            BinaryOperator::Multiplication => Self::Multiplication,
-            BinaryOperator::Division => Self::Multiplication,
-            BinaryOperator::Modulo => Self::Multiplication,
-
-            BinaryOperator::Equal => Self::Equal,
-            BinaryOperator::NotEqual => Self::Equal,
-            BinaryOperator::GreaterThan => Self::Equal,
-            BinaryOperator::GreaterThanOrEqual => Self::Equal,
-            BinaryOperator::LessThan => Self::Equal,
-            BinaryOperator::LessThanOrEqual => Self::Equal,
-            BinaryOperator::And => Self::And,
-            BinaryOperator::Or => Self::And,
+            BinaryOperator::And => "&&",
+            BinaryOperator::Or => "||",
+
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::GreaterThanOrEqual => ">=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::LessThanOrEqual => "<=",
+
+            BinaryOperator::Addition => "+",
+            BinaryOperator::Subtraction => "-",
+            BinaryOperator::Multiplication => "*",
+            BinaryOperator::Division => "/",
+            BinaryOperator::Modulo => "%",
+
+            BinaryOperator::Matches => "~",
+        }
+    }
+
+    /// How tightly the operator binds, operators with a higher precedence
+    /// are applied first. Operators with the same precedence are applied
+    /// from left to right. Casts bind tighter than every binary operator
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+
+            BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanOrEqual
+            | BinaryOperator::LessThan
+            | BinaryOperator::LessThanOrEqual
+            | BinaryOperator::Matches => 3,
+
+            BinaryOperator::Addition | BinaryOperator::Subtraction => 4,
+
+            BinaryOperator::Multiplication | BinaryOperator::Division | BinaryOperator::Modulo => 5,
         }
     }
-}
 
-impl std::cmp::Ord for BinaryOperator {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.value().partial_cmp(&other.value()).unwrap()
+    pub fn from_str(value: &str) -> Option<BinaryOperator> {
+        Some(match value {
+            "+" => BinaryOperator::Addition,
+            "-" => BinaryOperator::Subtraction,
+            "*" => BinaryOperator::Multiplication,
+            "/" => BinaryOperator::Division,
+            "%" => BinaryOperator::Modulo,
+            "~" => BinaryOperator::Matches,
+            "==" => BinaryOperator::Equal,
+            "!=" => BinaryOperator::NotEqual,
+            ">" => BinaryOperator::GreaterThan,
+            ">=" => BinaryOperator::GreaterThanOrEqual,
+            "<" => BinaryOperator::LessThan,
+            "<=" => BinaryOperator::LessThanOrEqual,
+            "&&" => BinaryOperator::And,
+            "||" => BinaryOperator::Or,
+            _ => return None,
+        })
     }
 }
 
@@ -123,14 +204,207 @@ impl std::fmt::Display for UnaryOperator {
 pub enum BuiltIn {
     Input(Box<Instruction>),
     Output(Box<Instruction>),
+    OutputFile(Box<Instruction>),
     Print(Box<Instruction>),
     Println(Box<Instruction>),
+    StripAnsi(Box<Instruction>),
+    ElapsedMs(Box<Instruction>),
+    LastResponseMs(Box<Instruction>),
+    Restart(Box<Instruction>),
+    InputRaw(Box<Instruction>),
+    SendEof(Box<Instruction>),
+    SkipLines(Box<Instruction>),
+    SkipUntil(Box<Instruction>),
+    Sample {
+        regex: Box<Instruction>,
+        count: Box<Instruction>,
+        max_size: u32,
+    },
+    Captures {
+        line: Box<Instruction>,
+        regex: Box<Instruction>,
+    },
+    LoadCases(Box<Instruction>),
+    /// A builtin registered with `plugin::register`
+    Custom {
+        name: &'static str,
+        arguments: Vec<Instruction>,
+    },
+}
+
+/// The name, parameters and result of a builtin
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub name: &'static str,
+    pub parameters: Cow<'static, [Type]>,
+    pub returns: Type,
+    /// Whether the last parameter can be given any number of times, also none
+    pub variadic: bool,
+    /// Whether it sends to or reads from the program of the test, which only
+    /// runs in a test
+    pub needs_program: bool,
+}
+
+impl Signature {
+    const fn new(name: &'static str, parameters: &'static [Type], returns: Type) -> Self {
+        Self {
+            name,
+            parameters: Cow::Borrowed(parameters),
+            returns,
+            variadic: false,
+            needs_program: false,
+        }
+    }
+
+    const fn with_program(mut self) -> Self {
+        self.needs_program = true;
+        self
+    }
+}
+
+/// The signature of every builtin of the language, which the type checker
+/// checks every call against. A builtin is added by adding it here and to
+/// `BuiltIn`. `try_cast` is not here, since it takes a type and is parsed as a
+/// type cast
+pub static SIGNATURES: [Signature; 16] = [
+    Signature::new("input", &[Type::String], Type::None).with_program(),
+    Signature::new("output", &[Type::String], Type::None).with_program(),
+    Signature::new("output_file", &[Type::String], Type::None).with_program(),
+    Signature::new("print", &[Type::String], Type::None),
+    Signature::new("println", &[Type::String], Type::None),
+    Signature::new("strip_ansi", &[Type::String], Type::String),
+    Signature::new("elapsed_ms", &[], Type::Int).with_program(),
+    Signature::new("last_response_ms", &[], Type::Int).with_program(),
+    Signature::new("restart", &[], Type::None).with_program(),
+    Signature::new("input_raw", &[Type::String], Type::None).with_program(),
+    Signature::new("send_eof", &[], Type::None).with_program(),
+    Signature::new("skip_lines", &[Type::Int], Type::None).with_program(),
+    Signature::new("skip_until", &[Type::Regex], Type::None).with_program(),
+    Signature::new("sample", &[Type::Regex, Type::Int], Type::Regex),
+    Signature::new("captures", &[Type::String, Type::Regex], Type::Regex),
+    Signature::new("load_cases", &[Type::String], Type::Cases),
+];
+
+pub const TRY_CAST: &str = "try_cast";
+
+/// The name of every builtin of the language, without the registered ones
+pub fn builtin_names<'a>() -> impl Iterator<Item = &'a str> {
+    SIGNATURES
+        .iter()
+        .map(|signature| signature.name)
+        .chain([TRY_CAST])
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    builtin_names().any(|builtin| builtin == name)
+}
+
+impl BuiltIn {
+    /// The signature in `SIGNATURES`, or the one a registered builtin was
+    /// registered with
+    pub fn signature(&self) -> Signature {
+        match self {
+            BuiltIn::Custom { name, .. } => {
+                let builtin = plugin::get(name).expect("registered builtins are never removed");
+                Signature {
+                    name,
+                    parameters: builtin
+                        .parameters()
+                        .iter()
+                        .map(|parameter| (*parameter).into())
+                        .collect(),
+                    returns: builtin.returns().into(),
+                    variadic: false,
+                    needs_program: false,
+                }
+            }
+            builtin => SIGNATURES
+                .iter()
+                .find(|signature| signature.name == builtin.name())
+                .expect("every builtin has a signature")
+                .clone(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuiltIn::Input(_) => "input",
+            BuiltIn::Output(_) => "output",
+            BuiltIn::OutputFile(_) => "output_file",
+            BuiltIn::Print(_) => "print",
+            BuiltIn::Println(_) => "println",
+            BuiltIn::StripAnsi(_) => "strip_ansi",
+            BuiltIn::ElapsedMs(_) => "elapsed_ms",
+            BuiltIn::LastResponseMs(_) => "last_response_ms",
+            BuiltIn::Restart(_) => "restart",
+            BuiltIn::InputRaw(_) => "input_raw",
+            BuiltIn::SendEof(_) => "send_eof",
+            BuiltIn::SkipLines(_) => "skip_lines",
+            BuiltIn::SkipUntil(_) => "skip_until",
+            BuiltIn::Sample { .. } => "sample",
+            BuiltIn::Captures { .. } => "captures",
+            BuiltIn::LoadCases(_) => "load_cases",
+            BuiltIn::Custom { name, .. } => name,
+        }
+    }
+
+    pub fn arguments(&self) -> Vec<&Instruction> {
+        match self {
+            BuiltIn::Input(argument)
+            | BuiltIn::Output(argument)
+            | BuiltIn::Print(argument)
+            | BuiltIn::Println(argument)
+            | BuiltIn::StripAnsi(argument)
+            | BuiltIn::ElapsedMs(argument)
+            | BuiltIn::LastResponseMs(argument)
+            | BuiltIn::Restart(argument)
+            | BuiltIn::InputRaw(argument)
+            | BuiltIn::SendEof(argument)
+            | BuiltIn::SkipLines(argument)
+            | BuiltIn::OutputFile(argument)
+            | BuiltIn::SkipUntil(argument)
+            | BuiltIn::LoadCases(argument) => vec![argument],
+            BuiltIn::Sample { regex, count, .. } => vec![regex, count],
+            BuiltIn::Captures { line, regex } => vec![line, regex],
+            BuiltIn::Custom { arguments, .. } => arguments.iter().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    pub arguments: Vec<Token>,
+    pub token: Token,
+}
+
+/// What an attribute is written in front of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttributeTarget {
+    Test,
+    Suite,
+    Function,
+    Statement,
+}
+
+impl std::fmt::Display for AttributeTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AttributeTarget::Test => write!(f, "a test"),
+            AttributeTarget::Suite => write!(f, "a suite"),
+            AttributeTarget::Function => write!(f, "a function"),
+            AttributeTarget::Statement => write!(f, "a statement"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Instruction {
     pub r#type: InstructionType,
     pub token: Token,
+    /// The whole expression, from its first to its last token
+    pub span: Token,
+    pub attributes: Vec<Attribute>,
 }
 
 impl std::fmt::Display for Instruction {
@@ -140,7 +414,7 @@ impl std::fmt::Display for Instruction {
             "{}",
             match self.r#type {
                 InstructionType::StringLiteral(ref value) => value.clone(),
-                InstructionType::RegexLiteral(ref value) => format!("{:?}", value),
+                InstructionType::RegexLiteral { ref values, .. } => format!("{:?}", values),
                 InstructionType::IntegerLiteral(ref value) => value.to_string(),
                 InstructionType::FloatLiteral(ref value) => value.to_string(),
                 InstructionType::BooleanLiteral(ref value) => value.to_string(),
@@ -150,9 +424,56 @@ impl std::fmt::Display for Instruction {
                     BuiltIn::Output(ref instruction) => format!("output({})", instruction),
                     BuiltIn::Print(ref instruction) => format!("print({})", instruction),
                     BuiltIn::Println(ref instruction) => format!("println({})", instruction),
+                    BuiltIn::StripAnsi(ref instruction) => {
+                        format!("strip_ansi({})", instruction)
+                    }
+                    BuiltIn::ElapsedMs(ref instruction) => {
+                        format!("elapsed_ms({})", instruction)
+                    }
+                    BuiltIn::LastResponseMs(ref instruction) => {
+                        format!("last_response_ms({})", instruction)
+                    }
+                    BuiltIn::Restart(ref instruction) => format!("restart({})", instruction),
+                    BuiltIn::InputRaw(ref instruction) => format!("input_raw({})", instruction),
+                    BuiltIn::SendEof(ref instruction) => format!("send_eof({})", instruction),
+                    BuiltIn::SkipLines(ref instruction) => {
+                        format!("skip_lines({})", instruction)
+                    }
+                    BuiltIn::OutputFile(ref instruction) => {
+                        format!("output_file({})", instruction)
+                    }
+                    BuiltIn::SkipUntil(ref instruction) => {
+                        format!("skip_until({})", instruction)
+                    }
+                    BuiltIn::Sample {
+                        ref regex,
+                        ref count,
+                        ..
+                    } => format!("sample({}, {})", regex, count),
+                    BuiltIn::Captures {
+                        ref line,
+                        ref regex,
+                    } => format!("captures({}, {})", line, regex),
+                    BuiltIn::LoadCases(ref instruction) => {
+                        format!("load_cases({})", instruction)
+                    }
+                    BuiltIn::Custom {
+                        ref name,
+                        ref arguments,
+                    } => format!(
+                        "{}({})",
+                        name,
+                        arguments
+                            .iter()
+                            .map(|argument| argument.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ),
                 },
 
-                InstructionType::Block(ref instructions) => {
+                InstructionType::Block {
+                    ref instructions, ..
+                } => {
                     let mut result = String::new();
                     for instruction in instructions {
                         result.push_str(&format!("{}\n", instruction));
@@ -161,8 +482,22 @@ impl std::fmt::Display for Instruction {
                 }
                 InstructionType::Paren(ref instruction) => format!("({})", instruction),
 
-                InstructionType::Test(ref left, ref operator, ref right) => {
-                    format!("{} {} {}", left, operator, right)
+                InstructionType::Test {
+                    ref name,
+                    ref command,
+                    ref instruction,
+                    ..
+                } => format!("{}({}) {}", name, command, instruction),
+                InstructionType::Suite {
+                    ref name,
+                    ref instructions,
+                } => {
+                    let mut result = format!("suite \"{}\" {{\n", name);
+                    for instruction in instructions {
+                        result.push_str(&format!("{}\n", instruction));
+                    }
+                    result.push('}');
+                    result
                 }
 
                 InstructionType::Function {
@@ -201,12 +536,21 @@ impl std::fmt::Display for Instruction {
                     ..
                 } => format!("{} = {}", variable, instruction),
                 InstructionType::IterableAssignment {
-                    ref variable,
+                    ref variables,
                     ref instruction,
                     ..
-                } => {
-                    format!("{} in {}", variable, instruction)
-                }
+                } => match variables.as_slice() {
+                    [variable] => format!("{} in {}", variable, instruction),
+                    variables => format!(
+                        "({}) in {}",
+                        variables
+                            .iter()
+                            .map(|variable| variable.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", "),
+                        instruction
+                    ),
+                },
                 InstructionType::Variable(ref variable) => variable.to_string(),
                 InstructionType::FunctionCall {
                     ref name,
@@ -236,7 +580,13 @@ impl std::fmt::Display for Instruction {
                 InstructionType::TypeCast {
                     ref instruction,
                     ref r#type,
+                    checked: false,
                 } => format!("{} as {}", instruction, r#type),
+                InstructionType::TypeCast {
+                    ref instruction,
+                    ref r#type,
+                    checked: true,
+                } => format!("try_cast({}, {})", instruction, r#type),
 
                 InstructionType::None => String::new(),
             }
@@ -245,26 +595,48 @@ impl std::fmt::Display for Instruction {
 }
 
 impl Instruction {
-    pub const NONE: Instruction = Instruction {
-        r#type: InstructionType::None,
-        token: Token {
-            r#type: TokenType::None,
-            file: String::new(),
-            row: 0,
-            column: 0,
-
-            line: String::new(),
-            last_token: None,
-        },
-    };
+    pub fn none() -> Instruction {
+        Instruction::new(InstructionType::None, Token::none())
+    }
 
     pub fn new(r#type: InstructionType, token: Token) -> Self {
-        Self { r#type, token }
+        Self {
+            r#type,
+            span: token.clone(),
+            token,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn attribute_target(&self) -> AttributeTarget {
+        match self.r#type {
+            InstructionType::Test { .. } => AttributeTarget::Test,
+            InstructionType::Suite { .. } => AttributeTarget::Suite,
+            InstructionType::Function { .. } => AttributeTarget::Function,
+            _ => AttributeTarget::Statement,
+        }
+    }
+
+    pub fn documentation(&self) -> String {
+        self.attributes
+            .iter()
+            .filter(|attribute| attribute.name == "doc")
+            .flat_map(|attribute| &attribute.arguments)
+            .filter_map(|argument| match &argument.r#type {
+                TokenType::DocComment { value } => {
+                    Some(value.strip_prefix(' ').unwrap_or(value).trim_end())
+                }
+                _ => None,
+            })
+            .collect::<Vec<&str>>()
+            .join("\n")
     }
 
     pub fn inner_most(&self) -> &Self {
         match &self.r#type {
-            InstructionType::Block(ref instructions) => {
+            InstructionType::Block {
+                ref instructions, ..
+            } => {
                 if instructions.is_empty() {
                     self
                 } else {
@@ -276,21 +648,238 @@ impl Instruction {
         }
     }
 
+    /// Whether every path through the instruction ends with a value. A `for`
+    /// may run zero times and an `if` without `else` may be skipped, so
+    /// neither yields a value on its own.
+    pub fn always_yields(&self) -> bool {
+        match &self.r#type {
+            InstructionType::Block { instructions, tail } => {
+                *tail
+                    && instructions
+                        .last()
+                        .is_some_and(|instruction| instruction.always_yields())
+            }
+            InstructionType::Paren(instruction) => instruction.always_yields(),
+            InstructionType::Conditional {
+                instruction,
+                r#else,
+                ..
+            } => instruction.always_yields() && r#else.always_yields(),
+            InstructionType::For { .. } | InstructionType::None => false,
+            _ => true,
+        }
+    }
+
+    /// The value at the end of the instruction that a semicolon discards,
+    /// which would be the value of the instruction without the semicolon
+    pub fn discarded_value(&self) -> Option<&Instruction> {
+        match &self.r#type {
+            InstructionType::Block { instructions, tail } => match instructions.last() {
+                Some(last) if *tail => last.discarded_value(),
+                Some(last) => match last.r#type {
+                    InstructionType::Assignment { .. }
+                    | InstructionType::IterableAssignment { .. }
+                    | InstructionType::For { .. }
+                    | InstructionType::Function { .. }
+                    | InstructionType::None => None,
+                    _ => Some(last),
+                },
+                None => None,
+            },
+            InstructionType::Paren(instruction) => instruction.discarded_value(),
+            // Without an `else` there is no value to keep either way
+            InstructionType::Conditional {
+                instruction,
+                r#else,
+                ..
+            } if r#else.r#type != InstructionType::None => instruction
+                .discarded_value()
+                .or_else(|| r#else.discarded_value()),
+            _ => None,
+        }
+    }
+
+    /// The functions that are called on every path through the instruction.
+    /// Only the condition of an `if` and the iterable of a `for` are certain
+    /// to run, and only the left side of `&&` and `||`.
+    pub fn always_calls(&self) -> HashSet<&str> {
+        match &self.r#type {
+            InstructionType::Block { instructions, .. } => instructions
+                .iter()
+                .flat_map(|instruction| instruction.always_calls())
+                .collect(),
+            InstructionType::Conditional {
+                condition,
+                instruction,
+                r#else,
+            } => {
+                let branch = instruction.always_calls();
+                let mut calls = condition.always_calls();
+                calls.extend(
+                    r#else
+                        .always_calls()
+                        .into_iter()
+                        .filter(|name| branch.contains(name)),
+                );
+                calls
+            }
+            InstructionType::FunctionCall { name, arguments } => {
+                let mut calls = arguments
+                    .iter()
+                    .flat_map(|argument| argument.always_calls())
+                    .collect::<HashSet<&str>>();
+                calls.insert(name);
+                calls
+            }
+            InstructionType::BinaryOperation {
+                operator: BinaryOperator::And | BinaryOperator::Or,
+                left,
+                ..
+            } => left.always_calls(),
+            InstructionType::BinaryOperation { left, right, .. } => {
+                let mut calls = left.always_calls();
+                calls.extend(right.always_calls());
+                calls
+            }
+            InstructionType::BuiltIn(builtin) => builtin
+                .arguments()
+                .into_iter()
+                .flat_map(|argument| argument.always_calls())
+                .collect(),
+            InstructionType::Paren(instruction)
+            | InstructionType::For {
+                assignment: instruction,
+                ..
+            }
+            | InstructionType::Assignment { instruction, .. }
+            | InstructionType::IterableAssignment { instruction, .. }
+            | InstructionType::UnaryOperation { instruction, .. }
+            | InstructionType::TypeCast { instruction, .. } => instruction.always_calls(),
+            _ => HashSet::new(),
+        }
+    }
+
+    /// The variables read and the functions called anywhere in the
+    /// instruction, including in the functions declared inside it
+    pub fn references(&self) -> (Vec<&Variable>, HashSet<&str>) {
+        let mut variables = Vec::new();
+        let mut calls = HashSet::new();
+        let mut pending = vec![self];
+        while let Some(instruction) = pending.pop() {
+            match &instruction.r#type {
+                InstructionType::Variable(variable) => variables.push(variable),
+                InstructionType::FunctionCall { name, .. } => {
+                    calls.insert(name.as_str());
+                }
+                _ => (),
+            }
+            pending.extend(instruction.children());
+        }
+        (variables, calls)
+    }
+
+    /// The instructions directly inside the instruction
+    pub fn children(&self) -> Vec<&Instruction> {
+        match &self.r#type {
+            InstructionType::FunctionCall { arguments, .. } => arguments.iter().collect(),
+            InstructionType::Block { instructions, .. }
+            | InstructionType::Suite { instructions, .. } => instructions.iter().collect(),
+            InstructionType::Conditional {
+                condition,
+                instruction,
+                r#else,
+            } => vec![condition, instruction, r#else],
+            InstructionType::BinaryOperation { left, right, .. } => vec![left, right],
+            InstructionType::Test {
+                command,
+                instruction,
+                ..
+            } => vec![command, instruction],
+            InstructionType::For {
+                assignment,
+                instruction,
+            } => vec![assignment, instruction],
+            InstructionType::BuiltIn(builtin) => builtin.arguments(),
+            InstructionType::Paren(instruction)
+            | InstructionType::Function { instruction, .. }
+            | InstructionType::Assignment { instruction, .. }
+            | InstructionType::IterableAssignment { instruction, .. }
+            | InstructionType::UnaryOperation { instruction, .. }
+            | InstructionType::TypeCast { instruction, .. } => vec![instruction],
+            _ => Vec::new(),
+        }
+    }
+
     pub fn interpret(
         &self,
         environment: &mut Environment,
         process: &mut Option<&mut Process>,
+    ) -> Result<InstructionResult, InterpreterError> {
+        let start = environment.profile.as_mut().map(|profile| {
+            profile.enter();
+            Instant::now()
+        });
+        let result = self
+            .interpret_instruction(environment, process)
+            .map_err(|error| error.locate(&self.token, environment));
+        if let (Some(start), Some(profile)) = (start, &mut environment.profile) {
+            let (kind, detail) = self.profile_kind();
+            profile.exit(kind, detail, start.elapsed());
+        }
+        result
+    }
+
+    /// What the instruction is counted as with `--profile-interpreter`
+    fn profile_kind(&self) -> (&'static str, &'static str) {
+        match &self.r#type {
+            InstructionType::StringLiteral(_)
+            | InstructionType::RegexLiteral { .. }
+            | InstructionType::IntegerLiteral(_)
+            | InstructionType::FloatLiteral(_)
+            | InstructionType::BooleanLiteral(_) => ("literal", ""),
+            InstructionType::BuiltIn(built_in) => ("builtin", built_in.name()),
+            InstructionType::Block { .. } => ("block", ""),
+            InstructionType::Paren(_) => ("parenthesis", ""),
+            InstructionType::Test { .. } => ("test", ""),
+            InstructionType::Suite { .. } => ("suite", ""),
+            InstructionType::Function { .. } => ("function definition", ""),
+            InstructionType::For { .. } => ("for loop", ""),
+            InstructionType::Conditional { .. } => ("if", ""),
+            InstructionType::Assignment { .. } => ("assignment", ""),
+            InstructionType::IterableAssignment { .. } => ("for loop iterable", ""),
+            InstructionType::Variable(_) => ("variable", ""),
+            InstructionType::FunctionCall { .. } => ("function call", ""),
+            InstructionType::UnaryOperation { operator, .. } => match operator {
+                UnaryOperator::Not => ("unary operation", "!"),
+                UnaryOperator::Negation => ("unary operation", "-"),
+            },
+            InstructionType::BinaryOperation { operator, .. } => {
+                ("binary operation", operator.as_str())
+            }
+            InstructionType::TypeCast { checked: false, .. } => ("type cast", ""),
+            InstructionType::TypeCast { checked: true, .. } => ("builtin", "try_cast"),
+            InstructionType::None => ("empty instruction", ""),
+        }
+    }
+
+    fn interpret_instruction(
+        &self,
+        environment: &mut Environment,
+        process: &mut Option<&mut Process>,
     ) -> Result<InstructionResult, InterpreterError> {
         Ok(match &self.r#type {
             InstructionType::StringLiteral(value) => InstructionResult::String(value.to_string()),
-            InstructionType::RegexLiteral(value) => InstructionResult::Regex(value.to_vec()),
+            InstructionType::RegexLiteral { pattern, values } => InstructionResult::Regex {
+                pattern: pattern.clone(),
+                values: Rc::clone(values),
+            },
             InstructionType::IntegerLiteral(value) => InstructionResult::Int(*value),
             InstructionType::FloatLiteral(value) => InstructionResult::Float(*value),
             InstructionType::BooleanLiteral(value) => InstructionResult::Bool(*value),
 
             InstructionType::BuiltIn(_) => self.interpret_builtin(environment, process)?,
 
-            InstructionType::Block(_) => self.interpret_block(environment, process)?,
+            InstructionType::Block { .. } => self.interpret_block(environment, process)?,
             InstructionType::Paren(instruction) => instruction.interpret(environment, process)?,
 
             InstructionType::For { .. } => self.interpret_for(environment, process)?,
@@ -338,11 +927,223 @@ impl Instruction {
             _ => unreachable!(),
         };
 
+        if let BuiltIn::ElapsedMs(_) = builtin {
+            return match process {
+                Some(process) => Ok(InstructionResult::Int(process.elapsed().as_millis() as i64)),
+                None => Err(InterpreterErrorType::TestFailed(
+                    "No process to measure the runtime of".to_string(),
+                )
+                .into()),
+            };
+        }
+
+        if let BuiltIn::LastResponseMs(_) = builtin {
+            return match process {
+                Some(process) => match process.last_response() {
+                    Some(response) => Ok(InstructionResult::Int(response.as_millis() as i64)),
+                    None => Err(InterpreterErrorType::TestFailed(
+                        "No output was read since the last input".to_string(),
+                    )
+                    .into()),
+                },
+                None => Err(InterpreterErrorType::TestFailed(
+                    "No process to measure the response time of".to_string(),
+                )
+                .into()),
+            };
+        }
+
+        if let BuiltIn::Restart(_) = builtin {
+            return match process {
+                Some(process) => process.restart().map(|_| InstructionResult::None),
+                None => Err(
+                    InterpreterErrorType::TestFailed("No process to restart".to_string()).into(),
+                ),
+            };
+        }
+
+        if let BuiltIn::Sample {
+            regex,
+            count,
+            max_size,
+        } = builtin
+        {
+            let pattern = match regex.interpret(environment, process)? {
+                InstructionResult::Regex { pattern, .. } => pattern,
+                _ => unreachable!(),
+            };
+            let count = match count.interpret(environment, process)? {
+                InstructionResult::Int(count) => usize::try_from(count).map_err(|_| {
+                    InterpreterErrorType::TestFailed(format!(
+                        "Can not take {} samples of `{}`",
+                        count, pattern
+                    ))
+                })?,
+                _ => unreachable!(),
+            };
+            return Ok(InstructionResult::Regex {
+                values: crate::regex::sample(&pattern, count, *max_size).into(),
+                pattern,
+            });
+        }
+
+        if let BuiltIn::Captures { line, regex } = builtin {
+            let line = match line.interpret(environment, process)? {
+                InstructionResult::String(line) => line,
+                _ => unreachable!(),
+            };
+            let pattern = match regex.interpret(environment, process)? {
+                InstructionResult::Regex { pattern, .. } => pattern,
+                _ => unreachable!(),
+            };
+            return crate::regex::captures(&line, &pattern).map_err(|e| {
+                InterpreterErrorType::TestFailed(format!(
+                    "Failed to compile regex `{}`: {}",
+                    pattern, e
+                ))
+                .into()
+            });
+        }
+
+        if let BuiltIn::LoadCases(path) = builtin {
+            let path = match path.interpret(environment, process)? {
+                InstructionResult::String(path) => path,
+                _ => unreachable!(),
+            };
+            return match crate::cases::load(std::path::Path::new(&path)) {
+                Ok(cases) => Ok(InstructionResult::Cases {
+                    path,
+                    cases: cases.into(),
+                }),
+                Err(e) => Err(InterpreterErrorType::TestFailed(format!(
+                    "Failed to load cases from `{}`: {}",
+                    path, e
+                ))
+                .into()),
+            };
+        }
+
+        if let BuiltIn::SkipLines(count) = builtin {
+            let count = match count.interpret(environment, process)? {
+                InstructionResult::Int(count) => usize::try_from(count).map_err(|_| {
+                    InterpreterErrorType::TestFailed(format!("Can not skip {} lines", count))
+                })?,
+                _ => unreachable!(),
+            };
+            return match process {
+                Some(process) => process.skip_lines(count).map(|_| InstructionResult::None),
+                None => Err(InterpreterErrorType::TestFailed(
+                    "No process to read output from".to_string(),
+                )
+                .into()),
+            };
+        }
+
+        if let BuiltIn::OutputFile(path) = builtin {
+            let path = match path.interpret(environment, process)? {
+                InstructionResult::String(path) => path,
+                _ => unreachable!(),
+            };
+            let Some(process) = process else {
+                return Err(InterpreterErrorType::TestFailed(
+                    "No process to read output from".to_string(),
+                )
+                .into());
+            };
+            if environment.update_expected {
+                let mut contents = process.read_remaining().join("\n");
+                if !contents.is_empty() {
+                    contents.push('\n');
+                }
+                let path = std::path::Path::new(&path);
+                return path
+                    .parent()
+                    .map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|_| std::fs::write(path, contents))
+                    .map(|_| InstructionResult::None)
+                    .map_err(|e| {
+                        InterpreterErrorType::TestFailed(format!(
+                            "Failed to write the expected output to `{}`: {}",
+                            path.display(),
+                            e
+                        ))
+                        .into()
+                    });
+            }
+            return match std::fs::read_to_string(&path) {
+                Ok(expected) => process.read_line(expected).map(|_| InstructionResult::None),
+                Err(e) => Err(InterpreterErrorType::TestFailed(format!(
+                    "Failed to read the expected output from `{}`: {}",
+                    path, e
+                ))
+                .into()),
+            };
+        }
+
+        if let BuiltIn::SkipUntil(regex) = builtin {
+            let pattern = match regex.interpret(environment, process)? {
+                InstructionResult::Regex { pattern, .. } => pattern,
+                _ => unreachable!(),
+            };
+            let regex = regex::Regex::new(&pattern).map_err(|e| {
+                InterpreterErrorType::TestFailed(format!(
+                    "Failed to compile regex `{}`: {}",
+                    pattern, e
+                ))
+            })?;
+            return match process {
+                Some(process) => process.skip_until(&regex).map(|_| InstructionResult::None),
+                None => Err(InterpreterErrorType::TestFailed(
+                    "No process to read output from".to_string(),
+                )
+                .into()),
+            };
+        }
+
+        if let BuiltIn::Custom { name, arguments } = builtin {
+            let mut values = Vec::new();
+            for argument in arguments {
+                values.push(argument.interpret(environment, process)?.into());
+            }
+            let builtin = plugin::get(name).expect("registered builtins are never removed");
+            return match builtin.call(&values) {
+                Ok(value) => Ok(value.into()),
+                Err(e) => Err(InterpreterErrorType::TestFailed(format!(
+                    "`{}` failed: {}",
+                    name, e
+                ))
+                .into()),
+            };
+        }
+
+        if let BuiltIn::SendEof(_) = builtin {
+            return match process {
+                Some(process) => process.send_eof().map(|_| InstructionResult::None),
+                None => Err(InterpreterErrorType::TestFailed(
+                    "No process to send input to".to_string(),
+                )
+                .into()),
+            };
+        }
+
         let value = match builtin {
             BuiltIn::Input(instruction) => instruction.interpret(environment, process)?,
             BuiltIn::Output(instruction) => instruction.interpret(environment, process)?,
             BuiltIn::Print(instruction) => instruction.interpret(environment, process)?,
             BuiltIn::Println(instruction) => instruction.interpret(environment, process)?,
+            BuiltIn::StripAnsi(instruction) => instruction.interpret(environment, process)?,
+            BuiltIn::InputRaw(instruction) => instruction.interpret(environment, process)?,
+            BuiltIn::ElapsedMs(_)
+            | BuiltIn::LastResponseMs(_)
+            | BuiltIn::Restart(_)
+            | BuiltIn::SendEof(_)
+            | BuiltIn::SkipLines(_)
+            | BuiltIn::SkipUntil(_)
+            | BuiltIn::OutputFile(_)
+            | BuiltIn::Sample { .. }
+            | BuiltIn::Captures { .. }
+            | BuiltIn::LoadCases(_)
+            | BuiltIn::Custom { .. } => unreachable!(),
         };
 
         let value = match value {
@@ -350,6 +1151,21 @@ impl Instruction {
             _ => unreachable!(),
         };
 
+        if let BuiltIn::StripAnsi(_) = builtin {
+            return Ok(InstructionResult::String(strip_ansi_escapes::strip_str(
+                &value,
+            )));
+        }
+
+        // Printing does not need a program, so it also works in constants
+        if let BuiltIn::Print(_) | BuiltIn::Println(_) = builtin {
+            match builtin {
+                BuiltIn::Print(_) => output!("{}", value),
+                _ => outputln!("{}", value),
+            }
+            return Ok(InstructionResult::None);
+        }
+
         match process {
             Some(ref mut process) => match builtin {
                 BuiltIn::Input(_) => match process.send(&value) {
@@ -358,19 +1174,33 @@ impl Instruction {
                         return Err(e);
                     }
                 },
+                BuiltIn::InputRaw(_) => process.send_raw(&value)?,
                 BuiltIn::Output(_) => match process.read_line(value) {
                     Ok(()) => (),
                     Err(e) => {
                         return Err(e);
                     }
                 },
-                BuiltIn::Print(_) => print!("{}", value),
-                BuiltIn::Println(_) => println!("{}", value),
+                BuiltIn::Print(_)
+                | BuiltIn::Println(_)
+                | BuiltIn::StripAnsi(_)
+                | BuiltIn::ElapsedMs(_)
+                | BuiltIn::LastResponseMs(_)
+                | BuiltIn::Restart(_)
+                | BuiltIn::SendEof(_)
+                | BuiltIn::SkipLines(_)
+                | BuiltIn::SkipUntil(_)
+                | BuiltIn::OutputFile(_)
+                | BuiltIn::Sample { .. }
+                | BuiltIn::Captures { .. }
+                | BuiltIn::LoadCases(_)
+                | BuiltIn::Custom { .. } => unreachable!(),
             },
             None => {
-                return Err(InterpreterError::TestFailed(
+                return Err(InterpreterErrorType::TestFailed(
                     "No process to send input to".to_string(),
-                ));
+                )
+                .into());
             }
         };
 
@@ -383,13 +1213,16 @@ impl Instruction {
         process: &mut Option<&mut Process>,
     ) -> Result<InstructionResult, InterpreterError> {
         environment.add_scope();
-        let instructions = match &self.r#type {
-            InstructionType::Block(instructions) => instructions,
+        let (instructions, tail) = match &self.r#type {
+            InstructionType::Block { instructions, tail } => (instructions, *tail),
             _ => unreachable!(),
         };
 
         let mut result = InstructionResult::None;
         for instruction in instructions {
+            if let Some(coverage) = &mut environment.coverage {
+                coverage.statement(instruction, environment.test.as_deref());
+            }
             result = match instruction.interpret(environment, process) {
                 Ok(value) => value,
                 Err(e) => {
@@ -399,7 +1232,10 @@ impl Instruction {
             };
         }
         environment.remove_scope();
-        Ok(result)
+        match tail {
+            true => Ok(result),
+            false => Ok(InstructionResult::None),
+        }
     }
 
     fn interpret_for(
@@ -418,9 +1254,9 @@ impl Instruction {
             }
         };
         environment.add_scope();
-        let (assignment_var, assignment_values) = match &assignment.r#type {
-            InstructionType::IterableAssignment { variable, .. } => (
-                variable,
+        let (variables, assignment_values) = match &assignment.r#type {
+            InstructionType::IterableAssignment { variables, .. } => (
+                variables,
                 match assignment.interpret(environment, process) {
                     Ok(value) => value,
                     Err(e) => {
@@ -434,12 +1270,18 @@ impl Instruction {
             }
         };
         match assignment_values {
-            InstructionResult::Regex(values) => {
-                for value in values {
-                    environment.insert(
-                        assignment_var.name.clone(),
-                        InstructionResult::String(value),
-                    );
+            InstructionResult::Product(factors) => {
+                // Counts through every combination like an odometer, so the
+                // product is never created as a whole
+                let mut indices = vec![0; factors.len()];
+                while factors.iter().all(|values| !values.is_empty()) {
+                    for ((variable, values), index) in variables.iter().zip(&factors).zip(&indices)
+                    {
+                        environment.insert(
+                            variable.symbol,
+                            InstructionResult::String(values[*index].clone()),
+                        );
+                    }
                     result = match instruction.interpret(environment, process) {
                         Ok(value) => value,
                         Err(e) => {
@@ -447,10 +1289,60 @@ impl Instruction {
                             return Err(e);
                         }
                     };
+
+                    let mut position = factors.len();
+                    loop {
+                        if position == 0 {
+                            environment.remove_scope();
+                            return Ok(result);
+                        }
+                        position -= 1;
+                        indices[position] += 1;
+                        if indices[position] < factors[position].len() {
+                            break;
+                        }
+                        indices[position] = 0;
+                    }
                 }
             }
-            _ => {
-                unreachable!()
+            InstructionResult::Cases { path, cases } => {
+                for (number, case) in cases.iter().enumerate() {
+                    if case.len() != variables.len() {
+                        environment.remove_scope();
+                        return Err(InterpreterErrorType::TestFailed(format!(
+                            "Case {} of `{}` has {} {}, but the loop assigns {}",
+                            number + 1,
+                            path,
+                            case.len(),
+                            if case.len() == 1 { "value" } else { "values" },
+                            variables.len()
+                        ))
+                        .into());
+                    }
+                    for (variable, value) in variables.iter().zip(case) {
+                        environment
+                            .insert(variable.symbol, InstructionResult::String(value.clone()));
+                    }
+                    result = match instruction.interpret(environment, process) {
+                        Ok(value) => value,
+                        Err(e) => {
+                            environment.remove_scope();
+                            return Err(e);
+                        }
+                    };
+                }
+            }
+            iterable => {
+                for value in iterable.elements() {
+                    environment.insert(variables[0].symbol, value);
+                    result = match instruction.interpret(environment, process) {
+                        Ok(value) => value,
+                        Err(e) => {
+                            environment.remove_scope();
+                            return Err(e);
+                        }
+                    };
+                }
             }
         }
         environment.remove_scope();
@@ -462,7 +1354,7 @@ impl Instruction {
         environment: &mut Environment,
         _process: &mut Option<&mut Process>,
     ) -> Result<InstructionResult, InterpreterError> {
-        environment.add_function(self.clone());
+        environment.add_function(Rc::new(self.clone()));
         Ok(InstructionResult::None)
     }
 
@@ -490,6 +1382,9 @@ impl Instruction {
             }
         };
 
+        if let Some(coverage) = &mut environment.coverage {
+            coverage.branch(self, condition, environment.test.as_deref());
+        }
         let result = if condition {
             instruction.interpret(environment, process)?
         } else {
@@ -509,18 +1404,13 @@ impl Instruction {
                 instruction,
                 ..
             } => (variable, instruction),
-            InstructionType::IterableAssignment {
-                variable,
-                instruction,
-                ..
-            } => (variable, instruction),
             _ => {
                 unreachable!()
             }
         };
 
         let value = instruction.interpret(environment, process)?;
-        environment.insert(variable.name.clone(), value);
+        environment.insert(variable.symbol, value);
         Ok(InstructionResult::None)
     }
 
@@ -536,7 +1426,7 @@ impl Instruction {
             }
         };
 
-        let value = environment.get(&variable.name).unwrap();
+        let value = environment.get(variable.symbol).unwrap();
         Ok(value.clone())
     }
 
@@ -550,7 +1440,7 @@ impl Instruction {
             _ => unreachable!(),
         };
 
-        let function = environment.get_function(&name).cloned().unwrap();
+        let function = environment.get_function(name).unwrap();
         let (parameters, instruction) = match &function.r#type {
             InstructionType::Function {
                 parameters,
@@ -565,15 +1455,20 @@ impl Instruction {
             .map(|argument| argument.interpret(environment, process))
             .collect::<Result<Vec<InstructionResult>, InterpreterError>>()?;
 
-        environment.add_frame();
+        environment.add_frame()?;
+        environment.calls.push(Call {
+            name: name.clone(),
+            token: self.token.clone(),
+        });
 
-        for (parameter, argument) in parameters.iter().zip(argument_values.iter()) {
-            environment.insert(parameter.name.clone(), argument.clone());
+        for (parameter, argument) in parameters.iter().zip(argument_values) {
+            environment.insert(parameter.symbol, argument);
         }
 
-        let result = instruction.interpret(environment, process)?;
+        let result = instruction.interpret(environment, process);
+        environment.calls.pop();
         environment.remove_frame();
-        Ok(result)
+        result
     }
 
     fn interpret_unary_operation(
@@ -629,6 +1524,8 @@ impl Instruction {
             BinaryOperator::Division => self.interpret_division(environment, process)?,
             BinaryOperator::Modulo => self.interpret_modulo(environment, process)?,
 
+            BinaryOperator::Matches => self.interpret_matches(environment, process)?,
+
             BinaryOperator::Equal => self.interpret_equal(environment, process)?,
             BinaryOperator::NotEqual => self.interpret_not_equal(environment, process)?,
             BinaryOperator::GreaterThan => self.interpret_greater_than(environment, process)?,
@@ -664,7 +1561,10 @@ impl Instruction {
                 InstructionResult::String(format!("{}{}", left, right))
             }
             (InstructionResult::Int(left), InstructionResult::Int(right)) => {
-                InstructionResult::Int(left + right)
+                InstructionResult::Int(
+                    left.checked_add(right)
+                        .ok_or_else(|| Self::arithmetic_error("Integer overflow"))?,
+                )
             }
             (InstructionResult::Float(left), InstructionResult::Float(right)) => {
                 InstructionResult::Float(left + right)
@@ -691,11 +1591,18 @@ impl Instruction {
         };
         Ok(match (left, right) {
             (InstructionResult::Int(left), InstructionResult::Int(right)) => {
-                InstructionResult::Int(left - right)
+                InstructionResult::Int(
+                    left.checked_sub(right)
+                        .ok_or_else(|| Self::arithmetic_error("Integer overflow"))?,
+                )
             }
             (InstructionResult::Float(left), InstructionResult::Float(right)) => {
                 InstructionResult::Float(left - right)
             }
+            (
+                InstructionResult::Regex { values: left, .. },
+                InstructionResult::Regex { values: right, .. },
+            ) => crate::regex::difference(&left, &right),
             _ => {
                 unreachable!()
             }
@@ -718,7 +1625,10 @@ impl Instruction {
         };
         Ok(match (left, right) {
             (InstructionResult::Int(left), InstructionResult::Int(right)) => {
-                InstructionResult::Int(left * right)
+                InstructionResult::Int(
+                    left.checked_mul(right)
+                        .ok_or_else(|| Self::arithmetic_error("Integer overflow"))?,
+                )
             }
             (InstructionResult::String(left), InstructionResult::Int(right)) => {
                 InstructionResult::String(left.repeat(right as usize))
@@ -726,6 +1636,14 @@ impl Instruction {
             (InstructionResult::Float(left), InstructionResult::Float(right)) => {
                 InstructionResult::Float(left * right)
             }
+            (
+                InstructionResult::Regex { values: left, .. },
+                InstructionResult::Regex { values: right, .. },
+            ) => InstructionResult::Product(vec![left, right]),
+            (InstructionResult::Product(mut factors), InstructionResult::Regex { values, .. }) => {
+                factors.push(values);
+                InstructionResult::Product(factors)
+            }
             _ => {
                 unreachable!()
             }
@@ -747,8 +1665,14 @@ impl Instruction {
             }
         };
         Ok(match (left, right) {
+            (InstructionResult::Int(_), InstructionResult::Int(0)) => {
+                return Err(Self::arithmetic_error("Division by zero"));
+            }
             (InstructionResult::Int(left), InstructionResult::Int(right)) => {
-                InstructionResult::Int(left / right)
+                InstructionResult::Int(
+                    left.checked_div(right)
+                        .ok_or_else(|| Self::arithmetic_error("Integer overflow"))?,
+                )
             }
             (InstructionResult::Float(left), InstructionResult::Float(right)) => {
                 InstructionResult::Float(left / right)
@@ -774,8 +1698,48 @@ impl Instruction {
             }
         };
         Ok(match (left, right) {
+            (InstructionResult::Int(_), InstructionResult::Int(0)) => {
+                return Err(Self::arithmetic_error("Modulo by zero"));
+            }
             (InstructionResult::Int(left), InstructionResult::Int(right)) => {
-                InstructionResult::Int(left % right)
+                InstructionResult::Int(
+                    left.checked_rem(right)
+                        .ok_or_else(|| Self::arithmetic_error("Integer overflow"))?,
+                )
+            }
+            _ => {
+                unreachable!()
+            }
+        })
+    }
+
+    fn arithmetic_error(message: &str) -> InterpreterError {
+        InterpreterErrorType::Arithmetic(message.to_string()).into()
+    }
+
+    fn interpret_matches(
+        &self,
+        environment: &mut Environment,
+        process: &mut Option<&mut Process>,
+    ) -> Result<InstructionResult, InterpreterError> {
+        let (left, right) = match &self.r#type {
+            InstructionType::BinaryOperation { left, right, .. } => (
+                left.interpret(environment, process)?,
+                right.interpret(environment, process)?,
+            ),
+            _ => {
+                unreachable!()
+            }
+        };
+        Ok(match (left, right) {
+            (InstructionResult::String(left), InstructionResult::Regex { pattern, .. }) => {
+                let regex = regex::Regex::new(&pattern).map_err(|e| {
+                    InterpreterErrorType::TestFailed(format!(
+                        "Failed to compile regex `{}`: {}",
+                        pattern, e
+                    ))
+                })?;
+                InstructionResult::Bool(regex.is_match(&left))
             }
             _ => {
                 unreachable!()
@@ -805,7 +1769,7 @@ impl Instruction {
                 InstructionResult::Bool(left == right)
             }
             (InstructionResult::Float(left), InstructionResult::Float(right)) => {
-                InstructionResult::Bool(left == right)
+                InstructionResult::Bool(float_equal(left, right, environment.float_epsilon))
             }
             (InstructionResult::Bool(left), InstructionResult::Bool(right)) => {
                 InstructionResult::Bool(left == right)
@@ -838,7 +1802,7 @@ impl Instruction {
                 InstructionResult::Bool(left != right)
             }
             (InstructionResult::Float(left), InstructionResult::Float(right)) => {
-                InstructionResult::Bool(left != right)
+                InstructionResult::Bool(!float_equal(left, right, environment.float_epsilon))
             }
             (InstructionResult::Bool(left), InstructionResult::Bool(right)) => {
                 InstructionResult::Bool(left != right)
@@ -963,21 +1927,32 @@ impl Instruction {
         process: &mut Option<&mut Process>,
     ) -> Result<InstructionResult, InterpreterError> {
         let (left, right) = match &self.r#type {
-            InstructionType::BinaryOperation { left, right, .. } => (
-                left.interpret(environment, process)?,
-                right.interpret(environment, process)?,
-            ),
+            InstructionType::BinaryOperation { left, right, .. } => (left, right),
             _ => {
                 unreachable!()
             }
         };
-        let (left, right) = match (left, right) {
-            (InstructionResult::Bool(left), InstructionResult::Bool(right)) => (left, right),
+        let left = left.interpret(environment, process)?;
+        // The right side of a bool `&&` is only evaluated when it can
+        // change the result
+        if let InstructionResult::Bool(left) = left {
+            if !left {
+                return Ok(InstructionResult::Bool(false));
+            }
+        }
+        let right = right.interpret(environment, process)?;
+        Ok(match (left, right) {
+            (InstructionResult::Bool(left), InstructionResult::Bool(right)) => {
+                InstructionResult::Bool(left && right)
+            }
+            (
+                InstructionResult::Regex { values: left, .. },
+                InstructionResult::Regex { values: right, .. },
+            ) => crate::regex::intersection(&left, &right),
             _ => {
                 unreachable!()
             }
-        };
-        Ok(InstructionResult::Bool(left && right))
+        })
     }
 
     fn interpret_or(
@@ -986,21 +1961,38 @@ impl Instruction {
         process: &mut Option<&mut Process>,
     ) -> Result<InstructionResult, InterpreterError> {
         let (left, right) = match &self.r#type {
-            InstructionType::BinaryOperation { left, right, .. } => (
-                left.interpret(environment, process)?,
-                right.interpret(environment, process)?,
-            ),
+            InstructionType::BinaryOperation { left, right, .. } => (left, right),
             _ => {
                 unreachable!()
             }
         };
-        let (left, right) = match (left, right) {
-            (InstructionResult::Bool(left), InstructionResult::Bool(right)) => (left, right),
+        let left = left.interpret(environment, process)?;
+        // The right side of a bool `||` is only evaluated when it can
+        // change the result
+        if let InstructionResult::Bool(left) = left {
+            if left {
+                return Ok(InstructionResult::Bool(true));
+            }
+        }
+        let right = right.interpret(environment, process)?;
+        Ok(match (left, right) {
+            (InstructionResult::Bool(left), InstructionResult::Bool(right)) => {
+                InstructionResult::Bool(left || right)
+            }
+            (
+                InstructionResult::Regex {
+                    pattern: left_pattern,
+                    values: left,
+                },
+                InstructionResult::Regex {
+                    pattern: right_pattern,
+                    values: right,
+                },
+            ) => crate::regex::union(&left_pattern, &left, &right_pattern, &right),
             _ => {
                 unreachable!()
             }
-        };
-        Ok(InstructionResult::Bool(left || right))
+        })
     }
 
     fn interpret_typecast(
@@ -1008,11 +2000,12 @@ impl Instruction {
         environment: &mut Environment,
         process: &mut Option<&mut Process>,
     ) -> Result<InstructionResult, InterpreterError> {
-        let (instruction, r#type) = match &self.r#type {
+        let (instruction, r#type, checked) = match &self.r#type {
             InstructionType::TypeCast {
                 instruction,
                 r#type,
-            } => (instruction, r#type),
+                checked,
+            } => (instruction, r#type, *checked),
             _ => {
                 unreachable!()
             }
@@ -1033,15 +2026,31 @@ impl Instruction {
                     InstructionResult::Int(match string_value.parse() {
                         Ok(value) => value,
                         Err(_) => {
-                            return Err(InterpreterError::TypeCast {
+                            return Err(InterpreterErrorType::TypeCast {
                                 result: value,
-                                from: *r#type,
+                                from: Type::String,
                                 to: Type::Int,
-                            });
+                            }
+                            .into());
                         }
                     })
                 }
-                InstructionResult::Float(value) => InstructionResult::Int(value as i64),
+                InstructionResult::Float(float_value) => {
+                    // `as` truncates and saturates, `try_cast` only accepts
+                    // whole numbers that fit in an `int`
+                    if checked
+                        && (float_value.fract() != 0.0
+                            || !(i64::MIN as f64..i64::MAX as f64).contains(&float_value))
+                    {
+                        return Err(InterpreterErrorType::TypeCast {
+                            result: value,
+                            from: Type::Float,
+                            to: Type::Int,
+                        }
+                        .into());
+                    }
+                    InstructionResult::Int(float_value as i64)
+                }
                 _ => {
                     unreachable!()
                 }
@@ -1051,11 +2060,12 @@ impl Instruction {
                     InstructionResult::Float(match string_value.parse() {
                         Ok(value) => value,
                         Err(_) => {
-                            return Err(InterpreterError::TypeCast {
+                            return Err(InterpreterErrorType::TypeCast {
                                 result: value,
-                                from: *r#type,
+                                from: Type::String,
                                 to: Type::Float,
-                            });
+                            }
+                            .into());
                         }
                     })
                 }
@@ -1069,14 +2079,26 @@ impl Instruction {
                     InstructionResult::Bool(match string_value.parse() {
                         Ok(value) => value,
                         Err(_) => {
-                            return Err(InterpreterError::TypeCast {
+                            return Err(InterpreterErrorType::TypeCast {
                                 result: value,
-                                from: *r#type,
+                                from: Type::String,
                                 to: Type::Bool,
-                            });
+                            }
+                            .into());
                         }
                     })
                 }
+                InstructionResult::Int(int_value) => {
+                    if checked && int_value != 0 && int_value != 1 {
+                        return Err(InterpreterErrorType::TypeCast {
+                            result: value,
+                            from: Type::Int,
+                            to: Type::Bool,
+                        }
+                        .into());
+                    }
+                    InstructionResult::Bool(int_value != 0)
+                }
                 _ => {
                     unreachable!()
                 }
@@ -1091,22 +2113,42 @@ impl Instruction {
 #[derive(Debug, Clone, PartialEq)]
 pub enum InstructionType {
     StringLiteral(String),
-    RegexLiteral(Vec<String>),
+    RegexLiteral {
+        pattern: String,
+        values: Rc<[String]>,
+    },
     IntegerLiteral(i64),
     FloatLiteral(f64),
     BooleanLiteral(bool),
 
     BuiltIn(BuiltIn),
 
-    Block(Vec<Instruction>),
+    Block {
+        instructions: Vec<Instruction>,
+        /// Whether the last instruction has no semicolon, which makes its
+        /// value the value of the block
+        tail: bool,
+    },
     Paren(Box<Instruction>),
 
-    Test(Box<Instruction>, String, String),
+    Test {
+        name: String,
+        command: Box<Instruction>,
+        env: Vec<(String, String)>,
+        instruction: Box<Instruction>,
+    },
+    /// Tests and suites grouped under a name, which prefixes their names
+    Suite {
+        name: String,
+        instructions: Vec<Instruction>,
+    },
     Function {
         name: String,
         parameters: Vec<Variable>,
         instruction: Box<Instruction>,
         return_type: Type,
+        /// The last token of the function, where a missing value is reported
+        end: Token,
     },
     For {
         assignment: Box<Instruction>,
@@ -1125,7 +2167,7 @@ pub enum InstructionType {
         declaration: bool,
     },
     IterableAssignment {
-        variable: Variable,
+        variables: Vec<Variable>,
         instruction: Box<Instruction>,
         token: Token,
     },
@@ -1149,6 +2191,8 @@ pub enum InstructionType {
     TypeCast {
         instruction: Box<Instruction>,
         r#type: Type,
+        /// Set by `try_cast`, which fails instead of losing information
+        checked: bool,
     },
 
     None,
@@ -1,43 +1,66 @@
-use crate::cli::Args;
+use crate::artifacts::Artifacts;
+use crate::cli::{Args, Verbosity};
+use crate::coverage::Coverage;
+use crate::diagnostic::outputln;
 use crate::environment::Environment;
-use crate::error::InterpreterError;
-use crate::instruction::{Instruction, InstructionType};
-use crate::process::Process;
+use crate::error::{InterpreterError, InterpreterErrorType};
+use crate::exitcode::ExitCode;
+use crate::history::{self, TestResult};
+use crate::instruction::{Attribute, Instruction, InstructionResult, InstructionType};
+use crate::manifest::Manifest;
+use crate::process::{Comparison, Limits, OutputCopy, Process, ProcessOptions, TerminalSize};
+use crate::profile::Profile;
+use crate::token::TokenType;
+
+use colored::Colorize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 struct Test {
     name: String,
     instruction: Instruction,
     process: Process,
     passed: bool,
+    verbosity: Verbosity,
 }
 
 impl Test {
-    fn new(name: String, command: String, instruction: Instruction, args: Args) -> Self {
-        let process = Process::new(&command, args.debug);
-
+    fn new(name: String, instruction: Instruction, process: Process, verbosity: Verbosity) -> Self {
         Self {
             name,
 
             instruction,
             process,
             passed: true,
+            verbosity,
         }
     }
 
     fn run(&mut self, environment: &mut Environment) {
-        environment.add_frame();
-        let instruction = self.instruction.clone();
-        match instruction.interpret(environment, &mut Some(&mut self.process)) {
+        if let Err(e) = environment.add_frame() {
+            self.fail(e);
+            return;
+        }
+        match self
+            .instruction
+            .interpret(environment, &mut Some(&mut self.process))
+        {
             Ok(_) => (),
             Err(e) => {
-                e.print();
                 environment.remove_frame();
+                self.fail(e);
                 return;
             }
         }
         environment.remove_frame();
 
-        match self.process.terminate() {
+        let start = Instant::now();
+        let terminated = self.process.terminate();
+        if let Some(profile) = &mut environment.profile {
+            profile.record("process", "wait for exit", start.elapsed());
+        }
+        match terminated {
             Ok(()) => (),
             Err(e) => {
                 self.fail(e);
@@ -52,12 +75,54 @@ impl Test {
     }
 
     fn pass(&self) {
-        println!("Test passed: {}", self.name);
+        if self.verbosity > Verbosity::Quiet {
+            outputln!("Test passed: {}", self.name);
+        }
     }
 
     fn fail(&mut self, error: InterpreterError) {
-        error.print();
-        let _ = self.process.terminate();
+        self.passed = false;
+        if self.verbosity > Verbosity::Quiet {
+            error.print();
+        }
+        self.process.kill();
+        if self.verbosity > Verbosity::Quiet {
+            if let Some(limit) = self.process.exceeded_limit() {
+                eprintln!("{}{}\n", "note: ".bright_blue(), limit);
+            }
+            self.print_stderr();
+        }
+    }
+
+    fn write_artifacts(&mut self, artifacts: &Artifacts) {
+        match artifacts.write(&self.name, &mut self.process) {
+            Ok(directory) if self.verbosity > Verbosity::Quiet => eprintln!(
+                "{}the artifacts of the test are in `{}`\n",
+                "note: ".bright_blue(),
+                directory.display()
+            ),
+            Ok(_) => (),
+            Err(e) => eprintln!(
+                "{}Failed to write the artifacts of `{}`: {}\n",
+                "error: ".bright_red(),
+                self.name,
+                e
+            ),
+        }
+    }
+
+    fn print_stderr(&mut self) {
+        if let Some((stderr, truncated)) = self.process.stderr() {
+            let note = match truncated {
+                true => "the end of what the program wrote to stderr:",
+                false => "the program wrote to stderr:",
+            };
+            eprintln!("{}{}", "note: ".bright_blue(), note);
+            for line in stderr.trim_end().lines() {
+                eprintln!("    {}", line);
+            }
+            eprintln!();
+        }
     }
 }
 
@@ -65,23 +130,232 @@ pub struct Interpreter {
     args: Args,
     program: Vec<Instruction>,
     environment: Environment,
+    passed: Vec<String>,
+    failed: Vec<String>,
+    /// How long every test took, for `--history`
+    durations: HashMap<String, Duration>,
+    exit_code: Option<ExitCode>,
+    seed: u64,
+    artifacts: Option<Artifacts>,
+    manifest: Option<Manifest>,
 }
 
 impl Interpreter {
-    pub fn new(program: Vec<Instruction>, args: Args) -> Self {
-        let environment = Environment::new();
+    pub fn new(program: Vec<Instruction>, args: Args, profile: Option<Profile>) -> Self {
+        let mut environment = Environment::new(args.verbosity());
+        environment.profile = profile;
+        environment.coverage = args
+            .script_coverage
+            .is_some()
+            .then(|| Coverage::new(&program));
+        environment.float_epsilon = args.float_epsilon.unwrap_or_default();
+        environment.update_expected = args.update_expected;
+        let seed = args.seed.unwrap_or_else(|| fastrand::u64(..));
+        let artifacts = (!args.no_artifacts).then(|| Artifacts::new(args.artifacts.clone(), seed));
+        let manifest = (args.manifest.is_some() || args.expected_manifest.is_some())
+            .then(|| Manifest::new(&args, seed, &program));
         Self {
             program,
             args,
             environment,
+            passed: Vec::new(),
+            failed: Vec::new(),
+            durations: HashMap::new(),
+            exit_code: None,
+            seed,
+            artifacts,
+            manifest,
+        }
+    }
+
+    fn terminal_size(&self, attributes: &[Attribute]) -> Option<TerminalSize> {
+        match attributes.iter().find(|attribute| attribute.name == "pty") {
+            Some(attribute) => match attribute.arguments.as_slice() {
+                [rows, columns] => match (&rows.r#type, &columns.r#type) {
+                    (
+                        TokenType::IntegerLiteral { value: rows },
+                        TokenType::IntegerLiteral { value: columns },
+                    ) => Some(TerminalSize {
+                        rows: *rows as u16,
+                        columns: *columns as u16,
+                    }),
+                    _ => unreachable!(),
+                },
+                _ => Some(TerminalSize::default()),
+            },
+            None => self.args.pty.then(TerminalSize::default),
+        }
+    }
+
+    fn path(&self, attributes: &[Attribute], name: &str) -> Option<PathBuf> {
+        let attribute = attributes.iter().find(|attribute| attribute.name == name)?;
+        match &attribute.arguments[0].r#type {
+            TokenType::StringLiteral { value } => Some(PathBuf::from(&value[1..value.len() - 1])),
+            _ => unreachable!(),
+        }
+    }
+
+    fn output_copy(&self, attributes: &[Attribute]) -> Option<OutputCopy> {
+        let attribute = attributes
+            .iter()
+            .find(|attribute| attribute.name == "stdout_to")?;
+        Some(OutputCopy {
+            path: self.path(attributes, "stdout_to")?,
+            tee: attribute.arguments.len() == 2,
+            append: false,
+        })
+    }
+
+    fn wrappers(&self, attributes: &[Attribute]) -> Vec<String> {
+        let mut wrappers = self.args.wrapper.clone();
+        for attribute in attributes
+            .iter()
+            .filter(|attribute| attribute.name == "wrapper")
+        {
+            match &attribute.arguments[0].r#type {
+                TokenType::StringLiteral { value } => {
+                    wrappers.push(value[1..value.len() - 1].to_string())
+                }
+                _ => unreachable!(),
+            }
+        }
+        wrappers
+    }
+
+    fn limit(&self, attributes: &[Attribute], name: &str) -> Option<u64> {
+        let attribute = attributes.iter().find(|attribute| attribute.name == name)?;
+        match attribute.arguments[0].r#type {
+            TokenType::IntegerLiteral { value } => Some(value as u64),
+            _ => unreachable!(),
+        }
+    }
+
+    /// How the output of the test `name` is compared. The table of every
+    /// suite the test is in changes the options of the whole file, the
+    /// tables of inner suites last
+    fn comparison(&self, name: &str) -> Comparison {
+        let mut comparison = Comparison {
+            keep_line_endings: self.args.keep_line_endings,
+            keep_trailing_whitespace: self.args.keep_trailing_whitespace,
+            collapse_whitespace: self.args.collapse_whitespace,
+            strip_ansi: self.args.strip_ansi,
+            number_tolerance: self.args.number_tolerance,
+        };
+        let mut suites = self
+            .args
+            .suites
+            .iter()
+            .filter(|(suite, _)| {
+                name.strip_prefix(suite.as_str())
+                    .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .collect::<Vec<_>>();
+        suites.sort_by_key(|(suite, _)| suite.matches("::").count());
+        for (_, suite) in suites {
+            comparison.keep_line_endings = suite
+                .keep_line_endings
+                .unwrap_or(comparison.keep_line_endings);
+            comparison.keep_trailing_whitespace = suite
+                .keep_trailing_whitespace
+                .unwrap_or(comparison.keep_trailing_whitespace);
+            comparison.collapse_whitespace = suite
+                .collapse_whitespace
+                .unwrap_or(comparison.collapse_whitespace);
+            comparison.strip_ansi = suite.strip_ansi.unwrap_or(comparison.strip_ansi);
+            comparison.number_tolerance = suite.number_tolerance.or(comparison.number_tolerance);
+        }
+        comparison
+    }
+
+    fn process_options(&self, name: &str, attributes: &[Attribute]) -> ProcessOptions {
+        ProcessOptions {
+            terminal: self.terminal_size(attributes),
+            cwd: self.path(attributes, "cwd"),
+            env: Vec::new(),
+            timeout: (!self.args.timeout.is_zero()).then_some(self.args.timeout),
+            comparison: self.comparison(name),
+            limits: Limits {
+                memory: self.limit(attributes, "max_memory"),
+                cpu_time: self.limit(attributes, "max_cpu_time"),
+                file_size: self.limit(attributes, "max_file_size"),
+            },
+            wrappers: self.wrappers(attributes),
+            stdin: self.path(attributes, "stdin"),
+            stdout_to: self.output_copy(attributes),
+            sandbox: self.args.sandbox,
+            max_runtime: self
+                .limit(attributes, "max_runtime")
+                .map(Duration::from_millis),
         }
     }
 
     fn interpret_test(&mut self, instruction: Instruction) {
+        let attributes = instruction.attributes;
         match instruction.r#type {
-            InstructionType::Test(instruction, name, file) => {
-                let mut test = Test::new(name, file, *instruction, self.args.clone());
+            InstructionType::Test {
+                name,
+                command,
+                env,
+                instruction,
+            } => {
+                let mut options = self.process_options(&name, &attributes);
+                self.environment.test = Some(name.clone());
+                // A test run alone with `--filter` creates the same random
+                // strings as in the whole run
+                fastrand::seed(self.seed);
+                options.env = env;
+                let verbosity = self.args.verbosity();
+                let process = match command.interpret(&mut self.environment, &mut None) {
+                    Ok(InstructionResult::String(value)) => {
+                        if let Some(manifest) = &mut self.manifest {
+                            manifest.command(&value, &options);
+                        }
+                        let start = Instant::now();
+                        let process = Process::new(&value, verbosity, options);
+                        if let Some(profile) = &mut self.environment.profile {
+                            profile.record("process", "spawn", start.elapsed());
+                        }
+                        process
+                    }
+                    Ok(_) => unreachable!(),
+                    Err(e) => {
+                        if verbosity > Verbosity::Quiet {
+                            e.print();
+                        }
+                        self.failed.push(name);
+                        return;
+                    }
+                };
+                let process = match process {
+                    Ok(process) => process,
+                    Err(error) => {
+                        let e = InterpreterError::new(
+                            InterpreterErrorType::Spawn {
+                                test: name.clone(),
+                                error,
+                            },
+                            command.token,
+                        );
+                        if verbosity > Verbosity::Quiet {
+                            e.print();
+                        }
+                        self.exit_code = self.exit_code.or(e.exit_code());
+                        self.failed.push(name);
+                        return;
+                    }
+                };
+                let mut test = Test::new(name, *instruction, process, verbosity);
                 test.run(&mut self.environment);
+                if let Some(artifacts) = &self.artifacts {
+                    match test.passed {
+                        true => artifacts.remove(&test.name),
+                        false => test.write_artifacts(artifacts),
+                    }
+                }
+                match test.passed {
+                    true => self.passed.push(test.name),
+                    false => self.failed.push(test.name),
+                }
             }
             _ => {
                 unreachable!()
@@ -90,9 +364,47 @@ impl Interpreter {
     }
 
     pub fn interpret(&mut self) {
-        for instruction in self.program.clone().into_iter() {
+        // Every function is declared first, since a constant may call a
+        // function declared below it. The constants are already ordered so
+        // that each comes after the constants it uses, and are evaluated once
+        // before the first test runs
+        let (functions, rest): (Vec<Instruction>, Vec<Instruction>) =
+            std::mem::take(&mut self.program)
+                .into_iter()
+                .partition(|instruction| {
+                    matches!(instruction.r#type, InstructionType::Function { .. })
+                });
+        let (suites, constants): (Vec<Instruction>, Vec<Instruction>) =
+            rest.into_iter().partition(|instruction| {
+                matches!(
+                    instruction.r#type,
+                    InstructionType::Test { .. } | InstructionType::Suite { .. }
+                )
+            });
+        let mut tests = Vec::new();
+        flatten_suites(suites, &mut tests);
+        fastrand::seed(self.seed);
+        tests.retain(|test| match &test.r#type {
+            InstructionType::Test { name, .. } => {
+                self.args.filter.is_empty()
+                    || self.args.filter.iter().any(|filter| matches(filter, name))
+            }
+            _ => unreachable!(),
+        });
+        for instruction in functions.into_iter().chain(constants).chain(tests) {
+            if let (InstructionType::Assignment { .. }, Some(coverage)) =
+                (&instruction.r#type, &mut self.environment.coverage)
+            {
+                coverage.statement(&instruction, None);
+            }
             match instruction.r#type {
-                InstructionType::Test(_, _, _) => self.interpret_test(instruction),
+                InstructionType::Test { ref name, .. } => {
+                    let name = name.clone();
+                    let start = Instant::now();
+                    self.interpret_test(instruction);
+                    self.durations.insert(name, start.elapsed());
+                    self.environment.test = None;
+                }
                 InstructionType::Function { .. } => {
                     let _ = instruction.interpret(&mut self.environment, &mut None);
                 }
@@ -109,12 +421,156 @@ impl Interpreter {
                             return;
                         }
                     };
-                    self.environment.insert(variable.name, result);
+                    self.environment.insert(variable.symbol, result);
                 }
                 _ => {
                     unreachable!()
                 }
             }
         }
+        self.print_summary();
+        if let Some(profile) = &self.environment.profile {
+            profile.print();
+        }
+        if let (Some(coverage), Some(path)) =
+            (&self.environment.coverage, &self.args.script_coverage)
+        {
+            if let Err(e) = coverage.write(path) {
+                eprintln!(
+                    "{}Failed to write the coverage report `{}`: {}\n",
+                    "error: ".bright_red(),
+                    path.display(),
+                    e
+                );
+                self.exit_code = self.exit_code.or(Some(ExitCode::CoverageFailed));
+            }
+        }
+        self.finish_manifest();
+        self.record_history();
+
+        if let Some(exit_code) = self.exit_code {
+            std::process::exit(exit_code as i32);
+        }
+    }
+
+    /// Writes the manifest of `--manifest`, and compares it with the one of
+    /// `--verify-manifest`
+    fn finish_manifest(&mut self) {
+        let Some(manifest) = &mut self.manifest else {
+            return;
+        };
+        for test in &self.passed {
+            manifest.result(test, true);
+        }
+        for test in &self.failed {
+            manifest.result(test, false);
+        }
+        if let Some(path) = &self.args.manifest {
+            if let Err(e) = manifest.write(path) {
+                eprintln!(
+                    "{}Failed to write the manifest `{}`: {}\n",
+                    "error: ".bright_red(),
+                    path.display(),
+                    e
+                );
+                self.exit_code = self.exit_code.or(Some(ExitCode::ManifestFailed));
+            }
+        }
+        if let (Some(expected), Some(path)) =
+            (&self.args.expected_manifest, &self.args.verify_manifest)
+        {
+            let differences = manifest.differences(expected);
+            match differences.is_empty() {
+                true => outputln!("The run is the same as the manifest `{}`", path.display()),
+                false => {
+                    eprintln!(
+                        "{}The run is different from the manifest `{}`:",
+                        "error: ".bright_red(),
+                        path.display()
+                    );
+                    for difference in differences {
+                        eprintln!("    {}", difference);
+                    }
+                    eprintln!();
+                    self.exit_code = self.exit_code.or(Some(ExitCode::ManifestDiffers));
+                }
+            }
+        }
+    }
+
+    fn record_history(&mut self) {
+        let Some(path) = &self.args.history else {
+            return;
+        };
+        let script = match (&self.args.eval, &self.args.file) {
+            (Some(_), _) => String::from("<eval>"),
+            (None, Some(file)) if file.as_os_str() == "-" => String::from("<stdin>"),
+            (None, file) => file.clone().unwrap_or_default().display().to_string(),
+        };
+        let results = self
+            .passed
+            .iter()
+            .map(|test| (test, true))
+            .chain(self.failed.iter().map(|test| (test, false)))
+            .map(|(test, passed)| TestResult {
+                name: test.clone(),
+                passed,
+                duration: self.durations.get(test).copied().unwrap_or_default(),
+            })
+            .collect::<Vec<TestResult>>();
+        if let Err(e) = history::record(path, &script, self.seed, &results) {
+            eprintln!(
+                "{}Failed to record the run in the history `{}`: {}\n",
+                "error: ".bright_red(),
+                path.display(),
+                e
+            );
+            self.exit_code = self.exit_code.or(Some(ExitCode::HistoryFailed));
+        }
+    }
+
+    fn print_summary(&self) {
+        if self.args.verbosity() > Verbosity::Quiet {
+            outputln!();
+        }
+        outputln!(
+            "Test result: {} passed; {} failed",
+            self.passed.len(),
+            self.failed.len()
+        );
+        if !self.failed.is_empty() {
+            outputln!("Failed tests: {}", self.failed.join(", "));
+        }
+    }
+}
+
+/// Moves the tests of every suite, and of the suites in them, into `tests`
+fn flatten_suites(instructions: Vec<Instruction>, tests: &mut Vec<Instruction>) {
+    for instruction in instructions {
+        match instruction.r#type {
+            InstructionType::Suite { instructions, .. } => flatten_suites(instructions, tests),
+            _ => tests.push(instruction),
+        }
+    }
+}
+
+/// Whether the name matches the pattern of `--filter`, where `*` matches any
+/// number of characters
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<&str>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
     }
+    rest.len() >= last.len() && rest.ends_with(last)
 }
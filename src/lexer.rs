@@ -1,79 +1,128 @@
-use crate::cli::Args;
+use crate::error::{ParseError, ParseErrorType};
+use crate::instruction;
+use crate::plugin;
 use crate::r#type::Type;
 use crate::token::{Token, TokenCollection, TokenType};
 
 use std::path::PathBuf;
+use std::rc::Rc;
 
-pub struct Lexer<'a> {
-    lines: Vec<String>,
-    contents: std::iter::Peekable<std::str::Chars<'a>>,
-    file: PathBuf,
+pub const KEYWORDS: [&str; 7] = ["for", "let", "const", "if", "else", "fn", "suite"];
+pub const TYPES: [&str; 6] = ["string", "regex", "int", "float", "bool", "none"];
+pub const BOOLEANS: [&str; 2] = ["true", "false"];
+pub const ITERABLE_ASSIGNMENT: &str = "in";
+pub const TYPE_CAST: &str = "as";
+
+pub struct Lexer {
+    /// The whole script, shared with every token so diagnostics can find
+    /// the line a token is on without the lexer storing each line
+    source: Rc<str>,
+    /// Byte offset of the next character
+    position: usize,
+    /// Byte offset of the token being lexed
+    start: usize,
+    file: Rc<str>,
     tokens: Vec<Token>,
+    /// Number of tokens whose end position is known
+    ended: usize,
 
     row: usize,
     column: usize,
 }
 
-impl<'a> Lexer<'a> {
-    pub fn new(contents: &'a mut String, args: Args) -> Lexer<'a> {
-        let lines = contents.lines().map(|s| s.to_string()).collect();
-        let contents = contents.chars().peekable().to_owned().clone();
-
+impl Lexer {
+    pub fn new(contents: &str, file: PathBuf) -> Lexer {
         let row = 1;
         let column = 1;
 
         let tokens = Vec::new();
 
         Lexer {
-            lines,
-            contents,
-            file: args.file,
+            source: Rc::from(contents),
+            position: 0,
+            start: 0,
+            file: Rc::from(file.to_str().unwrap()),
             tokens,
+            ended: 0,
 
             row,
             column,
         }
     }
 
+    fn peek(&self) -> Option<char> {
+        self.source[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.peek()?;
+        self.position += next.len_utf8();
+        Some(next)
+    }
+
     fn make_token(&self, r#type: TokenType) -> Token {
         Token {
             r#type,
-            file: self.file.to_str().unwrap().to_string(),
+            file: self.file.clone(),
             row: self.row,
             column: self.column,
+            offset: self.start,
+            end_row: self.row,
+            end_column: self.column,
+            end_offset: self.start,
 
-            line: self.get_line(),
+            source: self.source.clone(),
             last_token: match self.tokens.last() {
                 Some(token) => {
                     let mut token = token.clone();
                     token.last_token = None;
-                    Some(Box::new(token))
+                    Some(Rc::new(token))
                 }
                 None => None,
             },
         }
     }
 
-    fn get_line(&self) -> String {
-        self.lines[self.row as usize - 1].clone()
+    /// Every token is complete once the next one starts, so the current
+    /// position is the end of the tokens created since the last call
+    fn end_tokens(&mut self) {
+        let (row, column, offset) = (self.row, self.column, self.position);
+        for token in &mut self.tokens[self.ended..] {
+            token.end_row = row;
+            token.end_column = column;
+            token.end_offset = offset;
+        }
+        self.ended = self.tokens.len();
+    }
+
+    /// Reports a character that is not valid where it is, `column` and
+    /// `offset` are where it starts
+    fn unexpected_character(&self, c: char, column: usize, offset: usize) {
+        let mut token = self.make_token(TokenType::None);
+        (token.column, token.offset) = (column, offset);
+        token.end_column = column + 1;
+        token.end_offset = offset + c.len_utf8();
+        ParseError::new(ParseErrorType::UnexpectedCharacter(c), token).print();
     }
 
     fn identifier_type(&mut self, value: &String) -> TokenType {
         match value.as_str() {
-            "for" | "let" | "const" | "if" | "else" | "fn" => TokenType::Keyword {
+            value if KEYWORDS.contains(&value) => TokenType::Keyword {
                 value: value.to_string(),
             },
-            "string" | "regex" | "int" | "float" | "bool" | "none" => TokenType::Type {
+            value if TYPES.contains(&value) => TokenType::Type {
                 value: Type::from(value),
             },
-            "true" | "false" => TokenType::BooleanLiteral {
+            value if BOOLEANS.contains(&value) => TokenType::BooleanLiteral {
                 value: value.parse::<bool>().unwrap(),
             },
-            "in" => TokenType::IterableAssignmentOperator,
-            "as" => TokenType::TypeCast,
-            "input" | "output" | "print" | "println" => TokenType::BuiltIn {
-                value: value.to_string(),
-            },
+            ITERABLE_ASSIGNMENT => TokenType::IterableAssignmentOperator,
+            TYPE_CAST => TokenType::TypeCast,
+            value if instruction::is_builtin(value) || plugin::get(value).is_some() => {
+                TokenType::BuiltIn {
+                    value: value.to_string(),
+                }
+            }
             _ => TokenType::Identifier {
                 value: value.to_string(),
             },
@@ -84,12 +133,12 @@ impl<'a> Lexer<'a> {
         let mut length = 0;
         let mut current = String::new();
 
-        while let Some(next) = self.contents.peek() {
-            if !(next.is_alphanumeric() || *next == '_') {
+        while let Some(next) = self.peek() {
+            if !(next.is_alphanumeric() || next == '_') {
                 break;
             }
-            current.push(*next);
-            self.contents.next();
+            current.push(next);
+            self.next();
             length += 1;
         }
 
@@ -103,24 +152,27 @@ impl<'a> Lexer<'a> {
         let mut new_row = self.row;
         let mut new_column = self.column + 1;
         let mut current = String::from("\"");
+        let mut escaped = false;
 
-        self.contents.next();
+        self.next();
 
-        while let Some(next) = self.contents.peek() {
-            if *next == '\n' {
+        while let Some(next) = self.peek() {
+            if next == '\n' {
                 new_row += 1;
-                new_column = 1;
+                new_column = 0;
             }
-            current.push(*next);
+            current.push(next);
             new_column += 1;
-            if *next == '"' {
+            if next == '"' && !escaped {
                 break;
             }
-            self.contents.next();
+            escaped = next == '\\' && !escaped;
+            self.next();
         }
 
-        self.contents.next();
+        self.next();
 
+        current = current.replace("\\\"", "\"");
         current = current.replace("\\n", "\n");
         current = current.replace("\\t", "\t");
         current = current.replace("\\r", "\r");
@@ -134,23 +186,30 @@ impl<'a> Lexer<'a> {
         let mut new_row = self.row;
         let mut new_column = self.column + 1;
         let mut current = String::from("`");
+        let mut escaped = false;
 
-        self.contents.next();
+        self.next();
 
-        while let Some(next) = self.contents.peek() {
-            if *next == '\n' {
+        while let Some(next) = self.peek() {
+            if next == '\n' {
                 new_row += 1;
-                new_column = 1;
+                new_column = 0;
+            }
+            // `\`` is a backtick inside the regex, the backslash is dropped
+            // since a backtick needs no escape in the regex itself
+            if next == '`' && escaped {
+                current.pop();
             }
-            current.push(*next);
+            current.push(next);
             new_column += 1;
-            if *next == '`' {
+            if next == '`' && !escaped {
                 break;
             }
-            self.contents.next();
+            escaped = next == '\\' && !escaped;
+            self.next();
         }
 
-        self.contents.next();
+        self.next();
 
         let token = self.make_token(TokenType::RegexLiteral { value: current });
         self.row = new_row;
@@ -162,24 +221,39 @@ impl<'a> Lexer<'a> {
         let mut length = 0;
         let mut current = String::new();
         let mut float = false;
-        while let Some(next) = self.contents.peek() {
-            if *next == '.' {
+        while let Some(next) = self.peek() {
+            if next == '.' {
+                // The rest of a number with several dots is skipped, so it
+                // is one error
                 if float {
-                    panic!("Unexpected character: \".\"");
+                    self.unexpected_character(next, self.column + length, self.position);
+                    while let Some('0'..='9' | '.') = self.peek() {
+                        self.next();
+                        length += 1;
+                    }
+                    break;
                 }
                 float = true;
             } else if !next.is_ascii_digit() {
                 break;
             }
-            current.push(*next);
-            self.contents.next();
+            current.push(next);
+            self.next();
             length += 1;
         }
 
         let token = match float {
-            false => self.make_token(TokenType::IntegerLiteral {
-                value: current.parse::<i64>().unwrap(),
-            }),
+            false => match current.parse::<i64>() {
+                Ok(value) => self.make_token(TokenType::IntegerLiteral { value }),
+                Err(_) => {
+                    let mut token = self.make_token(TokenType::IntegerLiteral { value: i64::MAX });
+                    token.end_column = self.column + length;
+                    token.end_offset = self.position;
+                    ParseError::new(ParseErrorType::IntegerTooLarge(current), token.clone())
+                        .print();
+                    token
+                }
+            },
             true => self.make_token(TokenType::FloatLiteral {
                 value: current.parse::<f64>().unwrap(),
             }),
@@ -189,12 +263,17 @@ impl<'a> Lexer<'a> {
     }
 
     pub fn tokenize(&mut self) -> TokenCollection {
-        while let Some(c) = self.contents.peek() {
+        while let Some(c) = self.peek() {
+            self.end_tokens();
+            self.start = self.position;
             match c {
                 '{' => self.tokens.push(self.make_token(TokenType::OpenBlock)),
                 '}' => self.tokens.push(self.make_token(TokenType::CloseBlock)),
                 '(' => self.tokens.push(self.make_token(TokenType::OpenParen)),
                 ')' => self.tokens.push(self.make_token(TokenType::CloseParen)),
+                '#' => self.tokens.push(self.make_token(TokenType::Attribute)),
+                '[' => self.tokens.push(self.make_token(TokenType::OpenBracket)),
+                ']' => self.tokens.push(self.make_token(TokenType::CloseBracket)),
                 ';' => self.tokens.push(self.make_token(TokenType::Semicolon)),
                 ',' => self.tokens.push(self.make_token(TokenType::Comma)),
                 '+' => self.tokens.push(self.make_token(TokenType::BinaryOperator {
@@ -207,12 +286,22 @@ impl<'a> Lexer<'a> {
                     value: "*".to_string(),
                 })),
                 '/' => {
-                    self.contents.next();
-                    if let Some('/') = self.contents.peek() {
-                        while let Some(next) = self.contents.next() {
+                    self.next();
+                    if let Some('/') = self.peek() {
+                        self.next();
+                        let mut comment = String::new();
+                        while let Some(next) = self.next() {
                             if next == '\n' {
                                 break;
                             }
+                            comment.push(next);
+                        }
+                        if let Some(value) = comment.strip_prefix('/') {
+                            if !value.starts_with('/') {
+                                self.tokens.push(self.make_token(TokenType::DocComment {
+                                    value: value.to_string(),
+                                }));
+                            }
                         }
                     } else {
                         self.tokens.push(self.make_token(TokenType::BinaryOperator {
@@ -228,16 +317,19 @@ impl<'a> Lexer<'a> {
                 '%' => self.tokens.push(self.make_token(TokenType::BinaryOperator {
                     value: "%".to_string(),
                 })),
+                '~' => self.tokens.push(self.make_token(TokenType::BinaryOperator {
+                    value: "~".to_string(),
+                })),
                 ':' => self.tokens.push(self.make_token(TokenType::Colon)),
                 '<' => {
-                    self.contents.next();
+                    self.next();
                     let mut length = 1;
-                    if let Some('=') = self.contents.peek() {
+                    if let Some('=') = self.peek() {
                         self.tokens.push(self.make_token(TokenType::BinaryOperator {
                             value: "<=".to_string(),
                         }));
                         length += 1;
-                        self.contents.next();
+                        self.next();
                     } else {
                         self.tokens.push(self.make_token(TokenType::BinaryOperator {
                             value: "<".to_string(),
@@ -247,30 +339,31 @@ impl<'a> Lexer<'a> {
                     continue;
                 }
                 '>' => {
-                    self.contents.next();
+                    self.next();
                     let mut length = 1;
-                    if let Some('=') = self.contents.peek() {
+                    if let Some('=') = self.peek() {
                         self.tokens.push(self.make_token(TokenType::BinaryOperator {
                             value: ">=".to_string(),
                         }));
                         length += 1;
-                        self.contents.next();
+                        self.next();
                     } else {
                         self.tokens.push(self.make_token(TokenType::BinaryOperator {
                             value: ">".to_string(),
                         }));
                     }
                     self.column += length;
+                    continue;
                 }
                 '=' => {
-                    self.contents.next();
+                    self.next();
                     let mut length = 1;
-                    if let Some('=') = self.contents.peek() {
+                    if let Some('=') = self.peek() {
                         self.tokens.push(self.make_token(TokenType::BinaryOperator {
                             value: "==".to_string(),
                         }));
                         length += 1;
-                        self.contents.next();
+                        self.next();
                     } else {
                         self.tokens
                             .push(self.make_token(TokenType::AssignmentOperator));
@@ -279,14 +372,14 @@ impl<'a> Lexer<'a> {
                     continue;
                 }
                 '!' => {
-                    self.contents.next();
+                    self.next();
                     let mut length = 1;
-                    if let Some('=') = self.contents.peek() {
+                    if let Some('=') = self.peek() {
                         self.tokens.push(self.make_token(TokenType::BinaryOperator {
                             value: "!=".to_string(),
                         }));
                         length += 1;
-                        self.contents.next();
+                        self.next();
                     } else {
                         self.tokens.push(self.make_token(TokenType::UnaryOperator {
                             value: "!".to_string(),
@@ -296,31 +389,39 @@ impl<'a> Lexer<'a> {
                     continue;
                 }
                 '&' => {
-                    self.contents.next();
+                    self.next();
                     let mut length = 1;
-                    if let Some('&') = self.contents.peek() {
+                    if let Some('&') = self.peek() {
                         self.tokens.push(self.make_token(TokenType::BinaryOperator {
                             value: "&&".to_string(),
                         }));
                         length += 1;
-                        self.contents.next();
+                        self.next();
                     } else {
-                        panic!("Unexpected character: \"&\"");
+                        // Lexed as `&&`, which is most likely what was meant
+                        self.unexpected_character('&', self.column, self.start);
+                        self.tokens.push(self.make_token(TokenType::BinaryOperator {
+                            value: "&&".to_string(),
+                        }));
                     }
                     self.column += length;
                     continue;
                 }
                 '|' => {
-                    self.contents.next();
+                    self.next();
                     let mut length = 1;
-                    if let Some('|') = self.contents.peek() {
+                    if let Some('|') = self.peek() {
                         self.tokens.push(self.make_token(TokenType::BinaryOperator {
                             value: "||".to_string(),
                         }));
                         length += 1;
-                        self.contents.next();
+                        self.next();
                     } else {
-                        panic!("Unexpected character: \"|\"");
+                        // Lexed as `||`, which is most likely what was meant
+                        self.unexpected_character('|', self.column, self.start);
+                        self.tokens.push(self.make_token(TokenType::BinaryOperator {
+                            value: "||".to_string(),
+                        }));
                     }
                     self.column += length;
                     continue;
@@ -348,15 +449,17 @@ impl<'a> Lexer<'a> {
                 '\n' => {
                     self.row += 1;
                     self.column = 1;
-                    self.contents.next();
+                    self.next();
                     continue;
                 }
                 ' ' | '\t' => (),
-                _ => panic!("Unexpected character: \"{}\"", c),
+                // Skipped, so the rest of the script is still checked
+                _ => self.unexpected_character(c, self.column, self.start),
             }
             self.column += 1;
-            self.contents.next();
+            self.next();
         }
+        self.end_tokens();
 
         TokenCollection::new(self.tokens.clone())
     }
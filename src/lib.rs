@@ -0,0 +1,46 @@
+//! Runs test scripts like the `test-script` binary does. An embedder can
+//! add builtins of its own with `plugin::register` before calling `run`, and
+//! check a script without running it with `check_source`.
+
+mod artifacts;
+mod cases;
+pub mod check;
+mod cli;
+mod config;
+mod coverage;
+mod diagnostic;
+mod diff;
+mod doc;
+mod environment;
+mod error;
+mod exitcode;
+mod explain;
+mod grammar;
+mod history;
+mod init;
+mod instruction;
+mod interpreter;
+mod lexer;
+mod manifest;
+mod parser;
+pub mod plugin;
+mod process;
+mod profile;
+mod regex;
+mod render;
+mod sandbox;
+mod suggestion;
+mod symbol;
+mod test;
+mod token;
+mod r#type;
+mod type_checker;
+mod variable;
+mod white_listed_constants;
+
+pub use check::check_source;
+
+/// Runs `test-script` with the arguments of the process
+pub fn run() {
+    cli::run();
+}
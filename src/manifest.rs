@@ -0,0 +1,258 @@
+use crate::cli::Args;
+use crate::instruction::Instruction;
+use crate::process::{self, ProcessOptions};
+
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+/// What a run depended on and what it resulted in, written with `--manifest`
+/// and compared with a new run by `--verify-manifest`. Nothing in it depends
+/// on when or where the run was, so two runs of the same script on the same
+/// files with the same seed write the same manifest
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    version: String,
+    seed: u64,
+    script: String,
+    /// The settings that can change the result of a test
+    settings: Map<String, Value>,
+    /// The SHA-256 of the script, the config file, the plugins and the files
+    /// that the tests run, by their path
+    files: BTreeMap<String, String>,
+    /// Whether every test that ran passed, by its name
+    tests: BTreeMap<String, bool>,
+}
+
+impl Manifest {
+    pub fn new(args: &Args, seed: u64, program: &[Instruction]) -> Self {
+        let mut manifest = Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            seed,
+            ..Default::default()
+        };
+        manifest.settings = match json!({
+            "filter": args.filter,
+            "max_size": args.max_size,
+            "max_combinations": args.max_combinations,
+            "timeout": args.timeout.as_secs_f64(),
+            "pty": args.pty,
+            "sandbox": args.sandbox,
+            "wrapper": args.wrapper,
+            "keep_line_endings": args.keep_line_endings,
+            "keep_trailing_whitespace": args.keep_trailing_whitespace,
+            "collapse_whitespace": args.collapse_whitespace,
+            "strip_ansi": args.strip_ansi,
+            "float_epsilon": args.float_epsilon,
+            "number_tolerance": args.number_tolerance,
+            "suites": args
+                .suites
+                .iter()
+                .map(|(name, suite)| {
+                    let suite = json!({
+                        "keep_line_endings": suite.keep_line_endings,
+                        "keep_trailing_whitespace": suite.keep_trailing_whitespace,
+                        "collapse_whitespace": suite.collapse_whitespace,
+                        "strip_ansi": suite.strip_ansi,
+                        "number_tolerance": suite.number_tolerance,
+                    });
+                    (name.clone(), suite)
+                })
+                .collect::<Map<String, Value>>(),
+        }) {
+            Value::Object(settings) => settings,
+            _ => unreachable!(),
+        };
+        if let Some(instruction) = program.first() {
+            manifest.script = display(Path::new(&*instruction.token.file));
+            manifest.files.insert(
+                manifest.script.clone(),
+                hash(instruction.token.source.as_bytes()),
+            );
+        }
+        for file in args.config_file.iter().chain(&args.plugins) {
+            manifest.file(file);
+        }
+        manifest
+    }
+
+    /// Adds the files that a test runs with `command`
+    pub fn command(&mut self, command: &str, options: &ProcessOptions) {
+        let cwd = match &options.cwd {
+            Some(cwd) => cwd.clone(),
+            None => std::env::current_dir().unwrap_or_default(),
+        };
+        for part in options.wrappers.iter().map(String::as_str).chain([command]) {
+            for file in process::files(part, &cwd) {
+                self.file(&file);
+            }
+        }
+    }
+
+    fn file(&mut self, path: &Path) {
+        // A file that can not be read is missing from the manifest, which a
+        // verification reports
+        if let Ok(contents) = std::fs::read(path) {
+            self.files.insert(display(path), hash(&contents));
+        }
+    }
+
+    pub fn result(&mut self, test: &str, passed: bool) {
+        self.tests.insert(test.to_string(), passed);
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+
+    pub fn filter(&self) -> Vec<String> {
+        self.settings
+            .get("filter")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|filter| filter.as_str().map(str::to_string))
+            .collect()
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let tests = self
+            .tests
+            .iter()
+            .map(|(test, passed)| {
+                let result = if *passed { "passed" } else { "failed" };
+                (test.clone(), Value::from(result))
+            })
+            .collect::<Map<String, Value>>();
+        let manifest = json!({
+            "version": self.version,
+            "seed": self.seed,
+            "script": self.script,
+            "settings": self.settings,
+            "files": self.files,
+            "tests": tests,
+        });
+        let mut contents = serde_json::to_string_pretty(&manifest)?;
+        contents.push('\n');
+        std::fs::write(path, contents)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let manifest = serde_json::from_str::<Value>(&contents).map_err(|e| e.to_string())?;
+        let field = |name: &str| {
+            manifest
+                .get(name)
+                .ok_or_else(|| format!("`{}` is missing", name))
+        };
+        let invalid = |name: &str| format!("`{}` is not valid", name);
+        let strings = |name: &str| -> Result<BTreeMap<String, String>, String> {
+            field(name)?
+                .as_object()
+                .ok_or_else(|| invalid(name))?
+                .iter()
+                .map(|(key, value)| match value.as_str() {
+                    Some(value) => Ok((key.clone(), value.to_string())),
+                    None => Err(invalid(name)),
+                })
+                .collect()
+        };
+
+        Ok(Self {
+            version: field("version")?
+                .as_str()
+                .ok_or_else(|| invalid("version"))?
+                .to_string(),
+            seed: field("seed")?.as_u64().ok_or_else(|| invalid("seed"))?,
+            script: field("script")?
+                .as_str()
+                .ok_or_else(|| invalid("script"))?
+                .to_string(),
+            settings: field("settings")?
+                .as_object()
+                .ok_or_else(|| invalid("settings"))?
+                .clone(),
+            files: strings("files")?,
+            tests: strings("tests")?
+                .into_iter()
+                .map(|(test, result)| match result.as_str() {
+                    "passed" => Ok((test, true)),
+                    "failed" => Ok((test, false)),
+                    _ => Err(invalid("tests")),
+                })
+                .collect::<Result<_, String>>()?,
+        })
+    }
+
+    /// Everything in this run that is not like in the run of `expected`
+    pub fn differences(&self, expected: &Manifest) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.version != expected.version {
+            differences.push(format!(
+                "This is test-script {}, the manifest is written by {}",
+                self.version, expected.version
+            ));
+        }
+        for (setting, value) in &self.settings {
+            match expected.settings.get(setting) {
+                Some(expected) if expected == value => (),
+                Some(expected) => differences.push(format!(
+                    "`{}` is {}, it is {} in the manifest",
+                    setting, value, expected
+                )),
+                None => differences.push(format!("`{}` is not in the manifest", setting)),
+            }
+        }
+        for (file, hash) in &expected.files {
+            match self.files.get(file) {
+                Some(expected) if expected == hash => (),
+                Some(_) => differences.push(format!("`{}` has changed", file)),
+                None => differences.push(format!("`{}` was not used", file)),
+            }
+        }
+        for file in self.files.keys() {
+            if !expected.files.contains_key(file) {
+                differences.push(format!("`{}` is not in the manifest", file));
+            }
+        }
+        for (test, passed) in &expected.tests {
+            match self.tests.get(test) {
+                Some(expected) if expected == passed => (),
+                Some(true) => differences.push(format!("`{}` passed, it failed before", test)),
+                Some(false) => differences.push(format!("`{}` failed, it passed before", test)),
+                None => differences.push(format!("`{}` did not run", test)),
+            }
+        }
+        for test in self.tests.keys() {
+            if !expected.tests.contains_key(test) {
+                differences.push(format!("`{}` is not in the manifest", test));
+            }
+        }
+        differences
+    }
+}
+
+fn hash(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The path relative to the current directory if it is in it, so the
+/// manifest does not depend on where the project is
+fn display(path: &Path) -> String {
+    let current_directory = std::env::current_dir().unwrap_or_default();
+    path.strip_prefix(&current_directory)
+        .unwrap_or(path)
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect::<PathBuf>()
+        .display()
+        .to_string()
+}
@@ -1,18 +1,64 @@
 use crate::cli::Args;
 use crate::environment::ParseEnvironment;
-use crate::error::{ParseError, ParseErrorType, ParseWarning, ParseWarningType};
-use crate::instruction::{BinaryOperator, BuiltIn, Instruction, InstructionType, UnaryOperator};
+use crate::error::{ParseError, ParseErrorType, ParseWarning, ParseWarningType, WARNING_NAMES};
+use crate::instruction::{
+    self, Attribute, AttributeTarget, BinaryOperator, BuiltIn, Instruction, InstructionType,
+    UnaryOperator,
+};
+use crate::plugin;
 use crate::r#type::Type;
 use crate::regex;
+use crate::symbol::Symbol;
 use crate::token::{Token, TokenCollection, TokenType};
 use crate::variable::{SnakeCase, Variable};
 use crate::white_listed_constants;
 
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Checks a regex literal that is used without being expanded.
+type RegexCheck = fn(&Token) -> Result<(), ParseError>;
+
+const ANYWHERE: &[AttributeTarget] = &[
+    AttributeTarget::Test,
+    AttributeTarget::Suite,
+    AttributeTarget::Function,
+    AttributeTarget::Statement,
+];
+
+/// Every attribute and what it can be written in front of. Doc comments are
+/// stored as `doc` attributes
+const ATTRIBUTES: [(&str, &[AttributeTarget]); 11] = [
+    ("doc", ANYWHERE),
+    ("allow", ANYWHERE),
+    ("cwd", &[AttributeTarget::Test]),
+    ("wrapper", &[AttributeTarget::Test]),
+    ("stdin", &[AttributeTarget::Test]),
+    ("stdout_to", &[AttributeTarget::Test]),
+    ("max_memory", &[AttributeTarget::Test]),
+    ("max_cpu_time", &[AttributeTarget::Test]),
+    ("max_file_size", &[AttributeTarget::Test]),
+    ("max_runtime", &[AttributeTarget::Test]),
+    ("pty", &[AttributeTarget::Test]),
+];
+
 pub struct Parser {
     tokens: TokenCollection,
     environment: ParseEnvironment,
     args: Args,
     in_constant_declaration: bool,
+    /// Set while parsing an operand that is used without being expanded,
+    /// together with the check that replaces the expansion and the
+    /// precedence a binary operator after it needs to combine it.
+    unexpanded_regex: Option<(RegexCheck, u8)>,
+    /// Every regex that has been expanded, so a pattern that is used in many
+    /// places is only expanded once and its strings are shared.
+    expanded_regexes: HashMap<String, Rc<[String]>>,
+    /// Errors inside arguments and parentheses that were recovered from in
+    /// the current instruction, which is dropped once it has been parsed.
+    recovered_errors: usize,
+    /// The names of the suites around the current test, outermost first
+    suites: Vec<String>,
     success: bool,
 }
 
@@ -23,75 +69,409 @@ impl Parser {
             environment: ParseEnvironment::new(args.clone()),
             args,
             in_constant_declaration: false,
+            unexpanded_regex: None,
+            expanded_regexes: HashMap::new(),
+            recovered_errors: 0,
+            suites: Vec::new(),
             success: true,
         };
     }
 
     pub fn parse(&mut self) -> Result<Vec<Instruction>, Vec<Instruction>> {
         let mut program = Vec::new();
+        self.declare_globals();
 
-        while let Some(token) = self.tokens.peek() {
-            let instruction = match token.clone().r#type {
-                TokenType::Identifier { .. } => self.parse_test(),
-                TokenType::Keyword { value } => match value.as_str() {
-                    "const" => self.parse_statement(),
-                    "fn" => self.parse_function(),
-                    _ => {
-                        self.tokens.advance_to_next_instruction();
-                        Err(ParseError::new(
-                            ParseErrorType::GlobalScope(token.clone().r#type),
-                            token,
-                        ))
-                    }
-                },
-                TokenType::OpenBlock | TokenType::CloseBlock => {
-                    self.tokens.next();
-                    Err(ParseError::new(
-                        ParseErrorType::GlobalScope(token.clone().r#type),
-                        token.clone(),
-                    ))
-                }
-
-                r#type => {
-                    self.tokens.advance_to_next_instruction();
-                    Err(ParseError::new(
-                        ParseErrorType::GlobalScope(r#type),
-                        token.clone(),
-                    ))
-                }
-            };
-
-            match instruction {
-                Ok(instruction) => program.push(instruction),
-                Err(e) => e.print(),
+        while self.tokens.peek().is_some() {
+            if let Some(instruction) = self.parse_global() {
+                program.push(instruction);
             }
         }
 
+        self.order_constants(&mut program);
+
         match self.success {
             true => Ok(program),
             false => Err(program),
         }
     }
 
-    fn parse_statement(&mut self) -> Result<Instruction, ParseError> {
-        let instruction = self.parse_expression(true, true)?;
-        match self.end_statement() {
-            Ok(_) => (),
+    /// Parses a test, function, constant or suite with its attributes, or
+    /// prints why it could not be parsed. Only tests and suites can be
+    /// declared in a suite
+    fn parse_global(&mut self) -> Option<Instruction> {
+        let attributes = match self.parse_attributes() {
+            Ok(attributes) => attributes,
             Err(e) => {
                 e.print();
                 self.success = false;
+                return None;
+            }
+        };
+        let token = self.tokens.peek()?;
+        let scope_error = |r#type: TokenType| match self.suites.is_empty() {
+            true => ParseErrorType::GlobalScope(r#type),
+            false => ParseErrorType::SuiteScope(r#type),
+        };
+
+        let allowed_warnings = self.environment.allowed_warnings.len();
+        self.environment.allow_warnings(&attributes);
+        let recovered_errors = std::mem::take(&mut self.recovered_errors);
+        let instruction = match token.clone().r#type {
+            TokenType::Identifier { .. } => self.parse_test(),
+            TokenType::Keyword { value } => match value.as_str() {
+                "suite" => self.parse_suite(),
+                "const" if self.suites.is_empty() => self.parse_statement(),
+                "fn" if self.suites.is_empty() => self.parse_function(),
+                _ => {
+                    self.tokens.advance_to_next_instruction();
+                    Err(ParseError::new(scope_error(token.clone().r#type), token))
+                }
+            },
+            TokenType::OpenBlock | TokenType::CloseBlock => {
+                self.tokens.next();
+                Err(ParseError::new(
+                    scope_error(token.clone().r#type),
+                    token.clone(),
+                ))
+            }
+
+            r#type => {
+                self.tokens.advance_to_next_instruction();
+                Err(ParseError::new(scope_error(r#type), token.clone()))
+            }
+        };
+
+        self.environment.allowed_warnings.truncate(allowed_warnings);
+        let instruction = self.drop_recovered(instruction, recovered_errors);
+
+        match instruction {
+            Ok(mut instruction) => {
+                self.check_attribute_targets(&attributes, &instruction);
+                instruction.attributes = attributes;
+                Some(instruction)
+            }
+            Err(e) => {
+                e.print();
+                None
+            }
+        }
+    }
+
+    /// Parses `suite "name" { ... }`, whose tests are named `name::test`
+    fn parse_suite(&mut self) -> Result<Instruction, ParseError> {
+        let token = self.get_next_token()?;
+        // The name of a suite is not a magic string, like the command of a test
+        self.in_constant_declaration = true;
+        let name = self.parse_literal_string();
+        self.in_constant_declaration = false;
+        let name = name?;
+        self.expect_token(TokenType::OpenBlock)?;
+        let open = self.tokens.current().unwrap();
+        let empty = self.peek_next_token()?.r#type == TokenType::CloseBlock;
+
+        self.suites.push(name.clone());
+        let mut instructions = Vec::new();
+        loop {
+            match self.tokens.peek().map(|token| token.r#type) {
+                Some(TokenType::CloseBlock) => break,
+                Some(_) => instructions.extend(self.parse_global()),
+                None => {
+                    self.suites.pop();
+                    return Err(ParseError::new(
+                        ParseErrorType::UnclosedDelimiter(TokenType::OpenBlock),
+                        open,
+                    ));
+                }
             }
         }
+        self.suites.pop();
+        self.get_next_token()?;
+
+        if empty {
+            ParseWarning::new(ParseWarningType::EmptyBlock, open).print(
+                self.args.disable_warnings,
+                &self.environment.allowed_warnings,
+            )
+        }
+        Ok(Instruction::new(
+            InstructionType::Suite { name, instructions },
+            token,
+        ))
+    }
+
+    fn parse_statement(&mut self) -> Result<Instruction, ParseError> {
+        let attributes = self.parse_attributes()?;
+
+        let allowed_warnings = self.environment.allowed_warnings.len();
+        self.environment.allow_warnings(&attributes);
+        let recovered_errors = std::mem::take(&mut self.recovered_errors);
+        let instruction = self.parse_expression(true, true);
+        let instruction = match instruction {
+            Ok(instruction) => match self.end_statement(&instruction) {
+                Ok(_) => Ok(instruction),
+                Err(e) => {
+                    e.print();
+                    self.success = false;
+                    Ok(instruction)
+                }
+            },
+            Err(e) => Err(e),
+        };
+        self.environment.allowed_warnings.truncate(allowed_warnings);
+        let instruction = self.drop_recovered(instruction, recovered_errors);
 
+        let mut instruction = instruction?;
+        self.check_attribute_targets(&attributes, &instruction);
+        instruction.attributes.extend(attributes);
         Ok(instruction)
     }
 
+    /// An instruction that contains a recovered error is incomplete, so it is
+    /// dropped instead of being type checked. The errors are already printed.
+    fn drop_recovered(
+        &mut self,
+        instruction: Result<Instruction, ParseError>,
+        recovered_errors: usize,
+    ) -> Result<Instruction, ParseError> {
+        let recovered = std::mem::replace(&mut self.recovered_errors, recovered_errors) > 0;
+        match instruction {
+            Ok(_) if recovered => Err(ParseError::none()),
+            instruction => instruction,
+        }
+    }
+
+    /// Reports an error inside an argument or parentheses, and skips the rest
+    /// of the argument so the parser can continue after it
+    fn recover_argument(
+        &mut self,
+        error: ParseError,
+        start: (usize, bool),
+    ) -> Result<(), ParseError> {
+        error.print();
+        self.success = false;
+        self.tokens.rewind(start);
+        if !self.tokens.advance_to_next_argument() {
+            self.tokens.advance_to_next_instruction();
+            return Err(ParseError::none());
+        }
+        self.recovered_errors += 1;
+        Ok(())
+    }
+
+    fn parse_attributes(&mut self) -> Result<Vec<Attribute>, ParseError> {
+        let mut attributes = Vec::new();
+        while let Some(token) = self.tokens.peek() {
+            match token.r#type {
+                TokenType::Attribute => attributes.push(self.parse_attribute()?),
+                TokenType::DocComment { .. } => {
+                    self.tokens.next();
+                    attributes.push(Attribute {
+                        name: String::from("doc"),
+                        arguments: vec![token.clone()],
+                        token,
+                    });
+                }
+                _ => break,
+            }
+        }
+        Ok(attributes)
+    }
+
+    fn parse_attribute(&mut self) -> Result<Attribute, ParseError> {
+        let token = self.get_next_token()?;
+        self.expect_token(TokenType::OpenBracket)?;
+        let identifier = self.get_next_token()?;
+        let name = match &identifier.r#type {
+            TokenType::Identifier { value } => value.clone(),
+            r#type => {
+                self.tokens.advance_to_next_instruction();
+                return Err(ParseError::new(
+                    ParseErrorType::MismatchedTokenType {
+                        expected: TokenType::Identifier {
+                            value: String::new(),
+                        },
+                        actual: r#type.clone(),
+                    },
+                    identifier,
+                ));
+            }
+        };
+
+        let mut arguments = Vec::new();
+        if self.peek_next_token()?.r#type == TokenType::OpenParen {
+            self.get_next_token()?;
+            while self.peek_next_token()?.r#type != TokenType::CloseParen {
+                arguments.push(self.get_next_token()?);
+                match self.peek_next_token()?.r#type {
+                    TokenType::Comma => {
+                        self.get_next_token()?;
+                    }
+                    TokenType::CloseParen => (),
+                    _ => {
+                        self.tokens.advance_to_next_instruction();
+                        return Err(ParseError::new(
+                            ParseErrorType::MismatchedTokenType {
+                                expected: TokenType::Comma,
+                                actual: self.peek_next_token()?.r#type,
+                            },
+                            self.peek_next_token()?,
+                        ));
+                    }
+                }
+            }
+            self.get_next_token()?;
+        }
+        self.expect_token(TokenType::CloseBracket)?;
+
+        if !ATTRIBUTES.iter().any(|(attribute, _)| *attribute == name) {
+            return Err(ParseError::new(
+                ParseErrorType::UnknownAttribute(name),
+                identifier,
+            ));
+        }
+
+        match name.as_str() {
+            "allow" => {
+                for argument in &arguments {
+                    let warning = match &argument.r#type {
+                        TokenType::Identifier { value } => value.clone(),
+                        r#type => r#type.to_string(),
+                    };
+                    if !WARNING_NAMES.contains(&warning.as_str()) {
+                        return Err(ParseError::new(
+                            ParseErrorType::UnknownWarning(warning),
+                            argument.clone(),
+                        ));
+                    }
+                }
+            }
+            "cwd" | "wrapper" | "stdin" => {
+                if arguments.len() != 1 {
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedArguments {
+                            expected: 1,
+                            actual: arguments.len(),
+                        },
+                        identifier,
+                    ));
+                }
+                if !matches!(arguments[0].r#type, TokenType::StringLiteral { .. }) {
+                    let expected = match name.as_str() {
+                        "cwd" => "a string with the path of a directory",
+                        "stdin" => "a string with the path of a file",
+                        _ => "a string with a command",
+                    };
+                    return Err(ParseError::new(
+                        ParseErrorType::InvalidAttributeArgument {
+                            attribute: name,
+                            expected: String::from(expected),
+                        },
+                        arguments[0].clone(),
+                    ));
+                }
+            }
+            "stdout_to" => {
+                if arguments.is_empty() || arguments.len() > 2 {
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedArguments {
+                            expected: 1,
+                            actual: arguments.len(),
+                        },
+                        identifier,
+                    ));
+                }
+                if !matches!(arguments[0].r#type, TokenType::StringLiteral { .. }) {
+                    return Err(ParseError::new(
+                        ParseErrorType::InvalidAttributeArgument {
+                            attribute: name,
+                            expected: String::from("a string with the path of a file"),
+                        },
+                        arguments[0].clone(),
+                    ));
+                }
+                if let Some(mode) = arguments.get(1) {
+                    if !matches!(&mode.r#type, TokenType::Identifier { value } if value == "tee") {
+                        return Err(ParseError::new(
+                            ParseErrorType::InvalidAttributeArgument {
+                                attribute: name,
+                                expected: String::from("`tee` after the path"),
+                            },
+                            mode.clone(),
+                        ));
+                    }
+                }
+            }
+            "max_memory" | "max_cpu_time" | "max_file_size" | "max_runtime" => {
+                if arguments.len() != 1 {
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedArguments {
+                            expected: 1,
+                            actual: arguments.len(),
+                        },
+                        identifier,
+                    ));
+                }
+                if !matches!(arguments[0].r#type, TokenType::IntegerLiteral { value } if value > 0)
+                {
+                    let unit = match name.as_str() {
+                        "max_cpu_time" => "seconds",
+                        "max_runtime" => "milliseconds",
+                        _ => "megabytes",
+                    };
+                    return Err(ParseError::new(
+                        ParseErrorType::InvalidAttributeArgument {
+                            attribute: name,
+                            expected: format!("a positive number of {}", unit),
+                        },
+                        arguments[0].clone(),
+                    ));
+                }
+            }
+            "pty" => {
+                if !arguments.is_empty() && arguments.len() != 2 {
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedArguments {
+                            expected: 2,
+                            actual: arguments.len(),
+                        },
+                        identifier,
+                    ));
+                }
+                for argument in &arguments {
+                    match argument.r#type {
+                        TokenType::IntegerLiteral { value }
+                            if value > 0 && value <= u16::MAX as i64 => {}
+                        _ => {
+                            return Err(ParseError::new(
+                                ParseErrorType::InvalidAttributeArgument {
+                                    attribute: name,
+                                    expected: format!(
+                                        "a number of rows and columns between 1 and {}",
+                                        u16::MAX
+                                    ),
+                                },
+                                argument.clone(),
+                            ))
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        Ok(Attribute {
+            name,
+            arguments,
+            token,
+        })
+    }
+
     fn parse_expression(
         &mut self,
         parse_binary: bool,
         parse_type_cast: bool,
     ) -> Result<Instruction, ParseError> {
-        let mut token = self.peek_next_token()?;
+        let token = self.peek_next_token()?;
         let mut instruction = match &token.r#type {
             TokenType::StringLiteral { .. } => self.parse_string_literal()?,
             TokenType::RegexLiteral { .. } => self.parse_regex_literal()?,
@@ -128,12 +508,15 @@ impl Parser {
                 ));
             }
         };
+        let start = token;
+        self.extend_span(&mut instruction, &start);
 
-        token = self.peek_next_token()?;
-        while token.binary_operator() {
+        // A block may be the last thing in the file, so there may be no token
+        // after the expression
+        while let Some(token) = self.tokens.peek().filter(Token::binary_operator) {
             instruction = match token.r#type {
                 TokenType::BinaryOperator { .. } => match parse_binary {
-                    true => self.parse_binary_operator(instruction)?,
+                    true => self.parse_binary_operator(instruction, 0)?,
                     false => break,
                 },
                 TokenType::TypeCast => match parse_type_cast {
@@ -143,12 +526,43 @@ impl Parser {
                 TokenType::AssignmentOperator => self.parse_assignment(&instruction)?,
                 _ => unreachable!(),
             };
-            token = self.peek_next_token()?;
+            self.extend_span(&mut instruction, &start);
         }
 
         Ok(instruction)
     }
 
+    /// Makes the span of `instruction` reach from `start` to the last token
+    /// that has been parsed
+    fn extend_span(&self, instruction: &mut Instruction, start: &Token) {
+        if let Some(end) = self.tokens.current() {
+            instruction.span = start.to(&end);
+        }
+    }
+
+    /// Reports the attributes that can not be written in front of the
+    /// instruction, which is kept as if they were not there
+    fn check_attribute_targets(&mut self, attributes: &[Attribute], instruction: &Instruction) {
+        let target = instruction.attribute_target();
+        for attribute in attributes {
+            let allowed = ATTRIBUTES
+                .iter()
+                .any(|(name, targets)| *name == attribute.name && targets.contains(&target));
+            if !allowed {
+                ParseError::new(
+                    ParseErrorType::MisplacedAttribute {
+                        attribute: attribute.name.clone(),
+                        target,
+                    },
+                    attribute.token.clone(),
+                )
+                .with_label(instruction.token.clone(), format!("this is {target}"))
+                .print();
+                self.success = false;
+            }
+        }
+    }
+
     fn parse_test(&mut self) -> Result<Instruction, ParseError> {
         let token = self.get_next_token()?;
         let name = match &token.r#type {
@@ -157,22 +571,298 @@ impl Parser {
         };
         self.expect_token(TokenType::OpenParen)?;
         self.in_constant_declaration = true;
-        let path = self.parse_string_literal()?;
-        let path = match path.r#type {
-            InstructionType::StringLiteral(path) => path,
-            _ => unreachable!(),
-        };
+        let header = self.parse_test_header();
         self.in_constant_declaration = false;
+        let (command, env) = header?;
         self.expect_token(TokenType::CloseParen)?;
         let instruction = self.parse_statement()?;
+        match instruction.r#type {
+            InstructionType::Block { .. } | InstructionType::None => (),
+            _ => ParseWarning::new(
+                ParseWarningType::NoBlock(&self.tokens.current().unwrap()),
+                instruction.token.clone(),
+            )
+            .print(
+                self.args.disable_warnings || self.args.disable_style_warnings,
+                &self.environment.allowed_warnings,
+            ),
+        }
 
+        let name = self
+            .suites
+            .iter()
+            .map(String::as_str)
+            .chain([name.as_str()])
+            .collect::<Vec<&str>>()
+            .join("::");
         Ok(Instruction::new(
-            InstructionType::Test(Box::new(instruction), name.to_string(), path.into()),
+            InstructionType::Test {
+                name,
+                command: Box::new(command),
+                env,
+                instruction: Box::new(instruction),
+            },
             token,
         ))
     }
 
-    fn parse_function(&mut self) -> Result<Instruction, ParseError> {
+    fn parse_test_header(&mut self) -> Result<(Instruction, Vec<(String, String)>), ParseError> {
+        let command = self.parse_expression(true, true)?;
+        let env = match self.peek_next_token()?.r#type {
+            TokenType::Comma => {
+                self.get_next_token()?;
+                self.parse_environment()?
+            }
+            _ => Vec::new(),
+        };
+        Ok((command, env))
+    }
+
+    fn parse_environment(&mut self) -> Result<Vec<(String, String)>, ParseError> {
+        self.expect_token(TokenType::Identifier {
+            value: String::from("env"),
+        })?;
+        self.expect_token(TokenType::AssignmentOperator)?;
+        self.expect_token(TokenType::OpenBlock)?;
+
+        let mut env = Vec::new();
+        while self.peek_next_token()?.r#type != TokenType::CloseBlock {
+            let key = self.parse_literal_string()?;
+            self.expect_token(TokenType::Colon)?;
+            let value = self.parse_literal_string()?;
+            env.push((key, value));
+
+            match self.peek_next_token()?.r#type {
+                TokenType::Comma => {
+                    self.get_next_token()?;
+                }
+                TokenType::CloseBlock => (),
+                r#type => {
+                    self.tokens.advance_to_next_instruction();
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedTokenType {
+                            expected: TokenType::Comma,
+                            actual: r#type,
+                        },
+                        self.peek_next_token()?,
+                    ));
+                }
+            }
+        }
+        self.get_next_token()?;
+        Ok(env)
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, ParseError> {
+        let token = self.peek_next_token()?;
+        match &token.r#type {
+            TokenType::StringLiteral { .. } => match self.parse_string_literal()?.r#type {
+                InstructionType::StringLiteral(value) => Ok(value),
+                _ => unreachable!(),
+            },
+            r#type => {
+                self.tokens.advance_to_next_instruction();
+                Err(ParseError::new(
+                    ParseErrorType::MismatchedTokenType {
+                        expected: TokenType::StringLiteral {
+                            value: String::from("string"),
+                        },
+                        actual: r#type.clone(),
+                    },
+                    token,
+                ))
+            }
+        }
+    }
+
+    /// Declares the signature of every global function and the name and type
+    /// of every global constant before anything else is parsed, so both can
+    /// be used above their declaration. Errors in a signature or declaration
+    /// are reported when it is parsed itself
+    fn declare_globals(&mut self) {
+        let start = self.tokens.position();
+        let mut depth = 0usize;
+        while let Some(token) = self.tokens.peek() {
+            match &token.r#type {
+                TokenType::OpenBlock => depth += 1,
+                TokenType::CloseBlock => depth = depth.saturating_sub(1),
+                TokenType::Keyword { value } if value == "fn" && depth == 0 => {
+                    if let Ok(function) = self.parse_signature() {
+                        self.environment.add_function(Rc::new(function));
+                    }
+                    continue;
+                }
+                TokenType::Keyword { value } if value == "const" && depth == 0 => {
+                    let position = self.tokens.position();
+                    match self.parse_constant_header() {
+                        Ok(variable) => self.environment.insert(variable),
+                        Err(_) => self.tokens.rewind(position),
+                    }
+                }
+                _ => (),
+            }
+            self.tokens.next();
+        }
+        self.tokens.rewind(start);
+    }
+
+    /// Parses `const NAME: type =` into the constant it declares
+    fn parse_constant_header(&mut self) -> Result<Variable, ParseError> {
+        let token = self.get_next_token()?;
+        let identifier = self.get_next_token()?;
+        let type_token = match self.get_next_token()?.r#type {
+            TokenType::Colon => self.get_next_token()?,
+            _ => return Err(ParseError::none()),
+        };
+        let assignment = self.get_next_token()?;
+        match (&identifier.r#type, &type_token.r#type, &assignment.r#type) {
+            (
+                TokenType::Identifier { value: name },
+                TokenType::Type { value: r#type },
+                TokenType::AssignmentOperator,
+            ) => Ok(Variable {
+                name: name.clone(),
+                symbol: Symbol::intern(name),
+                r#const: true,
+                r#type: *r#type,
+                declaration_token: token,
+                identifier_token: identifier.clone(),
+                type_token: type_token.clone(),
+                last_assignment_token: assignment.clone(),
+                read: true,
+                assigned: true,
+                allowed_warnings: Vec::new(),
+            }),
+            _ => Err(ParseError::none()),
+        }
+    }
+
+    /// Orders the global constants so that every constant comes after the
+    /// constants it uses, directly or through the functions it calls, and
+    /// reports the constants that depend on themselves
+    fn order_constants(&mut self, program: &mut [Instruction]) {
+        let mut functions = HashMap::new();
+        let mut constants = Vec::new();
+        for (index, instruction) in program.iter().enumerate() {
+            match &instruction.r#type {
+                InstructionType::Function {
+                    name, instruction, ..
+                } => {
+                    functions.insert(name.as_str(), instruction.references());
+                }
+                InstructionType::Assignment { variable, .. } => constants.push((index, variable)),
+                _ => (),
+            }
+        }
+
+        // The constants each constant uses, by their position in `constants`
+        let dependencies = constants
+            .iter()
+            .map(|(index, _)| {
+                let (mut variables, calls) = program[*index].references();
+                let mut reached = HashSet::new();
+                let mut pending = calls.into_iter().collect::<Vec<&str>>();
+                while let Some(function) = pending.pop() {
+                    if !reached.insert(function) {
+                        continue;
+                    }
+                    if let Some((used, called)) = functions.get(function) {
+                        variables.extend(used.iter().copied());
+                        pending.extend(called.iter().copied());
+                    }
+                }
+                constants
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, constant))| {
+                        variables.iter().any(|variable| {
+                            variable.declaration_token == constant.declaration_token
+                        })
+                    })
+                    .map(|(position, _)| position)
+                    .collect::<Vec<usize>>()
+            })
+            .collect::<Vec<Vec<usize>>>();
+
+        let mut order = Vec::new();
+        let mut cycles = Vec::new();
+        let mut visited = vec![false; constants.len()];
+        for position in 0..constants.len() {
+            let mut path = Vec::new();
+            Self::visit_constant(
+                position,
+                &dependencies,
+                &mut visited,
+                &mut path,
+                &mut order,
+                &mut cycles,
+            );
+        }
+
+        for cycle in &cycles {
+            let names = cycle
+                .iter()
+                .map(|position: &usize| constants[*position].1)
+                .collect::<Vec<&Variable>>();
+            let mut error = ParseError::new(
+                ParseErrorType::ConstantCycle(names.iter().map(|v| v.name.clone()).collect()),
+                names[0].identifier_token.clone(),
+            );
+            for (variable, used) in names.iter().zip(names.iter().cycle().skip(1)).skip(1) {
+                error = error.with_label(
+                    variable.identifier_token.clone(),
+                    format!("`{}` uses `{}`", variable.name, used.name),
+                );
+            }
+            error.print();
+            self.success = false;
+        }
+        if !cycles.is_empty() {
+            return;
+        }
+
+        let ordered = order
+            .iter()
+            .map(|position| program[constants[*position].0].clone())
+            .collect::<Vec<Instruction>>();
+        let indices = constants
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<usize>>();
+        for (index, constant) in indices.into_iter().zip(ordered) {
+            program[index] = constant;
+        }
+    }
+
+    /// Adds the constant to `order` after the constants it depends on. A
+    /// constant that is reached again while its dependencies are visited is
+    /// part of a cycle, which is recorded from that constant on
+    fn visit_constant(
+        position: usize,
+        dependencies: &[Vec<usize>],
+        visited: &mut [bool],
+        path: &mut Vec<usize>,
+        order: &mut Vec<usize>,
+        cycles: &mut Vec<Vec<usize>>,
+    ) {
+        if let Some(start) = path.iter().position(|visiting| *visiting == position) {
+            cycles.push(path[start..].to_vec());
+            return;
+        }
+        if visited[position] {
+            return;
+        }
+        path.push(position);
+        for dependency in &dependencies[position] {
+            Self::visit_constant(*dependency, dependencies, visited, path, order, cycles);
+        }
+        path.pop();
+        visited[position] = true;
+        order.push(position);
+    }
+
+    /// Parses `fn name(parameters): type` into a function without a body
+    fn parse_signature(&mut self) -> Result<Instruction, ParseError> {
         let token = self.get_next_token()?;
         let name = self.get_next_token()?;
         let name = match &name.r#type {
@@ -208,32 +898,73 @@ impl Parser {
                 ));
             }
         };
-        let function = Instruction::new(
+        Ok(Instruction::new(
             InstructionType::Function {
                 name: name.to_string(),
-                parameters: parameters.clone(),
-                instruction: Box::new(Instruction::NONE),
+                parameters,
+                instruction: Box::new(Instruction::none()),
                 return_type,
+                end: token.clone(),
             },
-            token.clone(),
-        );
-        self.environment.add_function(Box::new(function.clone()));
+            token,
+        ))
+    }
+
+    fn parse_function(&mut self) -> Result<Instruction, ParseError> {
+        let nested = self.environment.is_nested();
+        let function = self.parse_signature()?;
+        let (name, parameters, return_type) = match &function.r#type {
+            InstructionType::Function {
+                name,
+                parameters,
+                return_type,
+                ..
+            } => (name.clone(), parameters.clone(), *return_type),
+            _ => unreachable!(),
+        };
+        let token = function.token.clone();
+
+        // A function inside a test or function may not hide another one, and
+        // a global function may not reuse the name of a nested one, so that a
+        // name refers to the same function everywhere it is visible
+        let defined = match nested {
+            true => self
+                .environment
+                .get_function(&name)
+                .map(|function| function.token.clone()),
+            false => self.environment.nested_functions.get(&name).cloned(),
+        };
+        if defined.is_none() {
+            self.environment.add_function(Rc::new(function));
+        }
+
+        let locals = self.environment.hide_locals();
         self.environment.add_scope();
         for parameter in parameters.iter() {
             self.environment.insert(parameter.clone());
         }
-        let instruction = self.parse_statement()?;
+        let instruction = self.parse_statement();
         self.environment.remove_scope();
+        self.environment.restore_locals(locals);
+        let instruction = instruction?;
+
+        if let Some(defined) = defined {
+            return Err(
+                ParseError::new(ParseErrorType::FunctionRedefinition(name), token)
+                    .with_label(defined, "first defined here"),
+            );
+        }
         let function = Instruction::new(
             InstructionType::Function {
-                name: name.to_string(),
+                name,
                 parameters,
                 instruction: Box::new(instruction),
                 return_type,
+                end: self.tokens.current().unwrap_or(token.clone()),
             },
-            token.clone(),
+            token,
         );
-        self.environment.add_function(Box::new(function.clone()));
+        self.environment.add_function(Rc::new(function.clone()));
         Ok(function)
     }
 
@@ -259,9 +990,10 @@ impl Parser {
                         ));
                     }
                     r#const = true;
+                    self.tokens.next();
                 }
                 TokenType::Identifier { .. } => {
-                    arguments.push(self.parse_parameter(r#const)?);
+                    arguments.push(self.parse_parameter(std::mem::take(&mut r#const))?);
                     match self.peek_next_token()?.r#type {
                         TokenType::Comma => {
                             self.get_next_token()?;
@@ -311,7 +1043,8 @@ impl Parser {
 
         self.expect_token(TokenType::Colon)?;
 
-        let r#type = match &self.get_next_token()? {
+        let type_token = self.get_next_token()?;
+        let r#type = match &type_token {
             Token {
                 r#type: TokenType::Type { value },
                 ..
@@ -330,13 +1063,16 @@ impl Parser {
 
         Ok(Variable {
             name: name.to_string(),
+            symbol: Symbol::intern(name),
             r#const,
             r#type,
             declaration_token: token.clone(),
             identifier_token: token.clone(),
+            type_token,
             last_assignment_token: token.clone(),
             read: true,
             assigned: true,
+            allowed_warnings: Vec::new(),
         })
     }
 
@@ -348,7 +1084,14 @@ impl Parser {
                     break;
                 }
                 _ => {
-                    arguments.push(self.parse_expression(true, true)?);
+                    let start = self.tokens.position();
+                    match self.parse_expression(true, true) {
+                        Ok(argument) => arguments.push(argument),
+                        Err(e) => {
+                            self.recover_argument(e, start)?;
+                            arguments.push(Instruction::new(InstructionType::None, token));
+                        }
+                    }
                     match self.peek_next_token()?.r#type {
                         TokenType::Comma => {
                             self.get_next_token()?;
@@ -389,7 +1132,10 @@ impl Parser {
                             ParseWarningType::MagicLiteral(Type::String),
                             token.clone(),
                         )
-                        .print(self.args.disable_warnings)
+                        .print(
+                            self.args.disable_warnings,
+                            &self.environment.allowed_warnings,
+                        )
                     }
                 }
 
@@ -427,81 +1173,63 @@ impl Parser {
         ))
     }
 
+    /// The operator of the next token, if it is a binary operator that binds
+    /// at least as tightly as `min_precedence`
+    fn peek_binary_operator(&mut self, min_precedence: u8) -> Option<BinaryOperator> {
+        match self.tokens.peek()?.r#type {
+            TokenType::BinaryOperator { value } => BinaryOperator::from_str(&value)
+                .filter(|operator| operator.precedence() >= min_precedence),
+            _ => None,
+        }
+    }
+
+    /// Parses the binary operators following `left` by precedence climbing:
+    /// an operand is only combined with the operator before it once every
+    /// operator after it that binds tighter has been applied
     fn parse_binary_operator(
         &mut self,
-        instruction: Instruction,
+        mut left: Instruction,
+        min_precedence: u8,
     ) -> Result<Instruction, ParseError> {
-        let token = self.get_next_token()?;
-        let new_operator = match &token.r#type {
-            TokenType::BinaryOperator { value } => match value.as_str() {
-                "+" => BinaryOperator::Addition,
-                "-" => BinaryOperator::Subtraction,
-                "*" => BinaryOperator::Multiplication,
-                "/" => BinaryOperator::Division,
-                "%" => BinaryOperator::Modulo,
-                "==" => BinaryOperator::Equal,
-                "!=" => BinaryOperator::NotEqual,
-                ">" => BinaryOperator::GreaterThan,
-                ">=" => BinaryOperator::GreaterThanOrEqual,
-                "<" => BinaryOperator::LessThan,
-                "<=" => BinaryOperator::LessThanOrEqual,
-                "&&" => BinaryOperator::And,
-                "||" => BinaryOperator::Or,
-                _ => unreachable!(),
-            },
-            _ => unreachable!(),
-        };
-
-        let new_right = self.parse_expression(false, true)?;
-        match new_right {
-            Instruction {
-                r#type: InstructionType::None,
-                ..
-            } => {
+        while let Some(operator) = self.peek_binary_operator(min_precedence) {
+            let token = self.get_next_token()?;
+            let start = self.peek_next_token()?;
+            // The regex a string is matched against is compiled instead of
+            // expanded, unless an operator after it combines it
+            if operator == BinaryOperator::Matches {
+                if let TokenType::RegexLiteral { .. } = start.r#type {
+                    self.unexpanded_regex = Some((regex::validate, operator.precedence() + 1));
+                }
+            }
+            let right = self.parse_expression(false, true);
+            self.unexpanded_regex = None;
+            let mut right = right?;
+            if right.r#type == InstructionType::None {
                 return Err(ParseError::new(
                     ParseErrorType::UnexpectedToken(TokenType::Semicolon),
-                    token.clone(),
-                ))
+                    token,
+                ));
             }
-            _ => (),
-        }
-        match instruction.r#type {
-            InstructionType::BinaryOperation {
-                ref operator,
-                ref left,
-                ref right,
-            } => Ok(Instruction::new(
-                if new_operator.cmp(&operator) != std::cmp::Ordering::Greater {
-                    InstructionType::BinaryOperation {
-                        operator: new_operator,
-                        left: Box::new(instruction.clone()),
-                        right: Box::new(new_right),
-                    }
-                } else {
-                    InstructionType::BinaryOperation {
-                        operator: operator.clone(),
-                        left: left.clone(),
-                        right: Box::new(Instruction::new(
-                            InstructionType::BinaryOperation {
-                                operator: new_operator,
-                                left: right.clone(),
-                                right: Box::new(new_right),
-                            },
-                            token.clone(),
-                        )),
-                    }
-                },
-                token,
-            )),
-            _ => Ok(Instruction::new(
+            self.extend_span(&mut right, &start);
+            while self
+                .peek_binary_operator(operator.precedence() + 1)
+                .is_some()
+            {
+                right = self.parse_binary_operator(right, operator.precedence() + 1)?;
+            }
+
+            let start = left.span.clone();
+            left = Instruction::new(
                 InstructionType::BinaryOperation {
-                    operator: new_operator,
-                    left: Box::new(instruction.clone()),
-                    right: Box::new(new_right),
+                    operator,
+                    left: Box::new(left),
+                    right: Box::new(right),
                 },
                 token,
-            )),
+            );
+            self.extend_span(&mut left, &start);
         }
+        Ok(left)
     }
 
     fn parse_type_cast(&mut self, instruction: &Instruction) -> Result<Instruction, ParseError> {
@@ -526,6 +1254,7 @@ impl Parser {
             InstructionType::TypeCast {
                 instruction: Box::new(instruction.clone()),
                 r#type,
+                checked: false,
             },
             token,
         ))
@@ -544,11 +1273,56 @@ impl Parser {
                             ParseWarningType::MagicLiteral(Type::Regex),
                             token.clone(),
                         )
-                        .print(self.args.disable_warnings)
+                        .print(
+                            self.args.disable_warnings,
+                            &self.environment.allowed_warnings,
+                        )
                     }
                 }
+                // A sampled, captured or matched regex is never expanded, so
+                // it may match more strings than `--max-combinations`.
+                // Regexes that are combined with other regexes must be
+                // expanded.
+                let check = self
+                    .unexpanded_regex
+                    .take()
+                    .filter(|(_, min_precedence)| {
+                        self.peek_binary_operator(*min_precedence).is_none()
+                            && !matches!(
+                                self.tokens.peek(),
+                                Some(Token {
+                                    r#type: TokenType::TypeCast | TokenType::AssignmentOperator,
+                                    ..
+                                })
+                            )
+                    })
+                    .map(|(check, _)| check);
+                let pattern = value[1..value.len() - 1].to_string();
+                if check.is_none() {
+                    self.check_lossy_class(&token);
+                }
+                let values = match check {
+                    Some(check) => {
+                        check(&token)?;
+                        Rc::from([])
+                    }
+                    None => match self.expanded_regexes.get(&pattern) {
+                        Some(values) => Rc::clone(values),
+                        None => {
+                            let values: Rc<[String]> = regex::parse(
+                                &token,
+                                self.args.max_size,
+                                self.args.max_combinations,
+                            )?
+                            .into();
+                            self.expanded_regexes
+                                .insert(pattern.clone(), Rc::clone(&values));
+                            values
+                        }
+                    },
+                };
                 Ok(Instruction::new(
-                    InstructionType::RegexLiteral(regex::parse(&token, self.args.max_size)?),
+                    InstructionType::RegexLiteral { pattern, values },
                     token,
                 ))
             }
@@ -566,7 +1340,10 @@ impl Parser {
                 {
                     if !self.args.disable_style_warnings {
                         ParseWarning::new(ParseWarningType::MagicLiteral(Type::Int), token.clone())
-                            .print(self.args.disable_warnings)
+                            .print(
+                                self.args.disable_warnings,
+                                &self.environment.allowed_warnings,
+                            )
                     }
                 }
                 Ok(Instruction::new(
@@ -591,7 +1368,10 @@ impl Parser {
                             ParseWarningType::MagicLiteral(Type::Float),
                             token.clone(),
                         )
-                        .print(self.args.disable_warnings)
+                        .print(
+                            self.args.disable_warnings,
+                            &self.environment.allowed_warnings,
+                        )
                     }
                 }
                 Ok(Instruction::new(
@@ -613,7 +1393,10 @@ impl Parser {
                 {
                     if !self.args.disable_style_warnings {
                         ParseWarning::new(ParseWarningType::MagicLiteral(Type::Bool), token.clone())
-                            .print(self.args.disable_warnings)
+                            .print(
+                                self.args.disable_warnings,
+                                &self.environment.allowed_warnings,
+                            )
                     }
                 }
                 Ok(Instruction::new(
@@ -633,6 +1416,7 @@ impl Parser {
                 "const" => self.parse_declaration(),
                 "for" => self.parse_for(),
                 "if" => self.parse_conditional(),
+                "fn" => self.parse_function(),
                 _ => {
                     self.tokens.advance_to_next_instruction();
                     Err(ParseError::new(
@@ -645,12 +1429,121 @@ impl Parser {
         }
     }
 
+    /// Parses `(a: string, b: string) in iterable` after `for`, which names
+    /// every string of a product of regexes.
+    fn parse_product_declaration(&mut self, token: Token) -> Result<Instruction, ParseError> {
+        self.expect_token(TokenType::OpenParen)?;
+        let mut variables = Vec::new();
+        loop {
+            let identifier = self.get_next_token()?;
+            let name = match &identifier.r#type {
+                TokenType::Identifier { value } => value.clone(),
+                r#type => {
+                    self.tokens.advance_to_next_instruction();
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedTokenType {
+                            expected: TokenType::Identifier {
+                                value: String::new(),
+                            },
+                            actual: r#type.clone(),
+                        },
+                        identifier,
+                    ));
+                }
+            };
+            if !self.args.disable_style_warnings && !name.is_snake_case() {
+                ParseWarning::new(
+                    ParseWarningType::VariableNotSnakeCase(name.clone()),
+                    identifier.clone(),
+                )
+                .print(
+                    self.args.disable_warnings,
+                    &self.environment.allowed_warnings,
+                )
+            }
+            if self.expect_token(TokenType::Colon).is_err() {
+                return Err(ParseError::new(
+                    ParseErrorType::VaribleTypeAnnotation,
+                    identifier,
+                ));
+            }
+            let type_token = self.get_next_token()?;
+            let r#type = match &type_token {
+                Token {
+                    r#type: TokenType::Type { value },
+                    ..
+                } => *value,
+                _ => {
+                    self.tokens.advance_to_next_instruction();
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedTokenType {
+                            expected: TokenType::Type { value: Type::Any },
+                            actual: type_token.r#type.clone(),
+                        },
+                        type_token,
+                    ));
+                }
+            };
+            variables.push(Variable {
+                symbol: Symbol::intern(&name),
+                name,
+                r#const: false,
+                r#type,
+                declaration_token: token.clone(),
+                identifier_token: identifier.clone(),
+                type_token,
+                last_assignment_token: identifier,
+                read: true,
+                assigned: true,
+                allowed_warnings: Vec::new(),
+            });
+
+            let next = self.get_next_token()?;
+            match &next.r#type {
+                TokenType::Comma => (),
+                TokenType::CloseParen => break,
+                r#type => {
+                    self.tokens.advance_to_next_instruction();
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedTokenType {
+                            expected: TokenType::CloseParen,
+                            actual: r#type.clone(),
+                        },
+                        next,
+                    ));
+                }
+            }
+        }
+        self.expect_token(TokenType::IterableAssignmentOperator)?;
+
+        let instruction = self.parse_expression(true, true);
+        for variable in &variables {
+            self.environment.insert(variable.clone());
+        }
+        Ok(Instruction::new(
+            InstructionType::IterableAssignment {
+                variables,
+                instruction: Box::new(instruction?),
+                token: token.clone(),
+            },
+            token,
+        ))
+    }
+
     fn parse_declaration(&mut self) -> Result<Instruction, ParseError> {
         let token = self.get_next_token()?;
         let r#const = match &token.r#type {
             TokenType::Keyword { value } => value == "const",
             _ => unreachable!(),
         };
+        if token.r#type
+            == (TokenType::Keyword {
+                value: String::from("for"),
+            })
+            && self.peek_next_token()?.r#type == TokenType::OpenParen
+        {
+            return self.parse_product_declaration(token);
+        }
         let identifier = self.get_next_token()?;
 
         let identifier_name = match &identifier.r#type {
@@ -662,7 +1555,10 @@ impl Parser {
                                 ParseWarningType::VariableNotSnakeCase(value.to_string()),
                                 identifier.clone(),
                             )
-                            .print(self.args.disable_warnings)
+                            .print(
+                                self.args.disable_warnings,
+                                &self.environment.allowed_warnings,
+                            )
                         }
                     }
                     true => {
@@ -672,7 +1568,10 @@ impl Parser {
                                 ParseWarningType::ConstantNotUpperCase(value.to_string()),
                                 identifier.clone(),
                             )
-                            .print(self.args.disable_warnings)
+                            .print(
+                                self.args.disable_warnings,
+                                &self.environment.allowed_warnings,
+                            )
                         }
                     }
                 }
@@ -699,13 +1598,16 @@ impl Parser {
             Err(_) => {
                 let variable = Variable {
                     name: identifier_name.clone(),
+                    symbol: Symbol::intern(&identifier_name),
                     r#const,
                     r#type: Type::Any,
                     declaration_token: token.clone(),
                     identifier_token: identifier.clone(),
+                    type_token: identifier.clone(),
                     last_assignment_token: token.clone(),
                     read: true,
                     assigned: true,
+                    allowed_warnings: Vec::new(),
                 };
 
                 self.environment.insert(variable.clone());
@@ -718,7 +1620,8 @@ impl Parser {
             }
         }
 
-        let r#type = match &self.get_next_token()? {
+        let type_token = self.get_next_token()?;
+        let r#type = match &type_token {
             Token {
                 r#type: TokenType::Type { value },
                 ..
@@ -755,13 +1658,16 @@ impl Parser {
 
         let variable = Variable {
             name: identifier_name.clone(),
+            symbol: Symbol::intern(&identifier_name),
             r#const,
             r#type: r#type.clone(),
             declaration_token: token.clone(),
             identifier_token: identifier.clone(),
+            type_token,
             last_assignment_token: assignment.clone(),
             read: true,
             assigned: true,
+            allowed_warnings: Vec::new(),
         };
 
         let instruction = match self.parse_expression(true, true) {
@@ -790,7 +1696,7 @@ impl Parser {
                 self.environment.insert(variable.clone());
                 Ok(Instruction::new(
                     InstructionType::IterableAssignment {
-                        variable,
+                        variables: vec![variable],
                         instruction: Box::new(instruction),
                         token: token.clone(),
                     },
@@ -817,7 +1723,10 @@ impl Parser {
         if variable.r#const {
             self.tokens.advance_to_next_instruction();
             return Err(ParseError::new(
-                ParseErrorType::ConstantReassignment(variable.clone()),
+                ParseErrorType::ConstantReassignment {
+                    variable: variable.clone(),
+                    global: self.environment.is_global(&variable.name),
+                },
                 instruction.token.clone(),
             ));
         }
@@ -837,7 +1746,10 @@ impl Parser {
         if self.environment.get(&variable.name).is_none() {
             self.tokens.advance_to_next_instruction();
             return Err(ParseError::new(
-                ParseErrorType::IdentifierNotDefined(variable.name.clone()),
+                ParseErrorType::IdentifierNotDefined {
+                    name: variable.name.clone(),
+                    suggestion: self.environment.similar_variable(&variable.name),
+                },
                 token.clone(),
             ));
         }
@@ -846,7 +1758,10 @@ impl Parser {
             InstructionType::Variable(ref instruction_variable) => {
                 if variable.name == instruction_variable.name {
                     ParseWarning::new(ParseWarningType::SelfAssignment, instruction.token.clone())
-                        .print(self.args.disable_warnings);
+                        .print(
+                            self.args.disable_warnings,
+                            &self.environment.allowed_warnings,
+                        );
                 }
             }
             _ => (),
@@ -867,12 +1782,28 @@ impl Parser {
         let token = self.get_next_token()?;
         match &token.r#type {
             TokenType::Identifier { value } => {
+                let called = self
+                    .peek_next_token()
+                    .is_ok_and(|next| next.r#type == TokenType::OpenParen);
                 let variable = self.environment.get(value).cloned();
                 let function = self.environment.get_function(value);
                 if variable.is_none() && function.is_none() {
+                    let r#type = match called {
+                        true => ParseErrorType::FunctionNotDefined {
+                            name: value.clone(),
+                            suggestion: self.environment.similar_function(value),
+                        },
+                        false => ParseErrorType::IdentifierNotDefined {
+                            name: value.clone(),
+                            suggestion: self.environment.similar_variable(value),
+                        },
+                    };
+                    self.tokens.advance_to_next_instruction();
+                    Err(ParseError::new(r#type, token.clone()))
+                } else if function.is_none() && called {
                     self.tokens.advance_to_next_instruction();
                     Err(ParseError::new(
-                        ParseErrorType::IdentifierNotDefined(value.clone()),
+                        ParseErrorType::NotCallable(variable.unwrap()),
                         token.clone(),
                     ))
                 } else if function.is_some() {
@@ -897,16 +1828,140 @@ impl Parser {
         }
     }
 
+    fn parse_unexpanded_regex(&mut self, check: RegexCheck) -> Result<Instruction, ParseError> {
+        if let TokenType::RegexLiteral { .. } = self.peek_next_token()?.r#type {
+            self.unexpanded_regex = Some((check, 0));
+        }
+        let regex = self.parse_expression(true, true);
+        self.unexpanded_regex = None;
+        regex
+    }
+
+    /// Warns if expanding or sampling the regex literal `token` leaves out
+    /// characters of a class
+    fn check_lossy_class(&self, token: &Token) {
+        if regex::is_lossy_literal(token) {
+            ParseWarning::new(ParseWarningType::LossyClass, token.clone()).print(
+                self.args.disable_warnings,
+                &self.environment.allowed_warnings,
+            );
+        }
+    }
+
+    fn parse_sample(&mut self, token: Token) -> Result<Instruction, ParseError> {
+        self.expect_token(TokenType::OpenParen)?;
+        let regex = self.parse_unexpanded_regex(regex::check)?;
+        if let InstructionType::RegexLiteral { values, .. } = &regex.r#type {
+            if values.is_empty() {
+                self.check_lossy_class(&regex.token);
+            }
+        }
+        self.expect_token(TokenType::Comma)?;
+        let count = self.parse_expression(true, true)?;
+        self.expect_token(TokenType::CloseParen)?;
+
+        Ok(Instruction::new(
+            InstructionType::BuiltIn(BuiltIn::Sample {
+                regex: Box::new(regex),
+                count: Box::new(count),
+                max_size: self.args.max_size,
+            }),
+            token,
+        ))
+    }
+
+    fn parse_captures(&mut self, token: Token) -> Result<Instruction, ParseError> {
+        self.expect_token(TokenType::OpenParen)?;
+        let line = self.parse_expression(true, true)?;
+        self.expect_token(TokenType::Comma)?;
+        let regex = self.parse_unexpanded_regex(regex::validate)?;
+        self.expect_token(TokenType::CloseParen)?;
+
+        Ok(Instruction::new(
+            InstructionType::BuiltIn(BuiltIn::Captures {
+                line: Box::new(line),
+                regex: Box::new(regex),
+            }),
+            token,
+        ))
+    }
+
+    fn parse_skip_until(&mut self, token: Token) -> Result<Instruction, ParseError> {
+        self.expect_token(TokenType::OpenParen)?;
+        let regex = self.parse_unexpanded_regex(regex::validate)?;
+        self.expect_token(TokenType::CloseParen)?;
+
+        Ok(Instruction::new(
+            InstructionType::BuiltIn(BuiltIn::SkipUntil(Box::new(regex))),
+            token,
+        ))
+    }
+
+    fn parse_try_cast(&mut self, token: Token) -> Result<Instruction, ParseError> {
+        self.expect_token(TokenType::OpenParen)?;
+        let instruction = self.parse_expression(true, true)?;
+        self.expect_token(TokenType::Comma)?;
+        let r#type = match self.get_next_token()? {
+            Token {
+                r#type: TokenType::Type { value },
+                ..
+            } => value,
+            type_token => {
+                self.tokens.advance_to_next_instruction();
+                return Err(ParseError::new(
+                    ParseErrorType::MismatchedTokenType {
+                        expected: TokenType::Type { value: Type::Any },
+                        actual: type_token.r#type.clone(),
+                    },
+                    type_token,
+                ));
+            }
+        };
+        self.expect_token(TokenType::CloseParen)?;
+
+        Ok(Instruction::new(
+            InstructionType::TypeCast {
+                instruction: Box::new(instruction),
+                r#type,
+                checked: true,
+            },
+            token,
+        ))
+    }
+
+    fn parse_custom_builtin(&mut self, token: Token) -> Result<Instruction, ParseError> {
+        let name = match &token.r#type {
+            TokenType::BuiltIn { value } => plugin::get(value).unwrap().name(),
+            _ => unreachable!(),
+        };
+        self.expect_token(TokenType::OpenParen)?;
+        let arguments = self.parse_arguments()?;
+        self.expect_token(TokenType::CloseParen)?;
+
+        Ok(Instruction::new(
+            InstructionType::BuiltIn(BuiltIn::Custom { name, arguments }),
+            token,
+        ))
+    }
+
     fn parse_builtin(&mut self) -> Result<Instruction, ParseError> {
         let token = self.get_next_token()?;
-        self.expect_token(TokenType::OpenParen)?;
-        let close_paren = self.get_next_token()?;
-        let instruction = match close_paren.r#type {
-            TokenType::CloseParen => Ok(Instruction::NONE),
-            _ => {
-                self.tokens.back();
-                self.parse_expression(true, true)
+        if let TokenType::BuiltIn { value } = &token.r#type {
+            match value.as_str() {
+                "sample" => return self.parse_sample(token),
+                "captures" => return self.parse_captures(token),
+                "skip_until" => return self.parse_skip_until(token),
+                instruction::TRY_CAST => return self.parse_try_cast(token),
+                value if !instruction::is_builtin(value) => {
+                    return self.parse_custom_builtin(token)
+                }
+                _ => (),
             }
+        }
+        self.expect_token(TokenType::OpenParen)?;
+        let instruction = match self.peek_next_token()?.r#type {
+            TokenType::CloseParen => Ok(Instruction::none()),
+            _ => self.parse_expression(true, true),
         }?;
 
         self.expect_token(TokenType::CloseParen)?;
@@ -929,6 +1984,42 @@ impl Parser {
                     InstructionType::BuiltIn(BuiltIn::Println(Box::new(instruction))),
                     token,
                 )),
+                "strip_ansi" => Ok(Instruction::new(
+                    InstructionType::BuiltIn(BuiltIn::StripAnsi(Box::new(instruction))),
+                    token,
+                )),
+                "elapsed_ms" => Ok(Instruction::new(
+                    InstructionType::BuiltIn(BuiltIn::ElapsedMs(Box::new(instruction))),
+                    token,
+                )),
+                "last_response_ms" => Ok(Instruction::new(
+                    InstructionType::BuiltIn(BuiltIn::LastResponseMs(Box::new(instruction))),
+                    token,
+                )),
+                "restart" => Ok(Instruction::new(
+                    InstructionType::BuiltIn(BuiltIn::Restart(Box::new(instruction))),
+                    token,
+                )),
+                "input_raw" => Ok(Instruction::new(
+                    InstructionType::BuiltIn(BuiltIn::InputRaw(Box::new(instruction))),
+                    token,
+                )),
+                "send_eof" => Ok(Instruction::new(
+                    InstructionType::BuiltIn(BuiltIn::SendEof(Box::new(instruction))),
+                    token,
+                )),
+                "output_file" => Ok(Instruction::new(
+                    InstructionType::BuiltIn(BuiltIn::OutputFile(Box::new(instruction))),
+                    token,
+                )),
+                "skip_lines" => Ok(Instruction::new(
+                    InstructionType::BuiltIn(BuiltIn::SkipLines(Box::new(instruction))),
+                    token,
+                )),
+                "load_cases" => Ok(Instruction::new(
+                    InstructionType::BuiltIn(BuiltIn::LoadCases(Box::new(instruction))),
+                    token,
+                )),
                 _ => unreachable!(),
             },
             _ => unreachable!(),
@@ -953,6 +2044,7 @@ impl Parser {
             next_token = match self.peek_next_token() {
                 Ok(token) => token,
                 Err(_) => {
+                    self.environment.remove_scope();
                     self.tokens.advance_to_next_instruction();
                     return Err(ParseError::new(
                         ParseErrorType::UnclosedDelimiter(TokenType::OpenBlock),
@@ -964,10 +2056,24 @@ impl Parser {
 
         self.environment.remove_scope();
         if block.is_empty() {
-            ParseWarning::new(ParseWarningType::EmptyBlock, token.clone())
-                .print(self.args.disable_warnings)
+            ParseWarning::new(ParseWarningType::EmptyBlock, token.clone()).print(
+                self.args.disable_warnings,
+                &self.environment.allowed_warnings,
+            )
         }
-        Ok(Instruction::new(InstructionType::Block(block), token))
+        let tail = !block.is_empty()
+            && self
+                .tokens
+                .current()
+                .is_some_and(|last| last.r#type != TokenType::Semicolon);
+        self.get_next_token()?;
+        Ok(Instruction::new(
+            InstructionType::Block {
+                instructions: block,
+                tail,
+            },
+            token,
+        ))
     }
 
     fn parse_conditional(&mut self) -> Result<Instruction, ParseError> {
@@ -975,7 +2081,7 @@ impl Parser {
         let condition = self.parse_expression(true, true)?;
         let statement = self.parse_statement()?;
         match statement.r#type {
-            InstructionType::Block(_) => (),
+            InstructionType::Block { .. } => (),
             InstructionType::None => {
                 self.tokens.advance_to_next_instruction();
                 return Err(ParseError::new(
@@ -987,35 +2093,33 @@ impl Parser {
                 ParseWarningType::NoBlock(&self.tokens.current().unwrap()),
                 statement.token.clone(),
             )
-            .print(self.args.disable_warnings || self.args.disable_style_warnings),
+            .print(
+                self.args.disable_warnings || self.args.disable_style_warnings,
+                &self.environment.allowed_warnings,
+            ),
         }
-        let r#else = match &self.peek_next_token()?.r#type {
-            TokenType::Keyword { value } => match value.as_str() {
+        let r#else = match self.tokens.peek().map(|token| token.r#type) {
+            Some(TokenType::Keyword { value }) => match value.as_str() {
                 "else" => {
                     self.get_next_token()?;
-                    let statement = self.parse_statement()?;
-                    self.tokens.back();
-                    statement
-                }
-                _ => {
-                    self.tokens.back();
-                    Instruction::NONE
+                    self.parse_statement()?
                 }
+                _ => Instruction::none(),
             },
-            _ => {
-                self.tokens.back();
-                Instruction::NONE
-            }
+            _ => Instruction::none(),
         };
 
         match r#else.r#type {
-            InstructionType::Block(_) => (),
+            InstructionType::Block { .. } => (),
             InstructionType::None => (),
             _ => ParseWarning::new(
-                ParseWarningType::NoBlock(&self.tokens.peek().unwrap()),
+                ParseWarningType::NoBlock(&self.tokens.current().unwrap()),
                 r#else.token.clone(),
             )
-            .print(self.args.disable_warnings || self.args.disable_style_warnings),
+            .print(
+                self.args.disable_warnings || self.args.disable_style_warnings,
+                &self.environment.allowed_warnings,
+            ),
         }
 
         Ok(Instruction::new(
@@ -1038,7 +2142,7 @@ impl Parser {
             Err(e) => {
                 e.print();
                 self.success = false;
-                Instruction::NONE
+                Instruction::none()
             }
         };
 
@@ -1049,7 +2153,7 @@ impl Parser {
         let statement = statement?;
 
         match statement.r#type {
-            InstructionType::Block(_) => (),
+            InstructionType::Block { .. } => (),
             InstructionType::None => {
                 self.tokens.advance_to_next_instruction();
                 return Err(ParseError::new(
@@ -1061,10 +2165,12 @@ impl Parser {
                 ParseWarningType::NoBlock(&self.tokens.current().unwrap()),
                 statement.token.clone(),
             )
-            .print(self.args.disable_warnings || self.args.disable_style_warnings),
+            .print(
+                self.args.disable_warnings || self.args.disable_style_warnings,
+                &self.environment.allowed_warnings,
+            ),
         }
 
-        self.tokens.back();
         Ok(Instruction::new(
             InstructionType::For {
                 assignment: Box::new(assignment),
@@ -1076,7 +2182,14 @@ impl Parser {
 
     fn parse_parentheses(&mut self) -> Result<Instruction, ParseError> {
         let token = self.get_next_token()?;
-        let instruction = self.parse_expression(true, true)?;
+        let start = self.tokens.position();
+        let instruction = match self.parse_expression(true, true) {
+            Ok(instruction) => instruction,
+            Err(e) => {
+                self.recover_argument(e, start)?;
+                Instruction::new(InstructionType::None, token.clone())
+            }
+        };
         self.expect_token(TokenType::CloseParen)?;
         Ok(Instruction::new(
             InstructionType::Paren(Box::new(instruction)),
@@ -1100,20 +2213,31 @@ impl Parser {
         }
     }
 
-    fn end_statement(&mut self) -> Result<(), ParseError> {
-        let token = self.get_next_token()?;
+    /// A statement ends with `;`, except for the last one in a block, which
+    /// is the value of the block without it. A statement that ends with a
+    /// block or with another statement, like `if` and `for`, needs no `;`
+    fn end_statement(&mut self, instruction: &Instruction) -> Result<(), ParseError> {
+        if let InstructionType::Block { .. }
+        | InstructionType::Conditional { .. }
+        | InstructionType::For { .. }
+        | InstructionType::Function { .. } = instruction.r#type
+        {
+            return Ok(());
+        }
+        let token = self.peek_next_token()?;
         match token.r#type {
-            TokenType::Semicolon | TokenType::CloseBlock => Ok(()),
-            _ => {
-                self.tokens.back();
-                Err(ParseError::new(
-                    ParseErrorType::MismatchedTokenType {
-                        expected: TokenType::Semicolon,
-                        actual: token.clone().r#type,
-                    },
-                    token,
-                ))
+            TokenType::Semicolon => {
+                self.get_next_token()?;
+                Ok(())
             }
+            TokenType::CloseBlock => Ok(()),
+            _ => Err(ParseError::new(
+                ParseErrorType::MismatchedTokenType {
+                    expected: TokenType::Semicolon,
+                    actual: token.clone().r#type,
+                },
+                token,
+            )),
         }
     }
 
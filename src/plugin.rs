@@ -0,0 +1,265 @@
+//! Builtins added by an embedder of `test-script`, or by a plugin listed in
+//! `test_script.toml`. They are registered before the script is lexed, and
+//! are called in scripts like the builtins of the language.
+
+use crate::instruction;
+use crate::instruction::InstructionResult;
+use crate::lexer::{BOOLEANS, ITERABLE_ASSIGNMENT, KEYWORDS, TYPES, TYPE_CAST};
+use crate::r#type::Type;
+
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// The version of `test-script` a plugin is built against, which must be
+/// the version that loads it
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A value passed to or returned from a registered builtin
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    None,
+}
+
+/// The type of a parameter or of the result of a registered builtin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    String,
+    Int,
+    Float,
+    Bool,
+    None,
+}
+
+type Function = dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync;
+
+/// A builtin with a name, the types of its parameters and its result, and
+/// the function that runs it. An error returned by the function fails the
+/// test that called it with the error as the message
+pub struct Builtin {
+    name: &'static str,
+    parameters: Vec<ValueType>,
+    returns: ValueType,
+    function: Box<Function>,
+}
+
+static REGISTRY: RwLock<Vec<Arc<Builtin>>> = RwLock::new(Vec::new());
+
+impl Builtin {
+    pub fn new(
+        name: &str,
+        parameters: &[ValueType],
+        returns: ValueType,
+        function: impl Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            // Profiles count builtins by a name that lives as long as the run
+            name: Box::leak(name.into()),
+            parameters: parameters.to_vec(),
+            returns,
+            function: Box::new(function),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub(crate) fn parameters(&self) -> &[ValueType] {
+        &self.parameters
+    }
+
+    pub(crate) fn returns(&self) -> ValueType {
+        self.returns
+    }
+
+    /// Runs the function, and checks that it returned the type it declared
+    pub(crate) fn call(&self, arguments: &[Value]) -> Result<Value, String> {
+        let value = (self.function)(arguments)?;
+        match value.r#type() == self.returns {
+            true => Ok(value),
+            false => Err(format!(
+                "returned a value of type {}, expected {}",
+                value.r#type(),
+                self.returns
+            )),
+        }
+    }
+}
+
+/// Adds a builtin to the scripts that are run after this. Fails if the name
+/// is not an identifier, or is already a keyword, a type or a builtin
+pub fn register(builtin: Builtin) -> Result<(), String> {
+    check_name(builtin.name)?;
+    REGISTRY.write().unwrap().push(Arc::new(builtin));
+    Ok(())
+}
+
+/// Checks that a builtin can be registered with the name
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    {
+        return Err(format!("`{}` is not a valid name for a builtin", name));
+    }
+    if KEYWORDS
+        .iter()
+        .chain(&TYPES)
+        .chain(&BOOLEANS)
+        .chain(&[ITERABLE_ASSIGNMENT, TYPE_CAST])
+        .copied()
+        .chain(instruction::builtin_names())
+        .any(|reserved| reserved == name)
+        || get(name).is_some()
+    {
+        return Err(format!("`{}` is already a keyword or builtin", name));
+    }
+    Ok(())
+}
+
+/// Exports builtins from a plugin, a crate built as a `cdylib` that depends on
+/// the same version of `test-script` and is built with the same compiler as
+/// the `test-script` that loads it. The argument is an expression that
+/// returns a `Vec<Builtin>`
+#[macro_export]
+macro_rules! export_builtins {
+    ($builtins:expr) => {
+        #[no_mangle]
+        pub fn test_script_version() -> &'static str {
+            $crate::plugin::VERSION
+        }
+
+        #[no_mangle]
+        pub fn test_script_builtins() -> Vec<$crate::plugin::Builtin> {
+            $builtins
+        }
+    };
+}
+
+/// Registers the builtins of a plugin built with `export_builtins!`
+#[cfg(feature = "native")]
+pub(crate) fn load(path: &Path) -> Result<(), String> {
+    // SAFETY: the library is a plugin, whose functions have the signatures
+    // that `export_builtins!` gives them. Its version is checked before
+    // anything else of it is used.
+    unsafe {
+        let library = libloading::Library::new(path).map_err(|e| e.to_string())?;
+        let version = library
+            .get::<fn() -> &'static str>(b"test_script_version")
+            .map_err(|_| String::from("it does not export its builtins with `export_builtins!`"))?;
+        if version() != VERSION {
+            return Err(format!(
+                "it is built for test-script {}, this is {}",
+                version(),
+                VERSION
+            ));
+        }
+        // The functions of the builtins are in the library, so it is never
+        // unloaded once they are created, also when registering them fails
+        let library: &'static libloading::Library = Box::leak(Box::new(library));
+        let builtins = library
+            .get::<fn() -> Vec<Builtin>>(b"test_script_builtins")
+            .map_err(|e| e.to_string())?();
+        // Either every builtin of the plugin is registered or none is
+        for (index, builtin) in builtins.iter().enumerate() {
+            check_name(builtin.name)?;
+            if builtins[..index]
+                .iter()
+                .any(|other| other.name == builtin.name)
+            {
+                return Err(format!("it exports `{}` twice", builtin.name));
+            }
+        }
+        for builtin in builtins {
+            register(builtin)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "native"))]
+pub(crate) fn load(_path: &Path) -> Result<(), String> {
+    Err(String::from(
+        "plugins are not supported in this build of test-script",
+    ))
+}
+
+pub(crate) fn get(name: &str) -> Option<Arc<Builtin>> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .find(|builtin| builtin.name == name)
+        .cloned()
+}
+
+pub(crate) fn names() -> Vec<&'static str> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .map(|builtin| builtin.name)
+        .collect()
+}
+
+impl Value {
+    fn r#type(&self) -> ValueType {
+        match self {
+            Value::String(_) => ValueType::String,
+            Value::Int(_) => ValueType::Int,
+            Value::Float(_) => ValueType::Float,
+            Value::Bool(_) => ValueType::Bool,
+            Value::None => ValueType::None,
+        }
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let r#type: Type = (*self).into();
+        write!(f, "{}", r#type)
+    }
+}
+
+impl From<ValueType> for Type {
+    fn from(r#type: ValueType) -> Type {
+        match r#type {
+            ValueType::String => Type::String,
+            ValueType::Int => Type::Int,
+            ValueType::Float => Type::Float,
+            ValueType::Bool => Type::Bool,
+            ValueType::None => Type::None,
+        }
+    }
+}
+
+impl From<InstructionResult> for Value {
+    /// Only called on the arguments of a registered builtin, whose types are
+    /// checked to be one of `ValueType`
+    fn from(result: InstructionResult) -> Value {
+        match result {
+            InstructionResult::String(value) => Value::String(value),
+            InstructionResult::Int(value) => Value::Int(value),
+            InstructionResult::Float(value) => Value::Float(value),
+            InstructionResult::Bool(value) => Value::Bool(value),
+            InstructionResult::None => Value::None,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl From<Value> for InstructionResult {
+    fn from(value: Value) -> InstructionResult {
+        match value {
+            Value::String(value) => InstructionResult::String(value),
+            Value::Int(value) => InstructionResult::Int(value),
+            Value::Float(value) => InstructionResult::Float(value),
+            Value::Bool(value) => InstructionResult::Bool(value),
+            Value::None => InstructionResult::None,
+        }
+    }
+}
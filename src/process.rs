@@ -1,179 +1,1369 @@
-use std::io::{BufRead, BufReader, ErrorKind, Write};
-use std::os::unix::process::ExitStatusExt;
-use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+#[cfg(feature = "native")]
+use portable_pty::{CommandBuilder, MasterPty, PtySize};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use crate::error::InterpreterError;
-use crate::exitcode::ExitCode;
+use crate::cli::Verbosity;
+use crate::diagnostic::{self, outputln, MessageFormat};
+use crate::error::{InterpreterError, InterpreterErrorType, SpawnError};
+use crate::sandbox::Sandbox;
+use crate::{diff, render};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerminalSize {
+    pub rows: u16,
+    pub columns: u16,
+}
+
+impl Default for TerminalSize {
+    fn default() -> Self {
+        Self {
+            rows: 24,
+            columns: 80,
+        }
+    }
+}
+
+/// How a line of output is normalized before it is compared to the expected
+/// line.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Comparison {
+    pub keep_line_endings: bool,
+    pub keep_trailing_whitespace: bool,
+    pub collapse_whitespace: bool,
+    pub strip_ansi: bool,
+    /// Numbers in the line may differ by this much from the expected ones
+    pub number_tolerance: Option<f64>,
+}
+
+impl Comparison {
+    /// The line as it is compared, only copied when whitespace is collapsed
+    pub fn normalize<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        let mut line = line.strip_suffix('\n').unwrap_or(line);
+        if !self.keep_line_endings {
+            line = line.strip_suffix('\r').unwrap_or(line);
+        }
+        if !self.keep_trailing_whitespace {
+            line = line.trim_end_matches(|c: char| {
+                c.is_whitespace() && (c != '\r' || !self.keep_line_endings)
+            });
+        }
+        if !self.collapse_whitespace {
+            return Cow::Borrowed(line);
+        }
+
+        let mut result = String::with_capacity(line.len());
+        for c in line.chars() {
+            match c.is_whitespace() {
+                true if result.ends_with(' ') => (),
+                true => result.push(' '),
+                false => result.push(c),
+            }
+        }
+        Cow::Owned(result)
+    }
+
+    /// Whether two normalized lines are equal. With a number tolerance the
+    /// text around the numbers must still be equal
+    pub fn matches(&self, expected: &str, output: &str) -> bool {
+        let Some(tolerance) = self.number_tolerance else {
+            return expected == output;
+        };
+        let number = NUMBER
+            .get_or_init(|| regex::Regex::new(r"[-+]?(\d+\.?\d*|\.\d+)([eE][-+]?\d+)?").unwrap());
+        let numbers = |line| {
+            number
+                .find_iter(line)
+                .map(|number| number.as_str().parse::<f64>().ok())
+                .collect::<Vec<_>>()
+        };
+        let (expected_numbers, output_numbers) = (numbers(expected), numbers(output));
+        number.split(expected).eq(number.split(output))
+            && expected_numbers.len() == output_numbers.len()
+            && expected_numbers
+                .into_iter()
+                .zip(output_numbers)
+                .all(|(expected, output)| match (expected, output) {
+                    (Some(expected), Some(output)) => {
+                        expected == output || (expected - output).abs() <= tolerance
+                    }
+                    _ => false,
+                })
+    }
+}
+
+static NUMBER: OnceLock<regex::Regex> = OnceLock::new();
+
+/// Resource limits of the child, memory and file sizes are in megabytes and
+/// the CPU time is in seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub memory: Option<u64>,
+    pub cpu_time: Option<u64>,
+    pub file_size: Option<u64>,
+}
+
+impl Limits {
+    fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    /// Sets the limits of the process with the id `pid`, or of the current
+    /// process if `pid` is `None`. Only async-signal-safe functions are called,
+    /// so this can run between `fork` and `exec`.
+    #[cfg(unix)]
+    fn apply(&self, pid: Option<u32>) -> std::io::Result<()> {
+        const MEGABYTE: u64 = 1024 * 1024;
+        // A limit too large to be set is no limit, it can not panic here.
+        // `rlim_t` is not 64 bits on every platform
+        #[allow(clippy::unnecessary_cast)]
+        let limit = |value: u64| value.min(libc::RLIM_INFINITY as u64) as libc::rlim_t;
+        // The hard CPU limit is a second above the soft one, so the program
+        // gets a `SIGXCPU` that can be reported instead of a plain `SIGKILL`
+        let limits = [
+            (
+                libc::RLIMIT_AS,
+                self.memory.map(|memory| memory.saturating_mul(MEGABYTE)),
+                0,
+            ),
+            (libc::RLIMIT_CPU, self.cpu_time, 1),
+            (
+                libc::RLIMIT_FSIZE,
+                self.file_size.map(|size| size.saturating_mul(MEGABYTE)),
+                0,
+            ),
+        ];
+        for (resource, value, grace) in limits {
+            let Some(value) = value else { continue };
+            let rlimit = libc::rlimit {
+                rlim_cur: limit(value),
+                rlim_max: limit(value.saturating_add(grace)),
+            };
+            // SAFETY: `rlimit` is a valid, initialized `struct rlimit`.
+            let result = match pid {
+                None => unsafe { libc::setrlimit(resource, &rlimit) },
+                #[cfg(target_os = "linux")]
+                Some(pid) => unsafe {
+                    libc::prlimit(pid as libc::pid_t, resource, &rlimit, std::ptr::null_mut())
+                },
+                #[cfg(not(target_os = "linux"))]
+                Some(_) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::Unsupported,
+                        "resource limits are not supported for `#[pty]` tests on this platform",
+                    ))
+                }
+            };
+            if result != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply(&self, _pid: Option<u32>) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            "resource limits are only supported on unix",
+        ))
+    }
+
+    /// Describes the limit the program exceeded, judging by how it exited.
+    #[cfg(unix)]
+    fn exceeded(&self, status: &ExitStatus) -> Option<String> {
+        let signal = status.signal();
+        if let (Some(cpu_time), Some(signal)) = (self.cpu_time, signal) {
+            if Some(signal) == signal_name(libc::SIGXCPU).as_deref() {
+                return Some(format!(
+                    "the program exceeded the CPU time limit of {}s",
+                    cpu_time
+                ));
+            }
+        }
+        if let (Some(file_size), Some(signal)) = (self.file_size, signal) {
+            if Some(signal) == signal_name(libc::SIGXFSZ).as_deref() {
+                return Some(format!(
+                    "the program exceeded the file size limit of {} MB",
+                    file_size
+                ));
+            }
+        }
+        // Running out of memory only makes allocations fail, and programs
+        // handle that in different ways, so this can only be a guess. Most
+        // programs abort when an allocation fails, or crash using the null
+        // pointer they got, while any exit code can have other reasons.
+        let crashed = [libc::SIGSEGV, libc::SIGABRT]
+            .into_iter()
+            .any(|crash| signal.is_some() && signal == signal_name(crash).as_deref());
+        match self.memory {
+            Some(memory) if crashed => Some(format!(
+                "the program may have exceeded the memory limit of {} MB",
+                memory
+            )),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn exceeded(&self, _status: &ExitStatus) -> Option<String> {
+        None
+    }
+}
+
+/// How the program exited, the same for a program in pipes and in a
+/// pseudo-terminal
+#[derive(Debug, Clone)]
+struct ExitStatus {
+    code: u32,
+    /// The name of the signal that terminated the program
+    signal: Option<String>,
+}
+
+impl ExitStatus {
+    fn exit_code(&self) -> u32 {
+        self.code
+    }
+
+    fn signal(&self) -> Option<&str> {
+        self.signal.as_deref()
+    }
+}
+
+impl From<std::process::ExitStatus> for ExitStatus {
+    fn from(status: std::process::ExitStatus) -> ExitStatus {
+        #[cfg(unix)]
+        let signal = {
+            use std::os::unix::process::ExitStatusExt;
+            status.signal().and_then(signal_name)
+        };
+        #[cfg(not(unix))]
+        let signal = None;
+        ExitStatus {
+            code: status.code().unwrap_or(1) as u32,
+            signal,
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+impl From<portable_pty::ExitStatus> for ExitStatus {
+    fn from(status: portable_pty::ExitStatus) -> ExitStatus {
+        ExitStatus {
+            code: status.exit_code(),
+            signal: status.signal().map(String::from),
+        }
+    }
+}
+
+/// The name of `signal` in the form `portable_pty::ExitStatus` reports it.
+#[cfg(unix)]
+fn signal_name(signal: libc::c_int) -> Option<String> {
+    // SAFETY: `strsignal` returns null or a pointer to a nul-terminated string.
+    let name = unsafe { libc::strsignal(signal) };
+    match name.is_null() {
+        true => None,
+        // SAFETY: `name` is not null and points to a nul-terminated string.
+        false => Some(
+            unsafe { std::ffi::CStr::from_ptr(name) }
+                .to_string_lossy()
+                .into_owned(),
+        ),
+    }
+}
+
+/// Where a copy of everything the program prints is written, set with
+/// `#[stdout_to]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputCopy {
+    pub path: PathBuf,
+    /// Also print the output as it arrives
+    pub tee: bool,
+    /// Set when the program is restarted, so the file keeps every run
+    pub append: bool,
+}
+
+impl OutputCopy {
+    fn open(&self) -> std::io::Result<File> {
+        if let Some(parent) = self
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        File::options()
+            .create(true)
+            .write(true)
+            .append(self.append)
+            .truncate(!self.append)
+            .open(&self.path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessOptions {
+    pub terminal: Option<TerminalSize>,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    pub timeout: Option<Duration>,
+    pub comparison: Comparison,
+    pub limits: Limits,
+    pub max_runtime: Option<Duration>,
+    pub wrappers: Vec<String>,
+    /// A file that is the input of the program instead of `input`
+    pub stdin: Option<PathBuf>,
+    pub stdout_to: Option<OutputCopy>,
+    pub sandbox: bool,
+}
+
+enum Child {
+    Pipe(std::process::Child),
+    #[cfg(feature = "native")]
+    Pty {
+        child: Box<dyn portable_pty::Child + Send + Sync>,
+        _master: Box<dyn MasterPty + Send>,
+    },
+}
+
+impl Child {
+    fn id(&self) -> Option<u32> {
+        match self {
+            Child::Pipe(child) => Some(child.id()),
+            #[cfg(feature = "native")]
+            Child::Pty { child, .. } => child.process_id(),
+        }
+    }
+
+    /// Waits for the child to exit, or until `deadline` has passed, in which
+    /// case `None` is returned.
+    fn wait(&mut self, deadline: Option<Instant>) -> std::io::Result<Option<ExitStatus>> {
+        let Some(deadline) = deadline else {
+            return match self {
+                Child::Pipe(child) => child.wait().map(ExitStatus::from),
+                #[cfg(feature = "native")]
+                Child::Pty { child, .. } => child.wait().map(ExitStatus::from),
+            }
+            .map(Some);
+        };
+
+        loop {
+            let status = match self {
+                Child::Pipe(child) => child.try_wait()?.map(ExitStatus::from),
+                #[cfg(feature = "native")]
+                Child::Pty { child, .. } => child.try_wait()?.map(ExitStatus::from),
+            };
+            if status.is_some() || Instant::now() >= deadline {
+                return Ok(status);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Kills every process in the process group led by the child, which also
+/// stops the programs the child started in the background. A child in a
+/// pseudo-terminal leads a session of its own, and with it a process group.
+#[cfg(unix)]
+fn kill_process_group(pid: u32) {
+    // SAFETY: `killpg` has no memory safety requirements, at worst the group
+    // no longer exists and an error is returned.
+    unsafe {
+        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_pid: u32) {}
+
+/// How long `restart` waits for the program to exit after closing its input,
+/// before it is killed.
+const RESTART_GRACE: Duration = Duration::from_millis(100);
+
+/// The most bytes of stderr kept per test, older output is dropped first.
+const STDERR_LIMIT: usize = 4096;
+
+/// Drains the stderr of the child on a separate thread, so a program that
+/// writes a lot to stderr can never block on a full pipe, and keeps the last
+/// `STDERR_LIMIT` bytes.
+struct StderrCapture {
+    buffer: Arc<Mutex<VecDeque<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    truncated: Arc<AtomicBool>,
+}
+
+impl StderrCapture {
+    fn new(mut stderr: impl Read + Send + 'static) -> Self {
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+        let truncated = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let buffer = Arc::clone(&buffer);
+            let truncated = Arc::clone(&truncated);
+            std::thread::spawn(move || {
+                let mut chunk = [0; 1024];
+                while let Ok(read @ 1..) = stderr.read(&mut chunk) {
+                    let mut buffer = buffer.lock().unwrap();
+                    buffer.extend(&chunk[..read]);
+                    if buffer.len() > STDERR_LIMIT {
+                        let excess = buffer.len() - STDERR_LIMIT;
+                        buffer.drain(..excess);
+                        truncated.store(true, Ordering::Relaxed);
+                    }
+                }
+            })
+        };
+
+        Self {
+            buffer,
+            thread: Some(thread),
+            truncated,
+        }
+    }
+
+    /// Waits until the child has closed stderr and returns what it wrote. A
+    /// child that started programs of its own which still hold stderr open is
+    /// only waited for a short while.
+    fn collect(&mut self) -> (String, bool) {
+        let start = Instant::now();
+        while self
+            .thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+            && start.elapsed() < Duration::from_millis(500)
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if let Some(thread) = self.thread.take_if(|thread| thread.is_finished()) {
+            let _ = thread.join();
+        }
+        let buffer = self.buffer.lock().unwrap();
+        let truncated = self.truncated.load(Ordering::Relaxed);
+        let (front, back) = buffer.as_slices();
+        let stderr = String::from_utf8_lossy(&[front, back].concat()).into_owned();
+        match truncated {
+            // The first line was cut off, so only show the lines after it
+            true => match stderr.split_once('\n') {
+                Some((_, rest)) => (rest.to_string(), truncated),
+                None => (stderr, truncated),
+            },
+            false => (stderr, truncated),
+        }
+    }
+}
+
+/// Reads the stdout of the child on a separate thread and sends every line
+/// it reads with the time it arrived, so reading can give up after a timeout.
+/// The start of a line that has not been ended yet is kept in `pending`.
+/// Everything that is read is also written to `copy` and, with `tee`, to
+/// stdout.
+struct OutputReader {
+    lines: Receiver<(String, Instant)>,
+    pending: Arc<Mutex<Vec<u8>>>,
+}
+
+impl OutputReader {
+    fn new(mut stdout: impl Read + Send + 'static, mut copy: Option<File>, tee: bool) -> Self {
+        let (sender, lines) = mpsc::channel();
+        let pending = Arc::new(Mutex::new(Vec::new()));
+        {
+            let pending = Arc::clone(&pending);
+            std::thread::spawn(move || {
+                let mut chunk = [0; 8192];
+                loop {
+                    let read = match stdout.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(read) => read,
+                        Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(_) => break,
+                    };
+                    if let Some(file) = &mut copy {
+                        let _ = file.write_all(&chunk[..read]);
+                    }
+                    if tee {
+                        let mut output: Box<dyn Write> = match diagnostic::message_format() {
+                            MessageFormat::Human => Box::new(std::io::stdout().lock()),
+                            MessageFormat::Json => Box::new(std::io::stderr().lock()),
+                        };
+                        let _ = output.write_all(&chunk[..read]);
+                        let _ = output.flush();
+                    }
+                    let mut pending = pending.lock().unwrap();
+                    // Only the new bytes can end a line, so a long line is
+                    // not searched again for every chunk
+                    let mut searched = pending.len();
+                    pending.extend_from_slice(&chunk[..read]);
+                    while let Some(index) =
+                        pending[searched..].iter().position(|byte| *byte == b'\n')
+                    {
+                        let line = pending.drain(..=searched + index).collect::<Vec<u8>>();
+                        searched = 0;
+                        if sender
+                            .send((String::from_utf8_lossy(&line).into_owned(), Instant::now()))
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                }
+                let rest = std::mem::take(&mut *pending.lock().unwrap());
+                if !rest.is_empty() {
+                    let _ =
+                        sender.send((String::from_utf8_lossy(&rest).into_owned(), Instant::now()));
+                }
+            });
+        }
+
+        Self { lines, pending }
+    }
+
+    fn pending(&self) -> String {
+        String::from_utf8_lossy(&self.pending.lock().unwrap()).into_owned()
+    }
+}
 
 pub struct Process {
+    command: String,
     child: Child,
-    stdin: ChildStdin,
-    reader: BufReader<ChildStdout>,
-    debug: bool,
+    stderr: Option<StderrCapture>,
+    stdin: Option<Box<dyn Write + Send>>,
+    output: OutputReader,
+    timeout: Option<Duration>,
+    comparison: Comparison,
+    limits: Limits,
+    max_runtime: Option<Duration>,
+    status: Option<ExitStatus>,
+    terminal: bool,
+    verbosity: Verbosity,
+    start: Instant,
+    /// When the latest input was sent, or the program was started
+    last_input: Instant,
+    /// How long after `last_input` the first line read since then arrived
+    last_response: Option<Duration>,
+    options: ProcessOptions,
+    /// Kept when the program is restarted, so it sees the files it wrote
+    sandbox: Option<Sandbox>,
+    /// Every message of `trace`, also without `--verbose`
+    transcript: Vec<String>,
+}
+
+#[derive(PartialEq)]
+enum Quote {
+    None,
+    Single,
+    Double,
 }
 
-fn split_command(command: &str) -> Vec<String> {
+/// Splits `command` into arguments the way a shell would, without running a
+/// shell. Single quotes keep everything literally, double quotes and a
+/// backslash outside of quotes escape the next character, and a leading `~`
+/// is replaced with the home directory.
+fn split_command(command: &str) -> Result<Vec<String>, String> {
     let mut args = Vec::new();
     let mut current_arg = String::new();
-    let mut in_quotes = false;
+    let mut in_arg = false;
+    let mut quote = Quote::None;
+    let mut chars = command.chars().peekable();
 
-    for c in command.chars() {
-        match c {
-            '\'' if in_quotes => {
-                in_quotes = false; // End of a quoted argument
+    while let Some(c) = chars.next() {
+        match (&quote, c) {
+            (Quote::Single, '\'') | (Quote::Double, '"') => quote = Quote::None,
+            (Quote::Single, c) => current_arg.push(c),
+            (Quote::Double, '\\') => match chars.peek() {
+                Some('"' | '\\' | '$' | '`') => current_arg.push(chars.next().unwrap()),
+                _ => current_arg.push('\\'),
+            },
+            (Quote::Double, c) => current_arg.push(c),
+
+            (Quote::None, '\'') => {
+                quote = Quote::Single;
+                in_arg = true;
             }
-            '\'' => {
-                in_quotes = true; // Start of a quoted argument
+            (Quote::None, '"') => {
+                quote = Quote::Double;
+                in_arg = true;
             }
-            ' ' if !in_quotes => {
-                if !current_arg.is_empty() {
-                    args.push(current_arg.clone());
-                    current_arg.clear();
+            (Quote::None, '\\') => {
+                match chars.next() {
+                    Some(c) => current_arg.push(c),
+                    None => return Err(String::from("Trailing backslash")),
                 }
+                in_arg = true;
             }
-            _ => {
-                current_arg.push(c); // Part of the current argument
+            (Quote::None, ' ' | '\t' | '\n') => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current_arg));
+                    in_arg = false;
+                }
+            }
+            (Quote::None, '~') if !in_arg && matches!(chars.peek(), None | Some('/' | ' ')) => {
+                match std::env::var("HOME") {
+                    Ok(home) => current_arg.push_str(&home),
+                    Err(_) => current_arg.push('~'),
+                }
+                in_arg = true;
+            }
+            (Quote::None, c) => {
+                current_arg.push(c);
+                in_arg = true;
             }
         }
     }
 
-    if !current_arg.is_empty() {
+    match quote {
+        Quote::None => (),
+        _ => return Err(String::from("Unterminated quote")),
+    }
+    if in_arg {
         args.push(current_arg);
     }
 
-    args
+    Ok(args)
 }
 
-impl Process {
-    pub fn new(command: &str, debug: bool) -> Self {
-        let command_vec = split_command(command);
-        let child = Command::new(command_vec[0].clone())
-            .args(command_vec[1..].iter())
-            .spawn();
+/// Disables the output buffering of the child with `stdbuf`, so every line it
+/// prints can be read as soon as it is written.
+#[cfg(unix)]
+fn unbuffered_wrapper() -> Vec<String> {
+    vec![
+        String::from("stdbuf"),
+        String::from("-o0"),
+        String::from("-e0"),
+    ]
+}
+
+/// `stdbuf` is not available, so the child is responsible for flushing its
+/// own output.
+#[cfg(not(unix))]
+fn unbuffered_wrapper() -> Vec<String> {
+    Vec::new()
+}
 
-        match child {
-            Ok(mut child) => {
-                let _ = child.kill();
+/// Turns off the echo of the terminal, otherwise every line sent to the child
+/// would be read back before its output.
+#[cfg(all(unix, feature = "native"))]
+fn disable_echo(master: &dyn MasterPty) {
+    if let Some(fd) = master.as_raw_fd() {
+        // SAFETY: `fd` is the open file descriptor of the pseudo-terminal and
+        // `termios` is fully initialized by `tcgetattr` before it is read.
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(fd, &mut termios) == 0 {
+                termios.c_lflag &= !libc::ECHO;
+                libc::tcsetattr(fd, libc::TCSANOW, &termios);
             }
-            Err(e) => match e.kind() {
-                ErrorKind::NotFound => {
-                    eprintln!("Failed to find command: {}", command);
-                    std::process::exit(ExitCode::ProcessNotFound as i32);
-                }
-                ErrorKind::PermissionDenied => {
-                    eprintln!("Permission denied to run command: {}", command);
-                    std::process::exit(ExitCode::ProcessPermissionDenied as i32);
-                }
-                _ => {
-                    eprintln!("Failed to run command: {}", command);
-                    std::process::exit(ExitCode::Unknown as i32);
+        }
+    }
+}
+
+#[cfg(all(not(unix), feature = "native"))]
+fn disable_echo(_master: &dyn MasterPty) {}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    metadata.is_file()
+}
+
+/// The names `program` may have on disk, on Windows `main` can also refer to
+/// `main.exe` and the other extensions in `PATHEXT`.
+fn program_names(program: &Path) -> Vec<PathBuf> {
+    let mut names = vec![program.to_path_buf()];
+    if cfg!(windows) && program.extension().is_none() {
+        let extensions = std::env::var("PATHEXT").unwrap_or(String::from(".EXE;.BAT;.CMD"));
+        for extension in extensions.split(';').filter(|e| !e.is_empty()) {
+            names.push(program.with_extension(extension.trim_start_matches('.')));
+        }
+    }
+    names
+}
+
+/// The files that a command runs or reads: its program, found like when it
+/// is started, and every argument that is a file in `cwd`
+pub fn files(command: &str, cwd: &Path) -> Vec<PathBuf> {
+    let args = split_command(command).unwrap_or_default();
+    let mut files = Vec::new();
+    if let Some(Ok(program)) = args.first().map(|program| find_executable(program, cwd)) {
+        files.push(program);
+    }
+    files.extend(
+        args.iter()
+            .skip(1)
+            .map(|arg| cwd.join(arg))
+            .filter(|path| path.is_file()),
+    );
+    files
+}
+
+/// Checks that `program` can be run, either as a path relative to `cwd` or by
+/// looking it up in `PATH`, without starting it.
+fn find_executable(program: &str, cwd: &Path) -> Result<PathBuf, SpawnError> {
+    let path = Path::new(program);
+    let directories = if path.components().count() > 1 {
+        vec![cwd.to_path_buf()]
+    } else {
+        std::env::var_os("PATH")
+            .map(|paths| std::env::split_paths(&paths).collect())
+            .unwrap_or_default()
+    };
+
+    let mut found = false;
+    for directory in directories {
+        for candidate in program_names(&directory.join(path)) {
+            if let Ok(metadata) = std::fs::metadata(&candidate) {
+                if is_executable(&metadata) {
+                    return Ok(candidate);
                 }
-            },
+                found |= metadata.is_file();
+            }
         }
+    }
 
-        let mut child = match Command::new("stdbuf")
-            .arg("-o0")
-            .arg("-e0")
-            .args(command_vec.iter())
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
+    match found {
+        true => Err(SpawnError::PermissionDenied(program.to_string())),
+        false => Err(SpawnError::NotFound(program.to_string())),
+    }
+}
+
+type Pipes = (
+    Child,
+    Option<Box<dyn Write + Send>>,
+    Box<dyn Read + Send>,
+    Option<StderrCapture>,
+);
+
+fn spawn_pipe(
+    command_vec: &[String],
+    cwd: &Path,
+    env: &[(String, String)],
+    limits: Limits,
+    input: Option<File>,
+    sandbox: Option<&Sandbox>,
+) -> std::io::Result<Pipes> {
+    let mut command = Command::new(&command_vec[0]);
+    command.args(command_vec[1..].iter());
+    // The child leads a process group of its own, so the programs it starts
+    // can be stopped together with it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    if !limits.is_empty() {
+        #[cfg(unix)]
         {
-            Ok(child) => child,
-            Err(e) => match e.kind() {
-                ErrorKind::NotFound => {
-                    eprintln!("Failed to find command: {}", command);
-                    std::process::exit(ExitCode::ProcessNotFound as i32);
-                }
-                ErrorKind::PermissionDenied => {
-                    eprintln!("Permission denied to run command: {}", command);
-                    std::process::exit(ExitCode::ProcessPermissionDenied as i32);
-                }
-                _ => {
-                    eprintln!("Failed to run command: {}", command);
-                    std::process::exit(ExitCode::Unknown as i32);
-                }
-            },
+            use std::os::unix::process::CommandExt;
+            // SAFETY: `apply` only calls `setrlimit`, which is
+            // async-signal-safe.
+            unsafe {
+                command.pre_exec(move || limits.apply(None));
+            }
+        }
+        #[cfg(not(unix))]
+        limits.apply(None)?;
+    }
+    if let Some(sandbox) = sandbox {
+        command.env_clear().envs(sandbox.env());
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::process::CommandExt;
+            let isolation = sandbox.isolation(cwd)?;
+            // SAFETY: `enter` only calls async-signal-safe functions.
+            unsafe {
+                command.pre_exec(move || isolation.enter());
+            }
+        }
+    }
+    let mut child = command
+        .current_dir(cwd)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(match input {
+            Some(file) => Stdio::from(file),
+            None => Stdio::piped(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdin = child
+        .stdin
+        .take()
+        .map(|stdin| Box::new(stdin) as Box<dyn Write + Send>);
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let stderr = child.stderr.take().expect("Failed to capture stderr");
+    Ok((
+        Child::Pipe(child),
+        stdin,
+        Box::new(stdout),
+        Some(StderrCapture::new(stderr)),
+    ))
+}
+
+#[cfg(feature = "native")]
+fn spawn_pty(
+    command_vec: &[String],
+    size: TerminalSize,
+    cwd: &Path,
+    env: &[(String, String)],
+    limits: Limits,
+    input: Option<File>,
+) -> Result<Pipes, Box<dyn std::error::Error + Send + Sync>> {
+    let pair = portable_pty::native_pty_system().openpty(PtySize {
+        rows: size.rows,
+        cols: size.columns,
+        pixel_width: 0,
+        pixel_height: 0,
+    })?;
+    disable_echo(pair.master.as_ref());
+
+    let mut command = CommandBuilder::new(&command_vec[0]);
+    command.args(command_vec[1..].iter());
+    command.cwd(cwd);
+    for (key, value) in env {
+        command.env(key, value);
+    }
+    let mut child = pair.slave.spawn_command(command)?;
+    drop(pair.slave);
+    if !limits.is_empty() {
+        // The limits can only be set once the child is running, so a program
+        // can exceed them for a moment right after it is started
+        if let Err(e) = limits.apply(child.process_id()) {
+            let _ = child.kill();
+            return Err(e.into());
+        }
+    }
+
+    let mut stdin = pair.master.take_writer()?;
+    let stdout = pair.master.try_clone_reader()?;
+    // The file is typed into the terminal while the program runs, since the
+    // program may not read it all before it prints
+    let stdin = match input {
+        Some(mut file) => {
+            std::thread::spawn(move || {
+                let _ = std::io::copy(&mut file, &mut stdin);
+                let _ = stdin.write_all(b"\x04");
+            });
+            None
+        }
+        None => Some(stdin),
+    };
+    Ok((
+        Child::Pty {
+            child,
+            _master: pair.master,
+        },
+        stdin,
+        stdout,
+        None,
+    ))
+}
+
+impl Process {
+    pub fn new(
+        command: &str,
+        verbosity: Verbosity,
+        options: ProcessOptions,
+    ) -> Result<Self, SpawnError> {
+        Self::start(command, verbosity, options, None)
+    }
+
+    /// Starts the program in `sandbox`, or in a new sandbox with `--sandbox`
+    fn start(
+        command: &str,
+        verbosity: Verbosity,
+        options: ProcessOptions,
+        sandbox: Option<Sandbox>,
+    ) -> Result<Self, SpawnError> {
+        let cwd = match &options.cwd {
+            Some(cwd) => cwd.clone(),
+            None => std::env::current_dir().unwrap_or_default(),
         };
+        if !cwd.is_dir() {
+            return Err(SpawnError::Failed {
+                command: command.to_string(),
+                message: format!("`{}` is not a directory", cwd.display()),
+            });
+        }
 
-        let stdin = child.stdin.take().expect("Failed to capture stdin");
-        let stdout = child.stdout.take().expect("Failed to capture stdout");
-        let reader = BufReader::new(stdout);
+        // Every wrapper is a command that is run with the rest of the chain as
+        // its arguments, the first wrapper is the outermost one
+        let mut chain = Vec::new();
+        for part in options.wrappers.iter().map(String::as_str).chain([command]) {
+            let part_vec = split_command(part).map_err(|message| SpawnError::Failed {
+                command: part.to_string(),
+                message,
+            })?;
+            match part_vec.first() {
+                Some(program) => find_executable(program, &cwd)?,
+                None => return Err(SpawnError::NotFound(part.to_string())),
+            };
+            chain.extend(part_vec);
+        }
+        let command_vec = match options.terminal {
+            Some(_) => chain,
+            None => [unbuffered_wrapper(), chain].concat(),
+        };
 
-        Self {
+        let copy = match &options.stdout_to {
+            Some(copy) => Some(copy.open().map_err(|e| SpawnError::Failed {
+                command: command.to_string(),
+                message: format!("Failed to open `{}`: {}", copy.path.display(), e),
+            })?),
+            None => None,
+        };
+        let input = match &options.stdin {
+            Some(path) => Some(File::open(path).map_err(|e| SpawnError::Failed {
+                command: command.to_string(),
+                message: format!("Failed to open `{}`: {}", path.display(), e),
+            })?),
+            None => None,
+        };
+        if let Some(reason) =
+            Sandbox::unsupported(options.terminal.is_some()).filter(|_| options.sandbox)
+        {
+            return Err(SpawnError::Failed {
+                command: command.to_string(),
+                message: format!("Failed to create the sandbox: {}", reason),
+            });
+        }
+        let sandbox = match (sandbox, options.sandbox) {
+            (Some(sandbox), _) => Some(sandbox),
+            (None, true) => Some(Sandbox::new().map_err(|e| SpawnError::Failed {
+                command: command.to_string(),
+                message: format!("Failed to create the sandbox: {}", e),
+            })?),
+            (None, false) => None,
+        };
+        let spawned = match options.terminal {
+            #[cfg(feature = "native")]
+            Some(size) => spawn_pty(
+                &command_vec,
+                size,
+                &cwd,
+                &options.env,
+                options.limits,
+                input,
+            )
+            .map_err(|e| e.to_string()),
+            #[cfg(not(feature = "native"))]
+            Some(_) => Err(String::from(
+                "pseudo-terminals are not supported in this build of test-script",
+            )),
+            None => spawn_pipe(
+                &command_vec,
+                &cwd,
+                &options.env,
+                options.limits,
+                input,
+                sandbox.as_ref(),
+            )
+            .map_err(|e| e.to_string()),
+        };
+        let (child, stdin, stdout, stderr) = spawned.map_err(|message| SpawnError::Failed {
+            command: command.to_string(),
+            message,
+        })?;
+        let tee = options.stdout_to.as_ref().is_some_and(|copy| copy.tee);
+        let output = OutputReader::new(stdout, copy, tee);
+        let start = Instant::now();
+
+        Ok(Self {
+            command: command.to_string(),
             child,
+            stderr,
             stdin,
-            reader,
-            debug,
+            output,
+            timeout: options.timeout,
+            comparison: options.comparison,
+            limits: options.limits,
+            max_runtime: options.max_runtime,
+            status: None,
+            terminal: options.terminal.is_some(),
+            verbosity,
+            start,
+            last_input: start,
+            last_response: None,
+            options,
+            sandbox,
+            transcript: Vec::new(),
+        })
+    }
+
+    /// Returns the end of what the program wrote to stderr, and whether the
+    /// beginning was cut off. Only call this after the program has exited.
+    pub fn stderr(&mut self) -> Option<(String, bool)> {
+        let (stderr, truncated) = self.stderr.as_mut()?.collect();
+        match stderr.trim_end().is_empty() {
+            true => None,
+            false => Some((stderr, truncated)),
+        }
+    }
+
+    fn trace(&mut self, message: &str) {
+        let line = format!("[{:>8.3}s] {}", self.start.elapsed().as_secs_f64(), message);
+        if self.verbosity >= Verbosity::Verbose {
+            outputln!("{}", line);
         }
+        self.transcript.push(line);
     }
 
+    /// What was sent to and read from the program, as `--verbose` prints it
+    pub fn transcript(&self) -> &[String] {
+        &self.transcript
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// The environment variables the program was started with
+    pub fn environment(&self) -> Vec<(String, String)> {
+        let mut environment = match &self.sandbox {
+            Some(sandbox) => sandbox.env(),
+            None => std::env::vars().collect(),
+        };
+        for (key, value) in &self.options.env {
+            environment.retain(|(existing, _)| existing != key);
+            environment.push((key.clone(), value.clone()));
+        }
+        environment
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), InterpreterError> {
+        let stdin = self.stdin.as_mut().ok_or_else(|| {
+            InterpreterErrorType::TestFailed(match &self.options.stdin {
+                Some(path) => format!(
+                    "Failed to write to stdin, it is read from `{}`",
+                    path.display()
+                ),
+                None => "Failed to write to stdin, it was closed by `send_eof()`".to_string(),
+            })
+        })?;
+        stdin.write_all(bytes).map_err(|_| {
+            InterpreterErrorType::TestFailed("Failed to write to stdin".to_string())
+        })?;
+        stdin.flush().map_err(|_| {
+            InterpreterErrorType::TestFailed("Failed to flush stdin".to_string()).into()
+        })
+    }
+
+    /// Sends every line of `input` followed by a newline, in a single write
     pub fn send(&mut self, input: &str) -> Result<(), InterpreterError> {
-        let lines = input.split('\n');
-        for line in lines {
-            if self.debug {
-                println!("Sending: {}", line);
+        let mut bytes = Vec::with_capacity(input.len() + 1);
+        for line in input.split('\n') {
+            self.trace(&format!("Sending: {}", line));
+            bytes.extend_from_slice(line.as_bytes());
+            bytes.push(b'\n');
+        }
+        self.write(&bytes)?;
+        self.sent();
+        self.trace(&format!("Sent: {}", input));
+        Ok(())
+    }
+
+    /// Sends `input` as it is, without a newline after it.
+    pub fn send_raw(&mut self, input: &str) -> Result<(), InterpreterError> {
+        self.trace(&format!("Sending raw: {}", input.escape_debug()));
+        self.write(input.as_bytes())?;
+        self.sent();
+        Ok(())
+    }
+
+    fn sent(&mut self) {
+        self.last_input = Instant::now();
+        self.last_response = None;
+    }
+
+    /// Closes stdin, so the program reads the end of its input. In a
+    /// pseudo-terminal the terminal's end of file character is sent instead,
+    /// which ends the input when it is at the start of a line.
+    pub fn send_eof(&mut self) -> Result<(), InterpreterError> {
+        self.trace("Sending end of file");
+        match self.terminal {
+            true => self.write(b"\x04"),
+            false => {
+                self.stdin = None;
+                Ok(())
             }
-            writeln!(self.stdin, "{}", line).map_err(|_| {
-                InterpreterError::TestFailed("Failed to write to stdin".to_string())
-            })?;
-            self.stdin
-                .flush()
-                .map_err(|_| InterpreterError::TestFailed("Failed to flush stdin".to_string()))?;
         }
-        if self.debug {
-            println!("Sent: {}", input);
+    }
+
+    /// Waits for the next line of output, without ANSI escape sequences in a
+    /// pseudo-terminal or with `--strip-ansi`
+    fn next_line(&mut self) -> Result<String, RecvTimeoutError> {
+        let (mut output, arrived) = match self.timeout {
+            Some(timeout) => self.output.lines.recv_timeout(timeout),
+            None => self.output.lines.recv().map_err(RecvTimeoutError::from),
+        }?;
+        self.last_response = self
+            .last_response
+            .or(Some(arrived.saturating_duration_since(self.last_input)));
+        if self.terminal || self.comparison.strip_ansi {
+            output = strip_ansi_escapes::strip_str(&output);
+        }
+        self.trace(&format!("Read: {}", output.trim_end()));
+        Ok(output)
+    }
+
+    /// Reads and discards `count` lines of output
+    pub fn skip_lines(&mut self, count: usize) -> Result<(), InterpreterError> {
+        self.trace(&format!("Skipping {} lines", count));
+        for skipped in 0..count {
+            match self.next_line() {
+                Ok(_) => (),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(InterpreterErrorType::TestFailed(format!(
+                        "Expected to skip {} lines, but the program exited after {}",
+                        count, skipped
+                    ))
+                    .into());
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(InterpreterErrorType::TestFailed(format!(
+                        "Expected to skip {} lines, but no output was produced within {}s after {} (pending buffer: {})",
+                        count,
+                        self.timeout.unwrap_or_default().as_secs_f64(),
+                        skipped,
+                        render::string(&self.output.pending())
+                    ))
+                    .into());
+                }
+            }
         }
         Ok(())
     }
 
-    pub fn read_line(&mut self, expected: String) -> Result<(), InterpreterError> {
-        if self.debug {
-            println!("Reading line");
+    /// Reads and discards lines of output up to and including the first line
+    /// that `regex` matches
+    pub fn skip_until(&mut self, regex: &regex::Regex) -> Result<(), InterpreterError> {
+        self.trace(&format!("Skipping until `{}`", regex));
+        loop {
+            let output = match self.next_line() {
+                Ok(output) => output,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(InterpreterErrorType::TestFailed(format!(
+                        "Expected a line matching `{}`, but the program exited",
+                        regex
+                    ))
+                    .into());
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(InterpreterErrorType::TestFailed(format!(
+                        "Expected a line matching `{}`, but no output was produced within {}s (pending buffer: {})",
+                        regex,
+                        self.timeout.unwrap_or_default().as_secs_f64(),
+                        render::string(&self.output.pending())
+                    ))
+                    .into());
+                }
+            };
+            if regex.is_match(&self.comparison.normalize(&output)) {
+                return Ok(());
+            }
         }
+    }
 
-        for line in expected.lines() {
-            let mut output = String::new();
-            self.reader
-                .read_line(&mut output)
-                .map_err(|_| InterpreterError::TestFailed("Failed to read line".to_string()))?;
+    /// Reads the normalized lines of output until the program exits or prints
+    /// nothing for the timeout
+    pub fn read_remaining(&mut self) -> Vec<String> {
+        self.trace("Reading the remaining output");
+        let mut lines = Vec::new();
+        while let Ok(output) = self.next_line() {
+            lines.push(self.comparison.normalize(&output).into_owned());
+        }
+        lines
+    }
 
-            if self.debug {
-                println!("Read: {}", output);
+    /// Reads a line of output for every line of `expected`. A single line
+    /// fails as soon as it differs, while several lines are all read so that
+    /// a diff of the whole block can be shown
+    pub fn read_line(&mut self, expected: String) -> Result<(), InterpreterError> {
+        self.trace("Reading line");
+
+        let expected = expected
+            .lines()
+            .map(|line| self.comparison.normalize(line))
+            .collect::<Vec<Cow<str>>>();
+        let block = expected.len() > 1;
+        let mut lines = Vec::new();
+        let mut failed = None;
+        for line in &expected {
+            let received = self.next_line();
+            let exited = received == Err(RecvTimeoutError::Disconnected);
+            let output = match received {
+                Ok(output) => output,
+                Err(RecvTimeoutError::Disconnected) => String::new(),
+                Err(RecvTimeoutError::Timeout) if block => {
+                    failed = Some(format!(
+                        "Output did not match, no output was produced within {}s",
+                        self.timeout.unwrap_or_default().as_secs_f64()
+                    ));
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(InterpreterErrorType::TestFailed(format!(
+                        "Expected {}, but no output was produced within {}s (pending buffer: {})",
+                        render::string(line),
+                        self.timeout.unwrap_or_default().as_secs_f64(),
+                        render::string(&self.output.pending())
+                    ))
+                    .into());
+                }
+            };
+            let output = self.comparison.normalize(&output).into_owned();
+            if !self.comparison.matches(line, &output) {
+                if !block {
+                    return Err(InterpreterErrorType::TestFailed(format!(
+                        "Expected: {}, got: {}",
+                        render::string(line),
+                        render::string(&output)
+                    ))
+                    .into());
+                }
+                failed = Some("Output did not match".to_string());
+                // The program printed nothing more, so the rest is missing
+                if exited {
+                    break;
+                }
             }
+            lines.push(output);
+        }
 
-            if output.trim_end() != line {
-                return Err(InterpreterError::TestFailed(format!(
-                    "Expected: `{}`, got: `{}`",
-                    line,
-                    output.trim_end()
-                )));
+        match failed {
+            Some(message) => {
+                let expected = expected
+                    .iter()
+                    .map(|line| line.as_ref())
+                    .collect::<Vec<&str>>();
+                let output = lines.iter().map(String::as_str).collect::<Vec<&str>>();
+                Err(InterpreterErrorType::OutputMismatch {
+                    message,
+                    diff: diff::unified(&expected, &output, |expected, output| {
+                        self.comparison.matches(expected, output)
+                    }),
+                }
+                .into())
             }
+            None => Ok(()),
+        }
+    }
+
+    /// Stops the program and starts the same command again with the same
+    /// options. The input of the program is closed and it is given a moment
+    /// to exit by itself, before it is killed with everything it started.
+    pub fn restart(&mut self) -> Result<(), InterpreterError> {
+        self.trace("Restarting");
+        // A program in a pseudo-terminal that can not be written to any more
+        // is killed below all the same
+        let _ = self.send_eof();
+        if let Ok(Some(status)) = self.child.wait(Some(Instant::now() + RESTART_GRACE)) {
+            self.status = Some(status);
+        }
+        self.kill();
+        let mut options = self.options.clone();
+        if let Some(copy) = &mut options.stdout_to {
+            copy.append = true;
         }
+        let sandbox = self.sandbox.take();
+        let process =
+            Process::start(&self.command, self.verbosity, options, sandbox).map_err(|e| {
+                InterpreterErrorType::TestFailed(format!("Failed to restart the program: {}", e))
+            })?;
+        let transcript = std::mem::take(&mut self.transcript);
+        *self = process;
+        self.transcript = transcript;
         Ok(())
     }
 
+    /// The time since the program was started.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// How long the first line of output read since the latest input took
+    /// to arrive after it, or after the start before the first input
+    pub fn last_response(&self) -> Option<Duration> {
+        self.last_response
+    }
+
+    /// Stops the program and everything it started after a failed test, it
+    /// is not waited for since it might never exit by itself.
+    pub fn kill(&mut self) {
+        if let Some(pid) = self.child.id() {
+            kill_process_group(pid);
+        }
+        if self.status.is_some() {
+            return;
+        }
+        let status = match &mut self.child {
+            Child::Pipe(child) => child
+                .kill()
+                .and_then(|_| child.wait().map(ExitStatus::from)),
+            #[cfg(feature = "native")]
+            Child::Pty { child, .. } => child
+                .kill()
+                .and_then(|_| child.wait().map(ExitStatus::from)),
+        };
+        self.status = status.ok();
+    }
+
+    /// Describes the resource limit the program exceeded, if it was stopped
+    /// by one. Only call this after the program has exited.
+    pub fn exceeded_limit(&self) -> Option<String> {
+        self.limits.exceeded(self.status.as_ref()?)
+    }
+
     pub fn terminate(&mut self) -> Result<(), InterpreterError> {
-        let status = self.child.wait().map_err(|_| {
-            InterpreterError::TestFailed("Failed to wait for child process".to_string())
+        let deadline = self.max_runtime.map(|max_runtime| self.start + max_runtime);
+        let status = self.child.wait(deadline).map_err(|_| {
+            InterpreterErrorType::TestFailed("Failed to wait for child process".to_string())
         })?;
+        let Some(status) = status else {
+            return Err(InterpreterErrorType::TestFailed(format!(
+                "Process did not exit within the maximum runtime of {}ms",
+                self.max_runtime.unwrap_or_default().as_millis()
+            ))
+            .into());
+        };
+        self.status = Some(status.clone());
+
+        if let Some(max_runtime) = self.max_runtime {
+            let runtime = self.start.elapsed();
+            if runtime > max_runtime {
+                return Err(InterpreterErrorType::TestFailed(format!(
+                    "Process ran for {}ms, longer than the maximum runtime of {}ms",
+                    runtime.as_millis(),
+                    max_runtime.as_millis()
+                ))
+                .into());
+            }
+        }
 
         if let Some(signal) = status.signal() {
-            return Err(InterpreterError::TestFailed(format!(
+            return Err(InterpreterErrorType::TestFailed(format!(
                 "Process terminated by signal: {}",
                 signal
-            )));
+            ))
+            .into());
         }
 
-        match status.code() {
-            Some(0) => Ok(()),
-            Some(code) => Err(InterpreterError::TestFailed(format!(
+        match status.exit_code() {
+            0 => Ok(()),
+            code => Err(InterpreterErrorType::TestFailed(format!(
                 "Process exited with code: {}",
                 code
-            ))),
-            None => Err(InterpreterError::TestFailed(
-                "Process terminated without exit code".to_string(),
-            )),
+            ))
+            .into()),
         }
     }
 }
@@ -0,0 +1,63 @@
+use crate::diagnostic::outputln;
+
+use indexmap::IndexMap;
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+struct Entry {
+    count: u64,
+    time: Duration,
+}
+
+/// Counts and cumulative time of everything the interpreter does, collected
+/// with `--profile-interpreter`. An entry is a kind of work and an optional
+/// detail, such as a builtin and its name
+#[derive(Debug, Default)]
+pub struct Profile {
+    entries: IndexMap<(&'static str, &'static str), Entry>,
+    /// Time spent in the instructions inside each running instruction
+    nested: Vec<Duration>,
+}
+
+impl Profile {
+    pub fn enter(&mut self) {
+        self.nested.push(Duration::ZERO);
+    }
+
+    /// Only the time not spent in nested instructions is recorded, so a
+    /// recursive function is not counted once for every call on the stack
+    pub fn exit(&mut self, kind: &'static str, detail: &'static str, time: Duration) {
+        let nested = self.nested.pop().unwrap_or_default();
+        if let Some(parent) = self.nested.last_mut() {
+            *parent += time;
+        }
+        self.record(kind, detail, time.saturating_sub(nested));
+    }
+
+    pub fn record(&mut self, kind: &'static str, detail: &'static str, time: Duration) {
+        let entry = self.entries.entry((kind, detail)).or_default();
+        entry.count += 1;
+        entry.time += time;
+    }
+
+    pub fn print(&self) {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.time));
+
+        outputln!();
+        outputln!("Interpreter profile, slowest first:");
+        outputln!("{:>10} {:>14}  what", "count", "time");
+        for ((kind, detail), entry) in entries {
+            let what = match detail.is_empty() {
+                true => kind.to_string(),
+                false => format!("{kind} `{detail}`"),
+            };
+            outputln!(
+                "{:>10} {:>14}  {}",
+                entry.count,
+                format!("{:.3?}", entry.time),
+                what
+            );
+        }
+    }
+}
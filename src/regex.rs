@@ -1,12 +1,27 @@
 use crate::error::{ParseError, ParseErrorType};
-use crate::token::Token;
+use crate::instruction::InstructionResult;
+use crate::token::{Token, TokenType};
 use regex_syntax::hir;
 
-fn expand_class(class: hir::ClassUnicode) -> Vec<String> {
+use std::collections::HashSet;
+
+/// Expands a class into its ASCII characters. Classes like `.` and `\d`
+/// contain thousands of other characters that nobody means to test with, so
+/// other characters are only kept if the range is written out in `pattern`,
+/// like `[éü]` or `[α-ω]`.
+fn expand_class(class: hir::ClassUnicode, pattern: &str) -> Vec<String> {
     let mut result = Vec::new();
     for range in class.ranges().iter() {
-        for c in range.start()..=range.end() {
-            if c != '\n' && c.is_ascii() && (c.is_ascii_graphic() || c.is_ascii_whitespace()) {
+        let written = is_written(range, pattern);
+        let end = match written {
+            true => range.end(),
+            false => range.end().min('\x7f'),
+        };
+        for c in range.start()..=end {
+            if c != '\n'
+                && ((c.is_ascii() && (c.is_ascii_graphic() || c.is_ascii_whitespace()))
+                    || (written && !c.is_ascii() && !c.is_control()))
+            {
                 result.push(c.to_string());
             }
         }
@@ -14,15 +29,79 @@ fn expand_class(class: hir::ClassUnicode) -> Vec<String> {
     result
 }
 
+fn is_written(range: &hir::ClassUnicodeRange, pattern: &str) -> bool {
+    pattern.contains(range.start()) && pattern.contains(range.end())
+}
+
+/// Whether `kind` has a class with characters that are not ASCII and are
+/// left out by `expand_class`
+fn is_lossy(kind: &hir::HirKind, pattern: &str) -> bool {
+    match kind {
+        hir::HirKind::Class(hir::Class::Unicode(class)) => class
+            .ranges()
+            .iter()
+            .any(|range| range.end() > '\x7f' && !is_written(range, pattern)),
+        hir::HirKind::Class(hir::Class::Bytes(class)) => {
+            class.to_unicode_class().is_some_and(|class| {
+                is_lossy(&hir::HirKind::Class(hir::Class::Unicode(class)), pattern)
+            })
+        }
+        hir::HirKind::Repetition(hir) => is_lossy(hir.sub.kind(), pattern),
+        hir::HirKind::Capture(hir) => is_lossy(hir.sub.kind(), pattern),
+        hir::HirKind::Concat(hirs) | hir::HirKind::Alternation(hirs) => {
+            hirs.iter().any(|hir| is_lossy(hir.kind(), pattern))
+        }
+        _ => false,
+    }
+}
+
+/// Whether expanding or sampling the regex literal `token` leaves out
+/// characters of a class like `.`, `\w` or `[^a]`, because they are not
+/// ASCII and not written in the regex.
+pub fn is_lossy_literal(token: &Token) -> bool {
+    parse_pattern(token).is_ok_and(|hir| is_lossy(hir.kind(), literal_pattern(token)))
+}
+
+fn literal_pattern(token: &Token) -> &str {
+    match &token.r#type {
+        TokenType::RegexLiteral { value } => &value[1..value.len() - 1],
+        _ => unreachable!(),
+    }
+}
+
+/// Fails if a part of the regex literal `token` creates `count` strings and
+/// that is more than `budget`, so a regex like `[a-z]{10}` is reported
+/// instead of filling up the memory. The literal is reported as it is
+/// written, since printing the part would spell out every character of its
+/// classes.
+fn check_budget(count: usize, budget: usize, token: &Token) -> Result<(), ParseError> {
+    match count > budget {
+        true => Err(ParseError::new(
+            ParseErrorType::RegexTooLarge {
+                pattern: literal_pattern(token).replace('`', "\\`"),
+                count,
+                budget,
+            },
+            token.clone(),
+        )),
+        false => Ok(()),
+    }
+}
+
 fn parse_repetiton(
     hir: hir::Repetition,
     token: &Token,
     max: u32,
+    budget: usize,
 ) -> Result<Vec<String>, ParseError> {
-    let sub_class = parse_kind((hir.sub).into_kind(), token, max)?;
+    let sub_class = parse_kind((hir.sub).clone().into_kind(), token, max, budget)?;
     let mut result: Vec<String> = Vec::new();
     let min = hir.min;
     let max = hir.max.unwrap_or(max);
+    let count = (min..=max).fold(0usize, |count, i| {
+        count.saturating_add(sub_class.len().saturating_pow(i))
+    });
+    check_budget(count, budget, token)?;
     for i in min..=max {
         let combinations = itertools::Itertools::multi_cartesian_product(
             (0..i).map(|_| sub_class.iter().cloned()),
@@ -35,10 +114,17 @@ fn parse_repetiton(
     Ok(result)
 }
 
-fn parse_concat(hirs: Vec<hir::Hir>, token: &Token, max: u32) -> Result<Vec<String>, ParseError> {
+fn parse_concat(
+    hirs: Vec<hir::Hir>,
+    token: &Token,
+    max: u32,
+    budget: usize,
+) -> Result<Vec<String>, ParseError> {
     let mut result = Vec::new();
-    for hir in hirs {
-        let mut sub_class = parse_kind(hir.into_kind(), token, max)?;
+    for hir in hirs.iter() {
+        let mut sub_class = parse_kind(hir.clone().into_kind(), token, max, budget)?;
+        let count = result.len().max(1).saturating_mul(sub_class.len());
+        check_budget(count, budget, token)?;
         if result.is_empty() {
             result.append(&mut sub_class);
         } else {
@@ -55,26 +141,257 @@ fn parse_concat(hirs: Vec<hir::Hir>, token: &Token, max: u32) -> Result<Vec<Stri
     Ok(result)
 }
 
-fn parse_kind(kind: hir::HirKind, token: &Token, max: u32) -> Result<Vec<String>, ParseError> {
+fn parse_alternation(
+    hirs: Vec<hir::Hir>,
+    token: &Token,
+    max: u32,
+    budget: usize,
+) -> Result<Vec<String>, ParseError> {
+    let mut result = Vec::new();
+    for hir in hirs.iter() {
+        result.append(&mut parse_kind(
+            hir.clone().into_kind(),
+            token,
+            max,
+            budget,
+        )?);
+        check_budget(result.len(), budget, token)?;
+    }
+    Ok(result)
+}
+
+fn parse_kind(
+    kind: hir::HirKind,
+    token: &Token,
+    max: u32,
+    budget: usize,
+) -> Result<Vec<String>, ParseError> {
     match kind {
         hir::HirKind::Literal(hir) => Ok(vec![String::from_utf8_lossy(&hir.0).to_string()]),
         hir::HirKind::Class(hir) => match hir {
-            hir::Class::Unicode(class) => Ok(expand_class(class)),
-            hir::Class::Bytes(class) => Ok(expand_class(class.to_unicode_class().unwrap())),
+            hir::Class::Unicode(class) => Ok(expand_class(class, literal_pattern(token))),
+            hir::Class::Bytes(class) => Ok(expand_class(
+                class.to_unicode_class().unwrap(),
+                literal_pattern(token),
+            )),
         },
-        hir::HirKind::Repetition(hir) => Ok(parse_repetiton(hir, token, max)?),
-        hir::HirKind::Concat(hirs) => Ok(parse_concat(hirs, token, max)?),
-        _hir => Err(ParseError::new(ParseErrorType::RegexError, token.clone())),
+        hir::HirKind::Repetition(hir) => Ok(parse_repetiton(hir, token, max, budget)?),
+        hir::HirKind::Concat(hirs) => Ok(parse_concat(hirs, token, max, budget)?),
+        hir::HirKind::Alternation(hirs) => Ok(parse_alternation(hirs, token, max, budget)?),
+        hir::HirKind::Capture(hir) => parse_kind(hir.sub.into_kind(), token, max, budget),
+        hir::HirKind::Empty => Ok(vec![String::new()]),
+        // Every expanded string is a whole line, so anchors always match
+        hir::HirKind::Look(look) if is_anchor(&look) => Ok(vec![String::new()]),
+        _hir => Err(ParseError::new(
+            ParseErrorType::RegexError(None),
+            token.clone(),
+        )),
     }
 }
 
-pub fn parse(token: &Token, max: u32) -> Result<Vec<String>, ParseError> {
-    let value = match &token.r#type {
-        crate::token::TokenType::RegexLiteral { value } => value,
-        _ => unreachable!(),
+/// Turns the error of an invalid pattern into a `ParseError` that points at
+/// the part of the literal the error is about, if it is on the first line.
+fn invalid(token: &Token, pattern: &str, error: regex_syntax::Error) -> ParseError {
+    let (message, span) = match &error {
+        regex_syntax::Error::Parse(e) => (e.kind().to_string(), Some(*e.span())),
+        regex_syntax::Error::Translate(e) => (e.kind().to_string(), Some(*e.span())),
+        _ => (error.to_string(), None),
+    };
+
+    let mut token = token.clone();
+    if let Some(span) = span.filter(|span| span.start.line == 1 && span.end.line == 1) {
+        let offending = match &pattern[span.start.offset..span.end.offset] {
+            "" => " ",
+            offending => offending,
+        };
+        // Every escaped backtick lost its backslash in the lexer
+        let escapes = pattern[..span.start.offset].matches('`').count();
+        token.column += span.start.column + escapes;
+        token.r#type = TokenType::RegexLiteral {
+            value: offending.to_string(),
+        };
+    }
+    ParseError::new(ParseErrorType::RegexError(Some(message)), token)
+}
+
+fn parse_pattern(token: &Token) -> Result<hir::Hir, ParseError> {
+    let pattern = literal_pattern(token);
+    regex_syntax::parse(pattern).map_err(|e| invalid(token, pattern, e))
+}
+
+pub fn parse(token: &Token, max: u32, budget: usize) -> Result<Vec<String>, ParseError> {
+    let kind = parse_pattern(token)?.into_kind();
+    parse_kind(kind.clone(), token, max, budget)
+}
+
+fn is_anchor(look: &hir::Look) -> bool {
+    matches!(
+        look,
+        hir::Look::Start
+            | hir::Look::End
+            | hir::Look::StartLF
+            | hir::Look::EndLF
+            | hir::Look::StartCRLF
+            | hir::Look::EndCRLF
+    )
+}
+
+fn check_kind(kind: &hir::HirKind, token: &Token) -> Result<(), ParseError> {
+    match kind {
+        hir::HirKind::Look(look) if !is_anchor(look) => Err(ParseError::new(
+            ParseErrorType::RegexError(None),
+            token.clone(),
+        )),
+        hir::HirKind::Repetition(hir) => check_kind(hir.sub.kind(), token),
+        hir::HirKind::Capture(hir) => check_kind(hir.sub.kind(), token),
+        hir::HirKind::Concat(hirs) | hir::HirKind::Alternation(hirs) => hirs
+            .iter()
+            .try_for_each(|hir| check_kind(hir.kind(), token)),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the regex literal `token` is valid, without expanding it.
+pub fn validate(token: &Token) -> Result<(), ParseError> {
+    parse_pattern(token).map(|_| ())
+}
+
+/// Checks that random strings can be created from the regex literal `token`
+/// with `sample`, without expanding it.
+pub fn check(token: &Token) -> Result<(), ParseError> {
+    check_kind(parse_pattern(token)?.kind(), token)
+}
+
+fn sample_kind(kind: &hir::HirKind, pattern: &str, max: u32, result: &mut String) {
+    match kind {
+        hir::HirKind::Literal(hir) => result.push_str(&String::from_utf8_lossy(&hir.0)),
+        hir::HirKind::Class(hir::Class::Unicode(class)) => {
+            let characters = expand_class(class.clone(), pattern);
+            if !characters.is_empty() {
+                result.push_str(&characters[fastrand::usize(..characters.len())]);
+            }
+        }
+        hir::HirKind::Class(hir::Class::Bytes(class)) => sample_kind(
+            &hir::HirKind::Class(hir::Class::Unicode(class.to_unicode_class().unwrap())),
+            pattern,
+            max,
+            result,
+        ),
+        hir::HirKind::Repetition(hir) => {
+            let upper = hir.max.unwrap_or(max).max(hir.min);
+            for _ in 0..fastrand::u32(hir.min..=upper) {
+                sample_kind(hir.sub.kind(), pattern, max, result);
+            }
+        }
+        hir::HirKind::Concat(hirs) => {
+            for hir in hirs {
+                sample_kind(hir.kind(), pattern, max, result);
+            }
+        }
+        hir::HirKind::Alternation(hirs) => sample_kind(
+            hirs[fastrand::usize(..hirs.len())].kind(),
+            pattern,
+            max,
+            result,
+        ),
+        hir::HirKind::Capture(hir) => sample_kind(hir.sub.kind(), pattern, max, result),
+        hir::HirKind::Empty | hir::HirKind::Look(_) => (),
+    }
+}
+
+/// Creates `count` random strings that `pattern` matches, repeating `*` and
+/// `+` at most `max` times. The pattern must have passed `check`.
+pub fn sample(pattern: &str, count: usize, max: u32) -> Vec<String> {
+    let hir = regex_syntax::parse(pattern).expect("Regex was checked by the parser");
+    (0..count)
+        .map(|_| {
+            let mut result = String::new();
+            sample_kind(hir.kind(), pattern, max, &mut result);
+            result
+        })
+        .collect()
+}
+
+/// Creates a pattern that matches exactly the strings in `values`, used for
+/// regexes that are built from other regexes and have no pattern of their own.
+fn values_pattern(values: &[String]) -> String {
+    match values.is_empty() {
+        // A class that contains no characters never matches
+        true => String::from(r"[^\s\S]"),
+        false => format!(
+            "(?:{})",
+            values
+                .iter()
+                .map(|value| regex::escape(value))
+                .collect::<Vec<String>>()
+                .join("|")
+        ),
+    }
+}
+
+/// Returns the strings of both regexes, without duplicates.
+pub fn union(
+    left_pattern: &str,
+    left: &[String],
+    right_pattern: &str,
+    right: &[String],
+) -> InstructionResult {
+    let seen: HashSet<&String> = left.iter().collect();
+    let values: Vec<String> = left
+        .iter()
+        .chain(right.iter().filter(|value| !seen.contains(value)))
+        .cloned()
+        .collect();
+    InstructionResult::Regex {
+        pattern: format!("(?:{})|(?:{})", left_pattern, right_pattern),
+        values: values.into(),
+    }
+}
+
+/// Returns the strings of `left` that `right` also creates.
+pub fn intersection(left: &[String], right: &[String]) -> InstructionResult {
+    let right: HashSet<&String> = right.iter().collect();
+    let values: Vec<String> = left
+        .iter()
+        .filter(|value| right.contains(value))
+        .cloned()
+        .collect();
+    InstructionResult::Regex {
+        pattern: values_pattern(&values),
+        values: values.into(),
+    }
+}
+
+/// Returns the strings of `left` that `right` does not create.
+pub fn difference(left: &[String], right: &[String]) -> InstructionResult {
+    let right: HashSet<&String> = right.iter().collect();
+    let values: Vec<String> = left
+        .iter()
+        .filter(|value| !right.contains(value))
+        .cloned()
+        .collect();
+    InstructionResult::Regex {
+        pattern: values_pattern(&values),
+        values: values.into(),
+    }
+}
+
+/// Returns the strings captured by the groups of the first match of `pattern`
+/// in `line`, or the whole match if the pattern has no groups. Groups that
+/// did not take part in the match capture an empty string.
+pub fn captures(line: &str, pattern: &str) -> Result<InstructionResult, regex::Error> {
+    let regex = regex::Regex::new(pattern)?;
+    let values: Vec<String> = match regex.captures(line) {
+        Some(captures) if captures.len() == 1 => vec![captures[0].to_string()],
+        Some(captures) => captures
+            .iter()
+            .skip(1)
+            .map(|group| group.map_or(String::new(), |group| group.as_str().to_string()))
+            .collect(),
+        None => Vec::new(),
     };
-    let kind = regex_syntax::parse(&value[1..value.len() - 1])
-        .unwrap()
-        .into_kind();
-    parse_kind(kind.clone(), token, max)
+    Ok(InstructionResult::Regex {
+        pattern: values_pattern(&values),
+        values: values.into(),
+    })
 }
@@ -0,0 +1,53 @@
+/// Strings with more characters than this are shortened in messages
+const MAX_LENGTH: usize = 120;
+/// How many characters of a shortened string are kept from its start, the
+/// rest of `MAX_LENGTH` is kept from its end
+const KEPT_FROM_START: usize = 80;
+
+/// A string as it is shown in a failure message, in backticks. Whitespace
+/// that can not be seen is shown as `·` for a space and `⏎` for a newline,
+/// that is spaces at the start or end and runs of several spaces. Other
+/// control characters are escaped. A long string keeps its start and end
+/// and is followed by its length
+pub fn string(value: &str) -> String {
+    let characters = value.chars().collect::<Vec<char>>();
+    let mut result = String::from("`");
+    if characters.len() > MAX_LENGTH {
+        let end = characters.len() - (MAX_LENGTH - KEPT_FROM_START);
+        push_characters(&mut result, &characters, 0..KEPT_FROM_START);
+        result.push('…');
+        push_characters(&mut result, &characters, end..characters.len());
+        result.push_str(&format!("` ({} characters)", characters.len()));
+    } else {
+        push_characters(&mut result, &characters, 0..characters.len());
+        result.push('`');
+    }
+    result
+}
+
+/// The string with the same whitespace shown as in `string`, but neither
+/// shortened nor in backticks
+pub fn visible(value: &str) -> String {
+    let characters = value.chars().collect::<Vec<char>>();
+    let mut result = String::new();
+    push_characters(&mut result, &characters, 0..characters.len());
+    result
+}
+
+fn push_characters(result: &mut String, characters: &[char], range: std::ops::Range<usize>) {
+    let space = |index: usize| characters.get(index) == Some(&' ');
+    for index in range {
+        match characters[index] {
+            ' ' if index == 0
+                || index == characters.len() - 1
+                || space(index - 1)
+                || space(index + 1) =>
+            {
+                result.push('·')
+            }
+            '\n' => result.push('⏎'),
+            c if c.is_control() => result.extend(c.escape_default()),
+            c => result.push(c),
+        }
+    }
+}
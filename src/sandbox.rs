@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The variables of `test-script` that a program in a sandbox keeps, and
+/// every `LC_` variable
+const KEPT_VARIABLES: [&str; 3] = ["PATH", "TERM", "LANG"];
+
+static NEXT_SANDBOX: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory that the program of a test runs in with
+/// `--sandbox`, and that is removed after the test. The program gets its own
+/// home and temporary directory in it, and only a few variables of the
+/// environment. It also gets its own mount and network namespace without a
+/// network, where its working directory is an overlay that keeps every
+/// change in the sandbox instead of in the real directory.
+#[derive(Debug)]
+pub struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    /// Why the program can not be isolated, in a pseudo-terminal if
+    /// `terminal` is set. A test that asked for a sandbox fails then, instead
+    /// of running with the network and the real working directory
+    pub fn unsupported(terminal: bool) -> Option<&'static str> {
+        if !cfg!(target_os = "linux") {
+            return Some("the sandbox is only supported on Linux");
+        }
+        match terminal {
+            true => Some("programs in a pseudo-terminal can not be sandboxed"),
+            false => None,
+        }
+    }
+
+    pub fn new() -> std::io::Result<Sandbox> {
+        let root = std::env::temp_dir().join(format!(
+            "test-script-{}-{}",
+            std::process::id(),
+            NEXT_SANDBOX.fetch_add(1, Ordering::Relaxed)
+        ));
+        for directory in ["upper", "work", "merged", "home", "tmp"] {
+            std::fs::create_dir_all(root.join(directory))?;
+        }
+        Ok(Sandbox { root })
+    }
+
+    /// The environment of the program, instead of the one of `test-script`
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = std::env::vars()
+            .filter(|(key, _)| KEPT_VARIABLES.contains(&key.as_str()) || key.starts_with("LC_"))
+            .collect::<Vec<(String, String)>>();
+        for (key, directory) in [("HOME", "home"), ("TMPDIR", "tmp")] {
+            let path = self.root.join(directory);
+            env.push((key.to_string(), path.to_string_lossy().into_owned()));
+        }
+        env
+    }
+
+    /// Everything the program needs to enter the sandbox, prepared before it
+    /// is started since nothing may be allocated between `fork` and `exec`
+    #[cfg(target_os = "linux")]
+    pub fn isolation(&self, cwd: &Path) -> std::io::Result<Isolation> {
+        use std::ffi::CString;
+        use std::io::{Error, ErrorKind};
+
+        let cwd = cwd.canonicalize()?;
+        let path = |path: &Path| {
+            CString::new(path.to_string_lossy().into_owned())
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+        };
+        let overlay = format!(
+            "lowerdir={},upperdir={},workdir={}",
+            cwd.display(),
+            self.root.join("upper").display(),
+            self.root.join("work").display()
+        );
+        // SAFETY: `getuid` and `getgid` can not fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Ok(Isolation {
+            uid_map: format!("{uid} {uid} 1").into_bytes(),
+            gid_map: format!("{gid} {gid} 1").into_bytes(),
+            overlay: path(Path::new(&overlay))?,
+            merged: path(&self.root.join("merged"))?,
+        })
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        // The overlay leaves a directory without permissions in `work`
+        make_removable(&self.root);
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+fn make_removable(directory: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(directory, std::fs::Permissions::from_mode(0o700));
+    }
+    for entry in std::fs::read_dir(directory).into_iter().flatten().flatten() {
+        if entry.file_type().is_ok_and(|r#type| r#type.is_dir()) {
+            make_removable(&entry.path());
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub struct Isolation {
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    overlay: std::ffi::CString,
+    merged: std::ffi::CString,
+}
+
+#[cfg(target_os = "linux")]
+impl Isolation {
+    /// Moves the current process into new namespaces and into the overlay of
+    /// its working directory. Only async-signal-safe functions are called,
+    /// so this can run between `fork` and `exec`.
+    pub fn enter(&self) -> std::io::Result<()> {
+        let check = |result: libc::c_int| match result {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        };
+        // SAFETY: every pointer is a valid, nul-terminated string or null
+        // where the function accepts null.
+        unsafe {
+            check(libc::unshare(
+                libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWNET,
+            ))?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", &self.uid_map)?;
+            write_file(c"/proc/self/gid_map", &self.gid_map)?;
+            // Mounts made in the sandbox must not reach the real system
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            check(libc::mount(
+                c"overlay".as_ptr(),
+                self.merged.as_ptr(),
+                c"overlay".as_ptr(),
+                0,
+                self.overlay.as_ptr().cast(),
+            ))?;
+            check(libc::chdir(self.merged.as_ptr()))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn write_file(path: &std::ffi::CStr, contents: &[u8]) -> std::io::Result<()> {
+    // SAFETY: `path` is nul-terminated and `contents` is valid for its length
+    unsafe {
+        let file = libc::open(path.as_ptr(), libc::O_WRONLY);
+        if file < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(file, contents.as_ptr().cast(), contents.len());
+        libc::close(file);
+        match written == contents.len() as isize {
+            true => Ok(()),
+            false => Err(std::io::Error::last_os_error()),
+        }
+    }
+}
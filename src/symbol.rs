@@ -0,0 +1,44 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// An interned identifier. Comparing and hashing a symbol is as cheap as for
+/// an integer, which keeps variable lookups fast in loops
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<str>>,
+}
+
+thread_local! {
+    // The whole script is parsed and run on one thread
+    static INTERNER: RefCell<Interner> = RefCell::default();
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        INTERNER.with_borrow_mut(|interner| {
+            if let Some(symbol) = interner.symbols.get(name) {
+                return *symbol;
+            }
+            let symbol = Symbol(interner.names.len() as u32);
+            let name: Rc<str> = Rc::from(name);
+            interner.names.push(name.clone());
+            interner.symbols.insert(name, symbol);
+            symbol
+        })
+    }
+
+    pub fn name(&self) -> Rc<str> {
+        INTERNER.with_borrow(|interner| interner.names[self.0 as usize].clone())
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -1,37 +1,97 @@
+use crate::diagnostic::Diagnostics;
 use crate::error::LexerError;
 use crate::exitcode::ExitCode;
+use crate::profile::Profile;
 use crate::{cli, interpreter, lexer, parser, type_checker};
 
-use std::io::ErrorKind;
+use std::io::{ErrorKind, Read};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-pub fn run(args: cli::Args) {
-    let mut contents = match std::fs::read_to_string(args.file.clone()) {
-        Ok(contents) => contents,
+pub fn read_source(args: &cli::Args) -> (PathBuf, String) {
+    if let Some(script) = &args.eval {
+        return (PathBuf::from("<eval>"), script.clone());
+    }
+
+    let file = args.file.clone().unwrap();
+    if file.as_os_str() == "-" {
+        let mut contents = String::new();
+        return match std::io::stdin().read_to_string(&mut contents) {
+            Ok(_) => (PathBuf::from("<stdin>"), contents),
+            Err(e) => {
+                LexerError::Unknown(&file, e).print();
+                std::process::exit(ExitCode::Unknown as i32);
+            }
+        };
+    }
+
+    match std::fs::read_to_string(&file) {
+        Ok(contents) => (file, contents),
         Err(e) => match e.kind() {
             ErrorKind::PermissionDenied => {
-                LexerError::PermissionDenied(&args.file).print();
+                LexerError::PermissionDenied(&file).print();
                 std::process::exit(ExitCode::SourcePermissionDenied as i32);
             }
             _ => {
-                LexerError::Unknown(&args.file, e).print();
+                LexerError::Unknown(&file, e).print();
                 std::process::exit(ExitCode::Unknown as i32);
             }
         },
-    };
-    let tokens = lexer::Lexer::new(&mut contents, args.clone()).tokenize();
+    }
+}
 
-    let program = parser::Parser::new(tokens, args.clone()).parse();
+/// How long each phase of running a script took, for `--profile-interpreter`
+/// and `--bench-internal`
+#[derive(Default)]
+struct Phases(Vec<(&'static str, Duration)>);
 
-    let type_check = match &program {
-        Ok(program) => type_checker::TypeChecker::new(program.clone(), args.clone()).check(),
-        Err(program) => type_checker::TypeChecker::new(program.clone(), args.clone()).check(),
-    };
+impl Phases {
+    fn time<T>(&mut self, phase: &'static str, run: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = run();
+        self.0.push((phase, start.elapsed()));
+        result
+    }
 
-    match program {
-        Ok(program) => match type_check {
-            Ok(_) => interpreter::Interpreter::new(program, args).interpret(),
-            Err(_) => (),
-        },
-        Err(_) => (),
+    /// One `phase nanoseconds` line per phase, read by the benchmarks
+    fn print(&self) {
+        for (phase, time) in &self.0 {
+            eprintln!("bench-internal: {} {}", phase, time.as_nanos());
+        }
+    }
+}
+
+pub fn run(args: cli::Args) {
+    let (file, contents) = read_source(&args);
+    Diagnostics::collect();
+    let mut phases = Phases::default();
+    let tokens = phases.time("lex", || lexer::Lexer::new(&contents, file).tokenize());
+
+    // Regex literals are expanded while parsing
+    let program = phases.time("parse", || {
+        parser::Parser::new(tokens, args.clone()).parse()
+    });
+
+    let type_check = phases.time("type_check", || match &program {
+        Ok(program) | Err(program) => type_checker::TypeChecker::new(program, args.clone()).check(),
+    });
+    // A denied warning is an error that the parser and type checker do not
+    // know about
+    let errors = Diagnostics::finish();
+
+    if let (Ok(program), Ok(_), 0) = (program, type_check, errors) {
+        let mut profile = args.profile_interpreter.then(Profile::default);
+        if let Some(profile) = &mut profile {
+            for (phase, time) in &phases.0 {
+                profile.record("phase", phase, *time);
+            }
+        }
+        let bench_internal = args.bench_internal;
+        phases.time("interpret", || {
+            interpreter::Interpreter::new(program, args, profile).interpret()
+        });
+        if bench_internal {
+            phases.print();
+        }
     }
 }
@@ -1,5 +1,6 @@
 use crate::r#type::Type;
 use colored::Colorize;
+use std::rc::Rc;
 
 pub enum PrintStyle<'a> {
     Warning,
@@ -29,6 +30,11 @@ pub enum TokenType {
     OpenParen,
     CloseParen,
 
+    Attribute,
+    OpenBracket,
+    CloseBracket,
+    DocComment { value: String },
+
     TypeCast,
     AssignmentOperator,
     IterableAssignmentOperator,
@@ -71,6 +77,11 @@ impl std::fmt::Display for TokenType {
             TokenType::OpenParen => write!(f, "("),
             TokenType::CloseParen => write!(f, ")"),
 
+            TokenType::Attribute => write!(f, "#"),
+            TokenType::OpenBracket => write!(f, "["),
+            TokenType::CloseBracket => write!(f, "]"),
+            TokenType::DocComment { .. } => write!(f, "doc comment"),
+
             TokenType::TypeCast => write!(f, "Keyword `as`"),
             TokenType::AssignmentOperator => write!(f, "="),
             TokenType::IterableAssignmentOperator => write!(f, "keyword `in`"),
@@ -86,30 +97,55 @@ impl std::fmt::Display for TokenType {
     }
 }
 
+/// Tokens are cloned whenever the parser looks at them, so the file name,
+/// the source and the previous token are shared instead of copied
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     pub r#type: TokenType,
-    pub file: String,
+    pub file: Rc<str>,
     pub row: usize,
     pub column: usize,
-
-    pub line: String,
-    pub last_token: Option<Box<Token>>,
+    /// Byte offset of the first character in the file
+    pub offset: usize,
+    /// Position just after the last character, which is on a later row
+    /// than `row` for strings and regexes spanning several lines
+    pub end_row: usize,
+    pub end_column: usize,
+    pub end_offset: usize,
+
+    /// The whole script the token was lexed from
+    pub source: Rc<str>,
+    pub last_token: Option<Rc<Token>>,
 }
 
 impl Token {
     pub fn none() -> Self {
         Self {
             r#type: TokenType::None,
-            file: String::new(),
+            file: Rc::from(""),
             row: 0,
             column: 0,
+            offset: 0,
+            end_row: 0,
+            end_column: 0,
+            end_offset: 0,
 
-            line: String::new(),
+            source: Rc::from(""),
             last_token: None,
         }
     }
 
+    /// The line the token starts on, without its line ending
+    pub fn line(&self) -> &str {
+        let offset = self.offset.min(self.source.len());
+        let start = self.source[..offset].rfind('\n').map_or(0, |index| index + 1);
+        let end = self.source[offset..]
+            .find('\n')
+            .map_or(self.source.len(), |index| offset + index);
+        let line = &self.source[start..end];
+        line.strip_suffix('\r').unwrap_or(line)
+    }
+
     pub fn binary_operator(&self) -> bool {
         match &self.r#type {
             TokenType::BinaryOperator { .. }
@@ -141,6 +177,11 @@ impl Token {
             TokenType::OpenParen => 1,
             TokenType::CloseParen => 1,
 
+            TokenType::Attribute => 1,
+            TokenType::OpenBracket => 1,
+            TokenType::CloseBracket => 1,
+            TokenType::DocComment { value } => value.len() + 3,
+
             TokenType::TypeCast => 2,
             TokenType::AssignmentOperator => 1,
             TokenType::IterableAssignmentOperator => 2,
@@ -155,6 +196,30 @@ impl Token {
         }
     }
 
+    /// A token that starts with this token and ends with `end`, to point at
+    /// a whole expression
+    pub fn to(&self, end: &Token) -> Token {
+        let mut token = self.clone();
+        if end.end_offset > self.offset {
+            token.end_row = end.end_row;
+            token.end_column = end.end_column;
+            token.end_offset = end.end_offset;
+        }
+        token
+    }
+
+    /// The number of columns to underline. A span over several lines is
+    /// underlined up to the end of its first line
+    fn width(&self) -> usize {
+        if self.end_offset <= self.offset {
+            self.len()
+        } else if self.end_row == self.row {
+            self.end_column - self.column
+        } else {
+            (self.line().chars().count() + 1).saturating_sub(self.column)
+        }
+    }
+
     const LINE_NUMBER_PADDING: usize = 4;
 
     pub fn as_string(&self, style: PrintStyle) -> String {
@@ -171,13 +236,13 @@ impl Token {
                 g: 0xFE,
                 b: 0xBF,
             }),
-            self.line,
+            self.line(),
             padding,
             match style {
-                PrintStyle::Warning => "^".repeat(self.len()).bright_yellow().to_string(),
-                PrintStyle::Error => "^".repeat(self.len()).bright_red().to_string(),
+                PrintStyle::Warning => "^".repeat(self.width()).bright_yellow().to_string(),
+                PrintStyle::Error => "^".repeat(self.width()).bright_red().to_string(),
                 PrintStyle::Help(message) =>
-                    "^".repeat(self.len()).bright_blue().to_string() + " " + message,
+                    "^".repeat(self.width()).bright_blue().to_string() + " " + message,
             }
         )
     }
@@ -198,9 +263,9 @@ impl Token {
             token_string = " ".to_string() + &token_string;
         }
 
-        let new_line = self.line[0..token_len].to_string()
+        let new_line = self.line()[0..token_len].to_string()
             + &token_string[..token_string.len() - 1]
-            + &self.line[token_len..];
+            + &self.line()[token_len..];
 
         format!(
             "{:<4}{}      \n\
@@ -223,31 +288,31 @@ impl Token {
             Self::LINE_NUMBER_PADDING,
             self.row.to_string().len() as usize,
         );
-        let line_padding = " ".repeat(self.line.chars().take_while(|c| c.is_whitespace()).count());
+        let line_padding = " ".repeat(self.line().chars().take_while(|c| c.is_whitespace()).count());
 
-        let start_line = if token_len > self.line.len() {
-            self.line[..self.line.len()].to_string() + " {"
+        let start_line = if token_len > self.line().len() {
+            self.line()[..self.line().len()].to_string() + " {"
         } else {
-            self.line[..token_len].to_string() + "{"
+            self.line()[..token_len].to_string() + "{"
         };
         let start_line_padding = &" ".repeat(padding_length + token_len);
 
         let content_line = line_padding.clone()
             + "    "
             + &(if self.row == close_token.row {
-                self.line[token_len..close_token.column as usize]
+                self.line()[token_len..close_token.column as usize]
                     .trim()
                     .to_string()
             } else {
-                close_token.line.to_string()
+                close_token.line().to_string()
             });
 
         let end_block_line = line_padding.clone() + "}";
         let end_block_padding = line_padding.clone() + &" ".repeat(padding_length);
 
         let close_token_len = close_token.column as usize + close_token.len();
-        let after_line = if close_token_len < close_token.line.len() {
-            Some(line_padding + &close_token.line[close_token_len..])
+        let after_line = if close_token_len < close_token.line().len() {
+            Some(line_padding + &close_token.line()[close_token_len..])
         } else {
             None
         };
@@ -364,6 +429,34 @@ impl TokenCollection {
         }
     }
 
+    pub fn position(&self) -> (usize, bool) {
+        (self.index, self.started)
+    }
+
+    pub fn rewind(&mut self, (index, started): (usize, bool)) {
+        self.index = index;
+        self.started = started;
+    }
+
+    /// Skips to the `,` or `)` that ends the current argument, and returns
+    /// false if the instruction ends first
+    pub fn advance_to_next_argument(&mut self) -> bool {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token.r#type {
+                TokenType::Comma | TokenType::CloseParen if depth == 0 => return true,
+                TokenType::OpenParen => depth += 1,
+                TokenType::CloseParen => depth -= 1,
+                TokenType::Semicolon | TokenType::OpenBlock | TokenType::CloseBlock => {
+                    return false
+                }
+                _ => (),
+            }
+            self.next();
+        }
+        false
+    }
+
     pub fn advance_to_next_instruction(&mut self) {
         while let Some(token) = self.next() {
             if token.r#type == TokenType::Semicolon || token.r#type == TokenType::CloseBlock {
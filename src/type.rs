@@ -8,10 +8,24 @@ pub enum Type {
     None,
 
     Iterable,
+    /// The cartesian product of this many regexes, created with `*`
+    Product(usize),
+    /// Rows of strings read from a file, with any number of values each
+    Cases,
 
     Any,
 }
 
+/// Every type a `for` loop can iterate over, and the type of the values it
+/// assigns. A type is made iterable by adding it here and to
+/// `InstructionResult::elements`
+const ITERABLES: [(Type, Type); 2] = [
+    // The strings the regex matches
+    (Type::Regex, Type::String),
+    // The characters of the string
+    (Type::String, Type::String),
+];
+
 impl Type {
     pub fn from(value: &str) -> Self {
         match value {
@@ -24,6 +38,14 @@ impl Type {
             _ => panic!("Invalid type"),
         }
     }
+
+    /// The type of the values a `for` loop over this type assigns
+    pub fn iterable_inner_type(&self) -> Option<Type> {
+        ITERABLES
+            .iter()
+            .find(|(r#type, _)| r#type == self)
+            .map(|(_, inner_type)| *inner_type)
+    }
 }
 
 impl std::fmt::Display for Type {
@@ -37,6 +59,8 @@ impl std::fmt::Display for Type {
             Type::None => write!(f, "none"),
 
             Type::Iterable => write!(f, "iterable"),
+            Type::Product(factors) => write!(f, "{}", vec!["regex"; *factors].join(" * ")),
+            Type::Cases => write!(f, "cases"),
 
             Type::Any => write!(f, "T"),
         }
@@ -1,80 +1,371 @@
-use crate::cli::Args;
-use crate::environment::ParseEnvironment;
-use crate::error::{ParseError, ParseErrorType, ParseWarning, ParseWarningType};
-use crate::instruction::{BinaryOperator, BuiltIn, Instruction, InstructionType, UnaryOperator};
+use crate::cli::{Args, Verbosity};
+use crate::environment::{Environment, ParseEnvironment};
+use crate::error::{self, ParseError, ParseErrorType, ParseWarning, ParseWarningType};
+use crate::instruction::{
+    BinaryOperator, BuiltIn, Instruction, InstructionResult, InstructionType, UnaryOperator,
+};
 use crate::r#type::Type;
 use crate::token::Token;
 use crate::variable::Variable;
 
-pub struct TypeChecker {
-    program: Vec<Instruction>,
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+pub struct TypeChecker<'a> {
+    program: &'a [Instruction],
     environment: ParseEnvironment,
+    /// Variables of unknown type that a note has been printed for
+    unknown_types: HashSet<String>,
+    /// Set while checking an instruction whose value is not used, which an
+    /// assignment must be since it has no value
+    statement: bool,
+    /// Set while checking the body of a test or a function, where the
+    /// program of a test can be used. Constants and the commands of tests are
+    /// evaluated before any program runs
+    in_test: bool,
+    /// The global functions that use the program of a test, directly or
+    /// through the functions they call, with where they use it
+    program_functions: HashMap<String, Token>,
     success: bool,
     args: Args,
 }
 
-impl TypeChecker {
-    pub fn new(program: Vec<Instruction>, args: Args) -> Self {
+impl<'a> TypeChecker<'a> {
+    pub fn new(program: &'a [Instruction], args: Args) -> Self {
         Self {
             program,
             environment: ParseEnvironment::new(args.clone()),
+            unknown_types: HashSet::new(),
+            statement: false,
+            in_test: false,
+            program_functions: HashMap::new(),
             success: true,
             args,
         }
     }
 
     pub fn check(&mut self) -> Result<(), ParseError> {
-        for instruction in self.program.clone() {
-            match instruction.r#type {
-                InstructionType::Test(instruction, _name, _command) => {
-                    match self.check_instruction(&instruction) {
-                        Ok(_) => (),
-                        Err(e) => {
-                            e.print();
-                            self.success = false;
-                        }
-                    }
+        // Functions may be called above their declaration
+        for instruction in self.program {
+            if let InstructionType::Function { .. } = instruction.r#type {
+                self.environment.add_function(Rc::new(instruction.clone()));
+            }
+        }
+        self.find_program_functions();
+        for instruction in self.program {
+            self.check_global(instruction);
+        }
+        self.check_recursion();
+        match self.success {
+            true => Ok(()),
+            false => Err(ParseError::none()),
+        }
+    }
+
+    /// Checks a test, function, constant or suite of the global scope
+    fn check_global(&mut self, instruction: &Instruction) {
+        let allowed_warnings = self.environment.allowed_warnings.len();
+        self.environment.allow_warnings(&instruction.attributes);
+        match &instruction.r#type {
+            InstructionType::Test {
+                command,
+                instruction: body,
+                ..
+            } => match self.check_test(command, body) {
+                Ok(_) => self.check_interaction_order(instruction, command, body),
+                Err(e) => {
+                    e.print();
+                    self.success = false;
                 }
-                InstructionType::Function { .. } => match self.check_instruction(&instruction) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        e.print();
-                        self.success = false;
-                    }
-                },
+            },
+            InstructionType::Function { .. } => match self.check_instruction(instruction) {
+                Ok(_) => (),
+                Err(e) => {
+                    e.print();
+                    self.success = false;
+                }
+            },
 
-                InstructionType::Assignment {
-                    variable: _variable,
-                    instruction,
-                    token: _token,
-                    declaration: _declaration,
-                } => match self.check_instruction(&instruction) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        e.print();
-                        self.success = false;
+            InstructionType::Assignment {
+                variable,
+                instruction,
+                token,
+                declaration,
+            } => match self.check_assignment(variable, instruction, token, declaration) {
+                Ok(_) => (),
+                Err(e) => {
+                    e.print();
+                    self.success = false;
+                }
+            },
+            InstructionType::Suite { instructions, .. } => {
+                for instruction in instructions {
+                    self.check_global(instruction);
+                }
+            }
+            _ => unreachable!(),
+        }
+        self.environment.allowed_warnings.truncate(allowed_warnings);
+    }
+
+    /// Warns about every global function that calls itself on every path,
+    /// directly or through other functions, since it can never return
+    fn check_recursion(&mut self) {
+        let calls = self
+            .program
+            .iter()
+            .filter_map(|instruction| match &instruction.r#type {
+                InstructionType::Function {
+                    name, instruction, ..
+                } => Some((name.as_str(), instruction.always_calls())),
+                _ => None,
+            })
+            .collect::<HashMap<&str, HashSet<&str>>>();
+
+        for instruction in self.program {
+            let name = match &instruction.r#type {
+                InstructionType::Function { name, .. } => name.as_str(),
+                _ => continue,
+            };
+            let mut reached = HashSet::new();
+            let mut pending = vec![name];
+            while let Some(function) = pending.pop() {
+                for called in calls.get(function).into_iter().flatten() {
+                    if reached.insert(*called) {
+                        pending.push(called);
                     }
+                }
+            }
+            if reached.contains(name) {
+                let allowed_warnings = self.environment.allowed_warnings.len();
+                self.environment.allow_warnings(&instruction.attributes);
+                ParseWarning::new(
+                    ParseWarningType::UnconditionalRecursion(name.to_string()),
+                    instruction.token.clone(),
+                )
+                .print(
+                    self.args.disable_warnings,
+                    &self.environment.allowed_warnings,
+                );
+                self.environment.allowed_warnings.truncate(allowed_warnings);
+            }
+        }
+    }
+
+    /// Finds the global functions that use the program of a test, so calling
+    /// them outside of a test can be reported
+    fn find_program_functions(&mut self) {
+        let functions = self
+            .program
+            .iter()
+            .filter_map(|instruction| match &instruction.r#type {
+                InstructionType::Function {
+                    name, instruction, ..
+                } => Some((name, instruction)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // Repeated until nothing changes, since a function can use the
+        // program through a function declared below it
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (name, body) in &functions {
+                if self.program_functions.contains_key(*name) {
+                    continue;
+                }
+                if let Some(token) = self.program_use(body) {
+                    self.program_functions.insert(name.to_string(), token);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// Where the instruction first uses the program of a test, with a builtin
+    /// or by calling a function that does
+    fn program_use(&self, instruction: &Instruction) -> Option<Token> {
+        let mut pending = vec![instruction];
+        while let Some(instruction) = pending.pop() {
+            match &instruction.r#type {
+                InstructionType::BuiltIn(built_in) if built_in.signature().needs_program => {
+                    return Some(instruction.token.clone());
+                }
+                InstructionType::FunctionCall { name, .. }
+                    if self.program_functions.contains_key(name) =>
+                {
+                    return Some(instruction.token.clone());
+                }
+                _ => (),
+            }
+            pending.extend(instruction.children().into_iter().rev());
+        }
+        None
+    }
+
+    fn check_test(
+        &mut self,
+        command: &Instruction,
+        instruction: &Instruction,
+    ) -> Result<Type, ParseError> {
+        let r#type = self.check_instruction(command)?;
+        if r#type != Type::String {
+            return Err(ParseError::new(
+                ParseErrorType::MismatchedType {
+                    expected: vec![Type::String],
+                    actual: r#type,
                 },
-                _ => unreachable!(),
+                command.span.clone(),
+            ));
+        }
+        let in_test = std::mem::replace(&mut self.in_test, true);
+        let result = self.check_statement(instruction);
+        self.in_test = in_test;
+        result
+    }
+
+    /// Warns when a test of a command that waits for input first reads its
+    /// output before sending it anything, which can only wait for the timeout
+    fn check_interaction_order(
+        &mut self,
+        test: &Instruction,
+        command: &Instruction,
+        body: &Instruction,
+    ) {
+        if test
+            .attributes
+            .iter()
+            .any(|attribute| attribute.name == "stdin")
+        {
+            return;
+        }
+        let command = match self.command_value(command) {
+            Some(command) => command,
+            None => return,
+        };
+        let waits_for_input = self.args.waits_for_input.iter().any(|waiting| {
+            command == waiting
+                || command
+                    .strip_prefix(waiting.as_str())
+                    .is_some_and(|rest| rest.starts_with(' '))
+        });
+        if !waits_for_input {
+            return;
+        }
+        if let Some(read) = Self::first_interaction(body).filter(|instruction| {
+            matches!(
+                instruction.r#type,
+                InstructionType::BuiltIn(
+                    BuiltIn::Output(_)
+                        | BuiltIn::OutputFile(_)
+                        | BuiltIn::SkipLines(_)
+                        | BuiltIn::SkipUntil(_)
+                )
+            )
+        }) {
+            ParseWarning::new(
+                ParseWarningType::OutputBeforeInput(command.to_string()),
+                read.token.clone(),
+            )
+            .print(
+                self.args.disable_warnings,
+                &self.environment.allowed_warnings,
+            );
+        }
+    }
+
+    /// The command of a test, if it is a string literal or a global constant
+    /// with a string literal as its value
+    fn command_value(&self, command: &'a Instruction) -> Option<&'a str> {
+        match &command.r#type {
+            InstructionType::StringLiteral(value) => Some(value),
+            InstructionType::Variable(variable) => {
+                self.program
+                    .iter()
+                    .find_map(|instruction| match &instruction.r#type {
+                        InstructionType::Assignment {
+                            variable: constant,
+                            instruction,
+                            ..
+                        } if constant.name == variable.name => match &instruction.r#type {
+                            InstructionType::StringLiteral(value) => Some(value.as_str()),
+                            _ => None,
+                        },
+                        _ => None,
+                    })
             }
+            _ => None,
         }
-        match self.success {
-            true => Ok(()),
-            false => Err(ParseError::none()),
+    }
+
+    /// The first builtin that talks to the program, in the order they are
+    /// written. A function call ends the search, since it may send input,
+    /// and a nested function only runs when it is called
+    fn first_interaction(instruction: &Instruction) -> Option<&Instruction> {
+        match &instruction.r#type {
+            InstructionType::BuiltIn(
+                builtin @ (BuiltIn::Input(_)
+                | BuiltIn::InputRaw(_)
+                | BuiltIn::SendEof(_)
+                | BuiltIn::Restart(_)
+                | BuiltIn::Output(_)
+                | BuiltIn::OutputFile(_)
+                | BuiltIn::SkipLines(_)
+                | BuiltIn::SkipUntil(_)),
+            ) => builtin
+                .arguments()
+                .into_iter()
+                .find_map(Self::first_interaction)
+                .or(Some(instruction)),
+            InstructionType::FunctionCall { .. } => Some(instruction),
+            InstructionType::Function { .. } => None,
+            _ => instruction
+                .children()
+                .into_iter()
+                .find_map(Self::first_interaction),
         }
     }
 
+    fn check_statement(&mut self, instruction: &Instruction) -> Result<Type, ParseError> {
+        self.statement = true;
+        self.check_instruction(instruction)
+    }
+
     fn check_instruction(&mut self, instruction: &Instruction) -> Result<Type, ParseError> {
+        let statement = std::mem::take(&mut self.statement);
+        let allowed_warnings = self.environment.allowed_warnings.len();
+        self.environment.allow_warnings(&instruction.attributes);
+        if let InstructionType::Assignment { token, .. } = &instruction.r#type {
+            if !statement {
+                ParseWarning::new(ParseWarningType::AssignmentAsValue, token.clone()).print(
+                    self.args.disable_warnings,
+                    &self.environment.allowed_warnings,
+                );
+            }
+        }
+        // A value of unknown type fits anywhere, so it causes no further
+        // errors. A call still has the type it returns, so a call whose value
+        // is not used is not reported as an unused value either
+        let result = match self.check_instruction_type(instruction) {
+            Err(e) if e.is_caused_by_unknown_type() => Ok(self.result_type(instruction)),
+            result => result,
+        };
+        self.environment.allowed_warnings.truncate(allowed_warnings);
+        result
+    }
+
+    fn check_instruction_type(&mut self, instruction: &Instruction) -> Result<Type, ParseError> {
         match &instruction.r#type {
             InstructionType::StringLiteral(_) => Ok(Type::String),
-            InstructionType::RegexLiteral(_) => Ok(Type::Regex),
+            InstructionType::RegexLiteral { .. } => Ok(Type::Regex),
             InstructionType::IntegerLiteral(_) => Ok(Type::Int),
             InstructionType::FloatLiteral(_) => Ok(Type::Float),
             InstructionType::BooleanLiteral(_) => Ok(Type::Bool),
 
-            InstructionType::BuiltIn(instruction) => self.check_builtin(instruction),
+            InstructionType::BuiltIn(built_in) => self.check_builtin(built_in, &instruction.token),
 
-            InstructionType::Block(instructions) => self.check_block(instructions),
+            InstructionType::Block { instructions, tail } => self.check_block(instructions, *tail),
 
             InstructionType::Paren(instruction) => self.check_instruction(instruction),
 
@@ -91,8 +382,8 @@ impl TypeChecker {
                 instruction,
             } => {
                 self.environment.add_scope();
-                self.check_instruction(&assignment)?;
-                let result = self.check_instruction(&instruction)?;
+                self.check_statement(&assignment)?;
+                let result = self.check_statement(&instruction)?;
                 self.environment.remove_scope();
                 Ok(result)
             }
@@ -105,11 +396,15 @@ impl TypeChecker {
                     }
                     None => variable,
                 };
+                if variable.r#type == Type::Any && self.unknown_types.insert(variable.name.clone())
+                {
+                    error::unknown_type_note(variable, &instruction.token);
+                }
                 Ok(variable.r#type)
             }
 
             InstructionType::FunctionCall { name, arguments } => {
-                self.check_function_call(name, arguments)
+                self.check_function_call(name, arguments, &instruction.token)
             }
 
             InstructionType::Assignment {
@@ -120,10 +415,10 @@ impl TypeChecker {
             } => self.check_assignment(&variable, &instruction, token, declaration),
 
             InstructionType::IterableAssignment {
-                variable,
+                variables,
                 instruction,
                 token,
-            } => self.check_iterable_assignment(&variable, &instruction, token),
+            } => self.check_iterable_assignment(variables, instruction, token),
 
             InstructionType::UnaryOperation {
                 operator,
@@ -138,14 +433,18 @@ impl TypeChecker {
             InstructionType::TypeCast {
                 instruction: left_instruction,
                 r#type,
-            } => self.check_type_cast(left_instruction, instruction, r#type),
+                checked,
+            } => self.check_type_cast(left_instruction, instruction, r#type, *checked),
 
             InstructionType::None => {
                 ParseWarning::new(
                     ParseWarningType::TrailingSemicolon,
                     instruction.token.clone(),
                 )
-                .print(self.args.disable_warnings);
+                .print(
+                    self.args.disable_warnings,
+                    &self.environment.allowed_warnings,
+                );
                 Ok(Type::None)
             }
             _ => {
@@ -154,93 +453,138 @@ impl TypeChecker {
         }
     }
 
-    fn check_builtin(&mut self, built_in: &BuiltIn) -> Result<Type, ParseError> {
-        match built_in {
-            BuiltIn::Input(instruction) => {
-                let r#type = self.check_instruction(&instruction)?;
-                if r#type == Type::String {
-                    Ok(Type::None)
-                } else {
-                    Err(ParseError::new(
-                        ParseErrorType::MismatchedType {
-                            expected: vec![Type::String],
-                            actual: r#type,
-                        },
-                        instruction.token.clone(),
-                    ))
-                }
-            }
-            BuiltIn::Output(instruction) => {
-                let r#type = self.check_instruction(&instruction)?;
-                if r#type == Type::String {
-                    Ok(Type::None)
-                } else {
-                    Err(ParseError::new(
-                        ParseErrorType::MismatchedType {
-                            expected: vec![Type::String],
-                            actual: r#type,
-                        },
-                        instruction.token.clone(),
-                    ))
-                }
-            }
-            BuiltIn::Print(instruction) => {
-                let r#type = self.check_instruction(&instruction)?;
-                if r#type == Type::String {
-                    Ok(Type::None)
-                } else {
-                    Err(ParseError::new(
-                        ParseErrorType::MismatchedType {
-                            expected: vec![Type::String],
-                            actual: r#type,
-                        },
-                        instruction.token.clone(),
-                    ))
+    /// The type of a call whatever its arguments are, and `Any` for every
+    /// other instruction
+    fn result_type(&self, instruction: &Instruction) -> Type {
+        match &instruction.r#type {
+            InstructionType::BuiltIn(built_in) => built_in.signature().returns,
+            InstructionType::FunctionCall { name, .. } => {
+                match self
+                    .environment
+                    .functions
+                    .get(name)
+                    .map(|function| &function.r#type)
+                {
+                    Some(InstructionType::Function { return_type, .. }) => *return_type,
+                    _ => Type::Any,
                 }
             }
-            BuiltIn::Println(instruction) => {
-                let r#type = self.check_instruction(&instruction)?;
-                if r#type == Type::String {
-                    Ok(Type::None)
-                } else {
-                    Err(ParseError::new(
-                        ParseErrorType::MismatchedType {
-                            expected: vec![Type::String],
-                            actual: r#type,
-                        },
-                        instruction.token.clone(),
-                    ))
-                }
+            _ => Type::Any,
+        }
+    }
+
+    /// Checks the number and types of the arguments against the signature of
+    /// the builtin, and returns the type of its result
+    fn check_builtin(&mut self, built_in: &BuiltIn, token: &Token) -> Result<Type, ParseError> {
+        let signature = built_in.signature();
+        if signature.needs_program && !self.in_test {
+            return Err(ParseError::new(
+                ParseErrorType::ProgramOutsideTest {
+                    name: signature.name.to_string(),
+                    function: false,
+                },
+                token.clone(),
+            ));
+        }
+        // A builtin called without arguments is parsed with a none
+        // instruction as its argument
+        let arguments = built_in
+            .arguments()
+            .into_iter()
+            .filter(|argument| argument.r#type != InstructionType::None)
+            .collect::<Vec<&Instruction>>();
+        let parameters = &signature.parameters;
+        let arity = match signature.variadic {
+            true => arguments.len() + 1 >= parameters.len(),
+            false => arguments.len() == parameters.len(),
+        };
+        if !arity {
+            return Err(ParseError::new(
+                ParseErrorType::MismatchedArguments {
+                    expected: parameters.len(),
+                    actual: arguments.len(),
+                },
+                token.clone(),
+            ));
+        }
+
+        for (index, argument) in arguments.into_iter().enumerate() {
+            let expected = parameters
+                .get(index)
+                .or(parameters.last())
+                .copied()
+                .unwrap_or(Type::Any);
+            let r#type = self.check_instruction(argument)?;
+            if r#type != expected && expected != Type::Any {
+                return Err(ParseError::new(
+                    ParseErrorType::MismatchedType {
+                        expected: vec![expected],
+                        actual: r#type,
+                    },
+                    argument.span.clone(),
+                ));
             }
         }
+        Ok(signature.returns)
     }
 
-    fn check_block(&mut self, instructions: &Vec<Instruction>) -> Result<Type, ParseError> {
-        self.environment.add_scope();
+    /// The value of a block is the value of its last instruction, if that
+    /// has no semicolon
+    fn check_block(
+        &mut self,
+        instructions: &Vec<Instruction>,
+        tail: bool,
+    ) -> Result<Type, ParseError> {
         if (instructions.len()) == 0 {
             return Ok(Type::None);
         }
+        self.environment.add_scope();
         for instruction in &instructions[..instructions.len() - 1] {
-            match self.check_instruction(&instruction) {
-                Ok(t) => match t {
-                    Type::None => (),
-                    _ => {
-                        ParseWarning::new(
-                            ParseWarningType::UnusedValue,
-                            instruction.inner_most().token.clone(),
-                        )
-                        .print(self.args.disable_warnings);
-                    }
-                },
+            match self.check_statement(&instruction) {
+                Ok(Type::None) => (),
+                Ok(_) => self.unused_value(instruction),
                 Err(e) => {
                     e.print();
                     self.success = false;
                 }
             }
         }
-        let result = self.check_instruction(&instructions[instructions.len() - 1])?;
+        let last = &instructions[instructions.len() - 1];
+        let result = self.check_statement(last)?;
         self.environment.remove_scope();
-        Ok(result)
+        match tail {
+            true => Ok(result),
+            false => {
+                if result != Type::None {
+                    self.unused_value(last);
+                }
+                Ok(Type::None)
+            }
+        }
+    }
+
+    /// Warns that the value of a statement is thrown away
+    fn unused_value(&mut self, instruction: &Instruction) {
+        let allowed_warnings = self.environment.allowed_warnings.len();
+        self.environment.allow_warnings(&instruction.attributes);
+        let warning = match &instruction.r#type {
+            InstructionType::BinaryOperation {
+                operator: BinaryOperator::Equal,
+                ..
+            } => ParseWarning::new(
+                ParseWarningType::UnusedComparison,
+                instruction.token.clone(),
+            ),
+            _ => ParseWarning::new(
+                ParseWarningType::UnusedValue,
+                instruction.inner_most().token.clone(),
+            ),
+        };
+        warning.print(
+            self.args.disable_warnings,
+            &self.environment.allowed_warnings,
+        );
+        self.environment.allowed_warnings.truncate(allowed_warnings);
     }
 
     fn check_assignment(
@@ -260,7 +604,11 @@ impl TypeChecker {
                     expected: vec![variable_type],
                     actual: instruction_type,
                 },
-                token.clone(),
+                instruction.span.clone(),
+            )
+            .with_label(
+                variable.type_token.clone(),
+                "expected because of this type annotation",
             ));
         }
 
@@ -283,14 +631,43 @@ impl TypeChecker {
 
     fn check_iterable_assignment(
         &mut self,
-        variable: &Variable,
+        variables: &[Variable],
         instruction: &Instruction,
         token: &Token,
     ) -> Result<Type, ParseError> {
-        let variable_type = variable.r#type;
-        match self.check_instruction(&instruction) {
-            Ok(Type::Regex) => match variable_type {
-                Type::String => {
+        let iterable_type = self.check_instruction(instruction)?;
+        let (factors, inner_type) = match iterable_type {
+            Type::Product(factors) => (factors, Type::String),
+            // The number of values in each case is checked while iterating
+            Type::Cases => (variables.len(), Type::String),
+            t => match t.iterable_inner_type() {
+                Some(inner_type) => (1, inner_type),
+                None => {
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedType {
+                            expected: vec![Type::Iterable],
+                            actual: t,
+                        },
+                        token.clone(),
+                    ))
+                }
+            },
+        };
+        if variables.len() != factors {
+            return Err(ParseError::new(
+                ParseErrorType::MismatchedType {
+                    expected: vec![match variables.len() {
+                        1 => Type::Iterable,
+                        n => Type::Product(n),
+                    }],
+                    actual: iterable_type,
+                },
+                instruction.span.clone(),
+            ));
+        }
+        for variable in variables {
+            match variable.r#type {
+                variable_type if variable_type == inner_type => {
                     self.environment.insert(variable.clone());
                     match self.environment.get(&variable.name) {
                         Some(v) => {
@@ -298,25 +675,19 @@ impl TypeChecker {
                         }
                         None => (),
                     }
-                    Ok(variable_type)
                 }
-                _ => Err(ParseError::new(
-                    ParseErrorType::MismatchedType {
-                        expected: vec![Type::Regex],
-                        actual: variable_type,
-                    },
-                    token.clone(),
-                )),
-            },
-            Ok(t) => Err(ParseError::new(
-                ParseErrorType::MismatchedType {
-                    expected: vec![Type::Iterable],
-                    actual: t,
-                },
-                token.clone(),
-            )),
-            Err(e) => Err(e),
+                variable_type => {
+                    return Err(ParseError::new(
+                        ParseErrorType::MismatchedType {
+                            expected: vec![inner_type],
+                            actual: variable_type,
+                        },
+                        variable.identifier_token.clone(),
+                    ))
+                }
+            }
         }
+        Ok(inner_type)
     }
 
     fn check_unary(
@@ -333,7 +704,7 @@ impl TypeChecker {
                         expected: vec![Type::Bool],
                         actual: t,
                     },
-                    instruction.token.clone(),
+                    instruction.span.clone(),
                 )),
             },
             UnaryOperator::Negation => match instruction_type {
@@ -343,7 +714,7 @@ impl TypeChecker {
                         expected: vec![Type::Int],
                         actual: t,
                     },
-                    instruction.token.clone(),
+                    instruction.span.clone(),
                 )),
             },
         }
@@ -362,6 +733,8 @@ impl TypeChecker {
             BinaryOperator::Division => self.check_division(left, right),
             BinaryOperator::Modulo => self.check_modulo(left, right),
 
+            BinaryOperator::Matches => self.check_matches(left, right),
+
             BinaryOperator::Equal => self.check_comparison(operator, left, right),
             BinaryOperator::NotEqual => self.check_comparison(operator, left, right),
             BinaryOperator::GreaterThan => self.check_comparison(operator, left, right),
@@ -391,21 +764,21 @@ impl TypeChecker {
                     expected: vec![Type::String],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
             )),
             (Type::Int, t2) => Err(ParseError::new(
                 ParseErrorType::MismatchedType {
                     expected: vec![Type::Int],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
             )),
             (t1, _t2) => Err(ParseError::new(
                 ParseErrorType::MismatchedType {
                     expected: vec![Type::String, Type::Int],
                     actual: t1,
                 },
-                left.token.clone(),
+                left.span.clone(),
             )),
         }
     }
@@ -421,19 +794,27 @@ impl TypeChecker {
         match (left_type, right_type) {
             (Type::Int, Type::Int) => Ok(Type::Int),
             (Type::Float, Type::Float) => Ok(Type::Float),
+            (Type::Regex, Type::Regex) => Ok(Type::Regex),
             (Type::Int, t2) => Err(ParseError::new(
                 ParseErrorType::MismatchedType {
                     expected: vec![Type::Int],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
+            )),
+            (Type::Regex, t2) => Err(ParseError::new(
+                ParseErrorType::MismatchedType {
+                    expected: vec![Type::Regex],
+                    actual: t2,
+                },
+                right.span.clone(),
             )),
             (t1, _) => Err(ParseError::new(
                 ParseErrorType::MismatchedType {
-                    expected: vec![Type::Int],
+                    expected: vec![Type::Int, Type::Regex],
                     actual: t1,
                 },
-                left.token.clone(),
+                left.span.clone(),
             )),
         }
     }
@@ -450,30 +831,122 @@ impl TypeChecker {
             (Type::String, Type::Int) => Ok(Type::String),
             (Type::Int, Type::Int) => Ok(Type::Int),
             (Type::Float, Type::Float) => Ok(Type::Float),
+            (Type::Regex, Type::Regex) => Ok(Type::Product(2)),
+            (Type::Product(factors), Type::Regex) => Ok(Type::Product(factors + 1)),
+            (Type::Regex | Type::Product(_), t2) => Err(ParseError::new(
+                ParseErrorType::MismatchedType {
+                    expected: vec![Type::Regex],
+                    actual: t2,
+                },
+                right.span.clone(),
+            )),
             (Type::String, t2) => Err(ParseError::new(
                 ParseErrorType::MismatchedType {
                     expected: vec![Type::Int],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
             )),
             (Type::Int, t2) => Err(ParseError::new(
                 ParseErrorType::MismatchedType {
                     expected: vec![Type::Int],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
             )),
             (t1, _) => Err(ParseError::new(
                 ParseErrorType::MismatchedType {
                     expected: vec![Type::String, Type::Int],
                     actual: t1,
                 },
-                left.token.clone(),
+                left.span.clone(),
             )),
         }
     }
 
+    /// Evaluates an instruction that only consists of literals and
+    /// operators, or returns `None` if it depends on anything else.
+    fn constant_value(instruction: &Instruction) -> Option<InstructionResult> {
+        match &instruction.r#type {
+            InstructionType::StringLiteral(_)
+            | InstructionType::IntegerLiteral(_)
+            | InstructionType::FloatLiteral(_)
+            | InstructionType::BooleanLiteral(_) => (),
+            InstructionType::Paren(instruction)
+            | InstructionType::UnaryOperation {
+                operator: UnaryOperator::Not,
+                instruction,
+            } => {
+                Self::constant_value(instruction)?;
+            }
+            InstructionType::BinaryOperation {
+                operator,
+                left,
+                right,
+            } => {
+                Self::constant_value(left)?;
+                let right = Self::constant_value(right)?;
+                if matches!(operator, BinaryOperator::Division | BinaryOperator::Modulo)
+                    && Self::is_zero(&right)
+                {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+        instruction
+            .interpret(&mut Environment::new(Verbosity::Quiet), &mut None)
+            .ok()
+    }
+
+    fn is_zero(value: &InstructionResult) -> bool {
+        match value {
+            InstructionResult::Int(value) => *value == 0,
+            InstructionResult::Float(value) => *value == 0.0,
+            _ => false,
+        }
+    }
+
+    fn warn_division_by_zero(&self, right: &Instruction) {
+        if Self::constant_value(right).is_some_and(|value| Self::is_zero(&value)) {
+            ParseWarning::new(ParseWarningType::DivisionByZero, right.token.clone()).print(
+                self.args.disable_warnings,
+                &self.environment.allowed_warnings,
+            );
+        }
+    }
+
+    /// Returns the value of a condition that does not depend on the state of
+    /// the test, like `true` or `x < x`.
+    fn constant_condition(condition: &Instruction) -> Option<bool> {
+        if let InstructionType::BinaryOperation {
+            operator,
+            left,
+            right,
+        } = &condition.r#type
+        {
+            if let (InstructionType::Variable(left), InstructionType::Variable(right)) =
+                (&left.r#type, &right.r#type)
+            {
+                if left.name == right.name && left.r#type != Type::Float {
+                    match operator {
+                        BinaryOperator::Equal
+                        | BinaryOperator::GreaterThanOrEqual
+                        | BinaryOperator::LessThanOrEqual => return Some(true),
+                        BinaryOperator::NotEqual
+                        | BinaryOperator::GreaterThan
+                        | BinaryOperator::LessThan => return Some(false),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        match Self::constant_value(condition)? {
+            InstructionResult::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
     fn check_division(
         &mut self,
         left: &Instruction,
@@ -481,6 +954,7 @@ impl TypeChecker {
     ) -> Result<Type, ParseError> {
         let left_type = self.check_instruction(left)?;
         let right_type = self.check_instruction(right)?;
+        self.warn_division_by_zero(right);
 
         match (left_type, right_type) {
             (Type::Int, Type::Int) => Ok(Type::Int),
@@ -489,7 +963,7 @@ impl TypeChecker {
                     expected: vec![Type::Int],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
             )),
             (Type::Float, Type::Float) => Ok(Type::Float),
             (Type::Float, t2) => Err(ParseError::new(
@@ -497,7 +971,7 @@ impl TypeChecker {
                     expected: vec![Type::Float],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
             )),
 
             (t1, _t2) => Err(ParseError::new(
@@ -505,7 +979,34 @@ impl TypeChecker {
                     expected: vec![Type::Int],
                     actual: t1,
                 },
-                left.token.clone(),
+                left.span.clone(),
+            )),
+        }
+    }
+
+    fn check_matches(
+        &mut self,
+        left: &Instruction,
+        right: &Instruction,
+    ) -> Result<Type, ParseError> {
+        let left_type = self.check_instruction(left)?;
+        let right_type = self.check_instruction(right)?;
+
+        match (left_type, right_type) {
+            (Type::String, Type::Regex) => Ok(Type::Bool),
+            (Type::String, t2) => Err(ParseError::new(
+                ParseErrorType::MismatchedType {
+                    expected: vec![Type::Regex],
+                    actual: t2,
+                },
+                right.span.clone(),
+            )),
+            (t1, _t2) => Err(ParseError::new(
+                ParseErrorType::MismatchedType {
+                    expected: vec![Type::String],
+                    actual: t1,
+                },
+                left.span.clone(),
             )),
         }
     }
@@ -517,6 +1018,7 @@ impl TypeChecker {
     ) -> Result<Type, ParseError> {
         let left_type = self.check_instruction(left)?;
         let right_type = self.check_instruction(right)?;
+        self.warn_division_by_zero(right);
 
         match (left_type, right_type) {
             (Type::Int, Type::Int) => Ok(Type::Int),
@@ -525,14 +1027,14 @@ impl TypeChecker {
                     expected: vec![Type::Int],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
             )),
             (t1, _t2) => Err(ParseError::new(
                 ParseErrorType::MismatchedType {
                     expected: vec![Type::Int],
                     actual: t1,
                 },
-                left.token.clone(),
+                left.span.clone(),
             )),
         }
     }
@@ -553,7 +1055,7 @@ impl TypeChecker {
                     expected: vec![Type::Int],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
             )),
             (Type::Float, Type::Float) => Ok(Type::Bool),
             (Type::Float, t2) => Err(ParseError::new(
@@ -561,7 +1063,7 @@ impl TypeChecker {
                     expected: vec![Type::Float],
                     actual: t2,
                 },
-                right.token.clone(),
+                right.span.clone(),
             )),
             (Type::String, Type::String) | (Type::Bool, Type::Bool) => match operator {
                 BinaryOperator::Equal | BinaryOperator::NotEqual => Ok(Type::Bool),
@@ -570,7 +1072,7 @@ impl TypeChecker {
                         expected: vec![Type::Int],
                         actual: Type::Int,
                     },
-                    left.token.clone(),
+                    left.span.clone(),
                 )),
             },
 
@@ -579,7 +1081,7 @@ impl TypeChecker {
                     expected: vec![Type::Int, Type::Float],
                     actual: t1,
                 },
-                left.token.clone(),
+                left.span.clone(),
             )),
         }
     }
@@ -594,13 +1096,28 @@ impl TypeChecker {
 
         match (left_type, right_type) {
             (Type::Bool, Type::Bool) => Ok(Type::Bool),
+            (Type::Regex, Type::Regex) => Ok(Type::Regex),
+            (Type::Bool, t2) => Err(ParseError::new(
+                ParseErrorType::MismatchedType {
+                    expected: vec![Type::Bool],
+                    actual: t2,
+                },
+                right.span.clone(),
+            )),
+            (Type::Regex, t2) => Err(ParseError::new(
+                ParseErrorType::MismatchedType {
+                    expected: vec![Type::Regex],
+                    actual: t2,
+                },
+                right.span.clone(),
+            )),
 
             (t1, _t2) => Err(ParseError::new(
                 ParseErrorType::MismatchedType {
-                    expected: vec![Type::Bool],
+                    expected: vec![Type::Bool, Type::Regex],
                     actual: t1,
                 },
-                left.token.clone(),
+                left.span.clone(),
             )),
         }
     }
@@ -610,10 +1127,27 @@ impl TypeChecker {
         left_instruction: &Instruction,
         instruction: &Instruction,
         r#type: &Type,
+        checked: bool,
     ) -> Result<Type, ParseError> {
         let instruction_type = self.check_instruction(left_instruction)?;
         match (instruction_type, r#type) {
             (Type::String, Type::Int) => Ok(Type::Int),
+            (Type::Float, Type::Int) | (Type::Int, Type::Bool) => {
+                if !checked {
+                    ParseWarning::new(
+                        ParseWarningType::LossyCast {
+                            from: instruction_type,
+                            to: *r#type,
+                        },
+                        instruction.token.clone(),
+                    )
+                    .print(
+                        self.args.disable_warnings,
+                        &self.environment.allowed_warnings,
+                    );
+                }
+                Ok(*r#type)
+            }
             (Type::Int, Type::String) => Ok(Type::String),
 
             (Type::String, Type::Bool) => Ok(Type::Bool),
@@ -630,30 +1164,78 @@ impl TypeChecker {
     }
 
     fn check_function(&mut self, instruction: &Instruction) -> Result<Type, ParseError> {
-        let (parameters, statement) = match &instruction.r#type {
+        let (name, parameters, statement, return_type, end) = match &instruction.r#type {
             InstructionType::Function {
+                name,
                 parameters,
                 instruction,
-                ..
-            } => (parameters, instruction),
+                return_type,
+                end,
+            } => (name, parameters, instruction, *return_type, end),
             _ => unreachable!(),
         };
-        self.environment.add_function(Box::new(instruction.clone()));
+        self.environment.add_function(Rc::new(instruction.clone()));
 
         self.environment.add_scope();
         for parameter in parameters {
             self.environment.insert(parameter.clone());
         }
-        let result = self.check_instruction(statement);
+        // Whether the function may use the program is checked where it is
+        // called
+        let in_test = std::mem::replace(&mut self.in_test, true);
+        let result = self.check_statement(statement);
+        self.in_test = in_test;
         self.environment.remove_scope();
-        result
+        let result = result?;
+
+        // A declaration has no value, whatever the function returns
+        if return_type == Type::None {
+            return Ok(Type::None);
+        }
+        if !statement.always_yields() {
+            let error = ParseError::new(
+                ParseErrorType::MissingReturn {
+                    name: name.clone(),
+                    expected: return_type,
+                },
+                end.clone(),
+            );
+            return Err(match statement.discarded_value() {
+                Some(value) => error.with_label(
+                    value.span.clone(),
+                    "remove the semicolon after this to make it the value of the function",
+                ),
+                None => error,
+            });
+        }
+        if result != return_type {
+            return Err(ParseError::new(
+                ParseErrorType::MismatchedType {
+                    expected: vec![return_type],
+                    actual: result,
+                },
+                statement.inner_most().span.clone(),
+            ));
+        }
+        Ok(Type::None)
     }
 
     fn check_function_call(
         &mut self,
         name: &str,
         arguments: &Vec<Instruction>,
+        token: &Token,
     ) -> Result<Type, ParseError> {
+        if let Some(used) = self.program_functions.get(name).filter(|_| !self.in_test) {
+            return Err(ParseError::new(
+                ParseErrorType::ProgramOutsideTest {
+                    name: name.to_string(),
+                    function: true,
+                },
+                token.clone(),
+            )
+            .with_label(used.clone(), "the program of the test is used here"));
+        }
         match &self.environment.functions.get(name).cloned() {
             Some(instruction) => {
                 let (parameters, return_type) = match &instruction.r#type {
@@ -683,7 +1265,11 @@ impl TypeChecker {
                                 expected: vec![parameter.r#type],
                                 actual: argument_type,
                             },
-                            argument.token.clone(),
+                            argument.span.clone(),
+                        )
+                        .with_label(
+                            parameter.type_token.clone(),
+                            format!("expected because of the type of `{}`", parameter.name),
                         ));
                     }
                 }
@@ -699,6 +1285,12 @@ impl TypeChecker {
         instruction: &Instruction,
         r#else: &Instruction,
     ) -> Result<Type, ParseError> {
+        if let InstructionType::Assignment { token, .. } = &condition.r#type {
+            return Err(ParseError::new(
+                ParseErrorType::AssignmentInCondition,
+                token.clone(),
+            ));
+        }
         let condition_type = self.check_instruction(&condition)?;
         if condition_type != Type::Bool {
             return Err(ParseError::new(
@@ -706,15 +1298,26 @@ impl TypeChecker {
                     expected: vec![Type::Bool],
                     actual: condition_type,
                 },
-                condition.token.clone(),
+                condition.span.clone(),
             ));
         }
-        let result = self.check_instruction(&instruction)?;
-        let result_else = if *r#else != Instruction::NONE {
-            self.check_instruction(&r#else)?
-        } else {
-            Type::None
-        };
+        if let Some(value) = Self::constant_condition(condition) {
+            ParseWarning::new(
+                ParseWarningType::ConstantCondition(value),
+                condition.token.clone(),
+            )
+            .print(
+                self.args.disable_warnings,
+                &self.environment.allowed_warnings,
+            );
+        }
+        let result = self.check_statement(&instruction)?;
+        // Without an `else` there is no value when the condition is false,
+        // which `check_function` reports where the function ends
+        if *r#else == Instruction::none() {
+            return Ok(Type::None);
+        }
+        let result_else = self.check_statement(&r#else)?;
 
         if result == Type::None || result == result_else {
             Ok(result)
@@ -724,7 +1327,7 @@ impl TypeChecker {
                     expected: vec![result],
                     actual: result_else,
                 },
-                r#else.inner_most().token.clone(),
+                r#else.inner_most().span.clone(),
             ))
         }
     }
@@ -1,19 +1,26 @@
 use crate::r#type::Type;
+use crate::symbol::Symbol;
 use crate::token::Token;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Variable {
     pub name: String,
+    /// The interned name, which the interpreter looks the value up by
+    pub symbol: Symbol,
     pub r#const: bool,
     pub r#type: Type,
 
     pub declaration_token: Token,
     pub identifier_token: Token,
+    /// The type annotation, or the identifier when the type is missing
+    pub type_token: Token,
 
     pub last_assignment_token: Token,
 
     pub read: bool,
     pub assigned: bool,
+
+    pub allowed_warnings: Vec<String>,
 }
 
 impl std::fmt::Display for Variable {